@@ -0,0 +1,160 @@
+use core::fmt;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum Command {
+    Quit,
+    Save,
+    Find,
+    SearchNext,
+    SearchPrevious,
+    GotoLine,
+    Replace,
+    ReplaceAll,
+    FindInFiles,
+    ReplaceInFiles,
+    FilterLines,
+    Fold,
+    Unfold,
+    ToggleFolds,
+    SwitchBuffer,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    MoveLineStart,
+    MoveLineEnd,
+    MoveWordForward,
+    MoveWordBackward,
+    PageUp,
+    PageDown,
+    InsertNewline,
+    DeleteForward,
+    DeleteBackward,
+    KillLine,
+    Yank,
+    YankPop,
+    Cancel,
+    Undo,
+    Redo,
+    RecordMacro,
+    StartMacro,
+    StopMacro,
+    PlayMacro,
+    PlayMacroTimes,
+    NameMacro,
+    ToggleViMode,
+    SetFileType,
+    SetTheme,
+}
+
+const NAMES: &[(Command, &str)] = &[
+    (Command::Quit, "quit"),
+    (Command::Save, "save"),
+    (Command::Find, "find"),
+    (Command::SearchNext, "search-next"),
+    (Command::SearchPrevious, "search-previous"),
+    (Command::GotoLine, "goto-line"),
+    (Command::Replace, "replace"),
+    (Command::ReplaceAll, "replace-all"),
+    (Command::FindInFiles, "find-in-files"),
+    (Command::ReplaceInFiles, "replace-in-files"),
+    (Command::FilterLines, "filter-lines"),
+    (Command::Fold, "fold"),
+    (Command::Unfold, "unfold"),
+    (Command::ToggleFolds, "toggle-folds"),
+    (Command::SwitchBuffer, "switch-buffer"),
+    (Command::MoveUp, "move-up"),
+    (Command::MoveDown, "move-down"),
+    (Command::MoveLeft, "move-left"),
+    (Command::MoveRight, "move-right"),
+    (Command::MoveLineStart, "move-line-start"),
+    (Command::MoveLineEnd, "move-line-end"),
+    (Command::MoveWordForward, "move-word-forward"),
+    (Command::MoveWordBackward, "move-word-backward"),
+    (Command::PageUp, "page-up"),
+    (Command::PageDown, "page-down"),
+    (Command::InsertNewline, "insert-newline"),
+    (Command::DeleteForward, "delete-forward"),
+    (Command::DeleteBackward, "delete-backward"),
+    (Command::KillLine, "kill-line"),
+    (Command::Yank, "yank"),
+    (Command::YankPop, "yank-pop"),
+    (Command::Cancel, "cancel"),
+    (Command::Undo, "undo"),
+    (Command::Redo, "redo"),
+    (Command::RecordMacro, "record-macro"),
+    (Command::StartMacro, "start-macro"),
+    (Command::StopMacro, "stop-macro"),
+    (Command::PlayMacro, "play-macro"),
+    (Command::PlayMacroTimes, "play-macro-times"),
+    (Command::NameMacro, "name-macro"),
+    (Command::ToggleViMode, "toggle-vi-mode"),
+    (Command::SetFileType, "set-filetype"),
+    (Command::SetTheme, "set-theme"),
+];
+
+impl Command {
+    #[must_use]
+    #[inline]
+    pub fn from_name(name: &str) -> Option<Self> {
+        NAMES
+            .iter()
+            .find(|&&(_, command_name)| command_name == name)
+            .map(|&(command, _)| command)
+    }
+
+    /// Whether the command moves the cursor towards an edge of the document,
+    /// so that not moving means it failed.
+    #[must_use]
+    #[inline]
+    pub fn is_motion(self) -> bool {
+        matches!(
+            self,
+            Command::MoveUp
+                | Command::MoveDown
+                | Command::MoveLeft
+                | Command::MoveRight
+                | Command::MoveWordForward
+                | Command::MoveWordBackward
+                | Command::PageUp
+                | Command::PageDown
+        )
+    }
+
+    /// Whether the command changes the document, which read-only documents
+    /// refuse.
+    #[must_use]
+    #[inline]
+    pub fn is_edit(self) -> bool {
+        matches!(
+            self,
+            Command::Replace
+                | Command::ReplaceAll
+                | Command::InsertNewline
+                | Command::DeleteForward
+                | Command::DeleteBackward
+                | Command::KillLine
+                | Command::Yank
+                | Command::YankPop
+                | Command::Undo
+                | Command::Redo
+        )
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn name(self) -> &'static str {
+        NAMES
+            .iter()
+            .find(|&&(command, _)| command == self)
+            .map_or("", |&(_, name)| name)
+    }
+}
+
+impl fmt::Display for Command {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,89 @@
+use std::{env, fs, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::ColorDepth;
+
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    vi_mode: bool,
+    persist_macros: bool,
+    theme: Option<String>,
+    colors: Option<ColorDepth>,
+}
+
+impl Config {
+    /// Loads `config.toml` from the config directory, falling back to the
+    /// defaults when it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error message if the file exists but cannot be read or parsed.
+    #[inline]
+    pub fn load() -> Result<Self, String> {
+        let path = match config_dir() {
+            Some(dir) => dir.join(CONFIG_FILE),
+            None => return Ok(Self::default()),
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        toml::from_str(&contents)
+            .map_err(|error| format!("Could not parse {}: {}", path.display(), error))
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn vi_mode(&self) -> bool {
+        self.vi_mode
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn persist_macros(&self) -> bool {
+        self.persist_macros
+    }
+
+    /// The name of the theme to start with.
+    #[must_use]
+    #[inline]
+    pub fn theme(&self) -> Option<&str> {
+        self.theme.as_deref()
+    }
+
+    /// The colors to use instead of those the terminal is detected to support.
+    #[must_use]
+    #[inline]
+    pub fn colors(&self) -> Option<ColorDepth> {
+        self.colors
+    }
+}
+
+#[must_use]
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("hecto"));
+    }
+
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("hecto"))
+}
+
+#[must_use]
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("hecto"));
+    }
+
+    env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".local")
+            .join("share")
+            .join("hecto")
+    })
+}
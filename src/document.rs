@@ -1,36 +1,197 @@
-use std::{fs, io::Write};
+use alloc::collections::VecDeque;
+use core::{cell::RefCell, cmp, ops::Range};
+use std::{ffi::OsString, fs, io::Write as _, path::Path};
 
-use crate::{row, FileType, Position, Row, SearchDirection};
+use unicode_segmentation::UnicodeSegmentation as _;
+
+use crate::{
+    fold::{self, Folds, Region},
+    highlighting,
+    history::{self, Edit, History},
+    row, FileType, FileTypes, Indent, Matcher, Position, Row, SearchDirection,
+};
+
+#[cfg(feature = "tree-sitter")]
+use crate::syntax::Syntax;
+
+/// How many lines at the start and at the end of a file may hold a modeline,
+/// as in Vim.
+const MODELINES: usize = 5;
+/// How many of the last edits are kept for `edits_since`.
+const KEPT_EDITS: usize = 256;
+
+/// The rows an edit changed: `removed` rows from `row` on were replaced by
+/// `inserted` rows, each counting the row the edit started in.
+#[derive(Clone, Copy)]
+pub struct RowEdit {
+    pub row: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+/// How the rows of a file are separated, kept when it is saved.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// The whole document as searches see it.
+struct SearchText {
+    /// Every row followed by a newline.
+    text: String,
+    /// The byte offset each row starts at, with one more for the row after
+    /// the last.
+    row_starts: Vec<usize>,
+}
 
 #[derive(Default)]
 pub struct Document {
     rows: Vec<Row>,
     dirty: bool,
     file_type: FileType,
+    history: History,
+    revision: usize,
+    read_only: bool,
+    line_ending: LineEnding,
+    /// Whether the file ended without a newline after its last row, so that
+    /// saving it does not add one.
+    missing_final_newline: bool,
+    /// The query whose matches are highlighted as rows are drawn.
+    search: Option<Matcher>,
+    /// The text searched, built on the first search and kept in step with
+    /// edits since.
+    search_text: RefCell<Option<SearchText>>,
+    /// The rows before this one are highlighted, each from the state the row
+    /// above ended in, so highlighting resumes from here.
+    highlighted: usize,
+    /// Rows after an edit that were highlighted before it, where they are now.
+    /// If the first one still starts in the state it was highlighted from,
+    /// the edit did not reach them and highlighting skips past them.
+    unchanged: Option<Range<usize>>,
+    folds: Folds,
+    /// The last edits, up to `KEPT_EDITS`, and how many were made in all.
+    edits: VecDeque<RowEdit>,
+    edit_count: usize,
+    /// The parser highlighting the rows, if the file type has a grammar.
+    #[cfg(feature = "tree-sitter")]
+    syntax: Option<Syntax>,
     pub file_name: Option<String>,
 }
 
 impl Document {
+    /// Opens `filename`, with the file type it has among `file_types`.
+    ///
     /// # Errors
     ///
     /// Will return `std::io::Error` if it fails to get open file
-    pub fn open(filename: &str) -> Result<Self, std::io::Error> {
+    #[inline]
+    pub fn open(filename: &str, file_types: &FileTypes) -> Result<Self, std::io::Error> {
         let mut rows = Vec::new();
         let contents = fs::read_to_string(filename)?;
-        let file_type = FileType::from(filename);
+        let line_ending = match contents.split_once('\n') {
+            Some((first, _)) if first.ends_with('\r') => LineEnding::CrLf,
+            _ => LineEnding::Lf,
+        };
 
-        for value in contents.lines() {
-            let mut row = Row::from(value);
-            row.highlight(file_type.highlighting_options(), None);
-            rows.push(row);
+        if line_ending == LineEnding::CrLf {
+            rows.extend(contents.lines().map(Row::from));
+        } else if !contents.is_empty() {
+            // Split on newlines only, so that a carriage return ending a row
+            // is kept in it.
+            let body = contents.strip_suffix('\n').unwrap_or(&contents);
+            rows.extend(body.split('\n').map(Row::from));
         }
 
-        Ok(Self {
+        let mut document = Self {
             rows,
             dirty: false,
-            file_type,
+            file_type: FileType::default(),
+            history: History::default(),
+            revision: 0,
+            read_only: false,
+            line_ending,
+            missing_final_newline: !contents.is_empty() && !contents.ends_with('\n'),
+            search: None,
+            search_text: RefCell::default(),
+            highlighted: 0,
+            unchanged: None,
+            folds: Folds::default(),
+            edits: VecDeque::new(),
+            edit_count: 0,
+            #[cfg(feature = "tree-sitter")]
+            syntax: None,
             file_name: Some(filename.to_string()),
-        })
+        };
+        let file_type = file_types.detect(filename, &document.edge_lines());
+        document.set_file_type(file_type);
+        Ok(document)
+    }
+
+    /// An empty read-only document listing the hits of a project search,
+    /// filled with `push_result`.
+    #[must_use]
+    #[inline]
+    pub fn results() -> Self {
+        Self {
+            file_type: FileType::results(),
+            read_only: true,
+            ..Self::default()
+        }
+    }
+
+    /// Appends a `location` followed by `text` to the results, highlighting
+    /// the grapheme range `columns` of `text` as a match.
+    #[inline]
+    pub fn push_result(&mut self, location: &str, text: &str, columns: &Range<usize>) {
+        let row = self.result_row(location, text, columns);
+        self.changed(
+            &Position {
+                x: 0,
+                y: self.rows.len(),
+            },
+            "",
+            "\n",
+        );
+        self.rows.push(row);
+    }
+
+    /// Replaces the results line at `index`, as `push_result` adds them.
+    #[inline]
+    pub fn set_result(&mut self, index: usize, location: &str, text: &str, columns: &Range<usize>) {
+        let row = self.result_row(location, text, columns);
+        if let Some(existing) = self
+            .rows
+            .get(index)
+            .map(|current| current.as_str().to_owned())
+        {
+            self.changed(&Position { x: 0, y: index }, &existing, row.as_str());
+        }
+        if let Some(existing) = self.rows.get_mut(index) {
+            *existing = row;
+        }
+    }
+
+    fn result_row(&self, location: &str, text: &str, columns: &Range<usize>) -> Row {
+        let offset = location.graphemes(true).count();
+        let mut row = Row::from(format!("{location}{text}").as_str());
+        let matched = columns.start.saturating_add(offset)..columns.end.saturating_add(offset);
+        row.highlight(
+            self.file_type.highlighting_options(),
+            &[matched],
+            highlighting::State::Normal,
+        );
+        row
     }
 
     #[must_use]
@@ -38,46 +199,121 @@ impl Document {
         self.file_type.name()
     }
 
+    #[must_use]
+    #[inline]
+    pub fn indent(&self) -> Indent {
+        self.file_type.indent()
+    }
+
+    /// Detects the file type again from the file name and contents, as when
+    /// the file was saved under a new name.
+    #[inline]
+    pub fn detect_file_type(&mut self, file_types: &FileTypes) {
+        let name = self.file_name.clone().unwrap_or_default();
+        let file_type = file_types.detect(&name, &self.edge_lines());
+        self.set_file_type(file_type);
+    }
+
+    /// The first and last few lines, where modelines are looked for.
+    fn edge_lines(&self) -> Vec<&str> {
+        let tail = self.rows.len().saturating_sub(MODELINES).max(MODELINES);
+        self.rows
+            .iter()
+            .take(MODELINES)
+            .chain(self.rows.iter().skip(tail))
+            .map(Row::as_str)
+            .collect()
+    }
+
+    /// Changes the file type, highlighting the rows again, with its grammar
+    /// if it has one compiled in.
+    #[inline]
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        self.file_type = file_type;
+        #[cfg(feature = "tree-sitter")]
+        self.load_syntax();
+        self.rows.iter_mut().for_each(Row::invalidate_highlighting);
+        self.highlighted = 0;
+        self.unchanged = None;
+    }
+
+    /// Starts a parser for the grammar of the file type, if it has one.
+    #[cfg(feature = "tree-sitter")]
+    fn load_syntax(&mut self) {
+        self.syntax = self.file_type.grammar().and_then(Syntax::new);
+    }
+
     /// # Errors
     ///
     /// Will return `std::io::Error` if it fails to save file
     pub fn save(&mut self) -> Result<(), std::io::Error> {
-        if let Some(file_name) = self.file_name.as_deref() {
-            let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
+        if let Some(file_name) = self.file_name.clone() {
+            self.write_atomically(Path::new(&file_name))?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
 
-            for row in &mut self.rows {
+    /// Writes the rows to a temporary file next to `path` and renames it over
+    /// `path`, so the file is never left half written. A symlink is followed
+    /// and the file keeps its permissions.
+    fn write_atomically(&self, path: &Path) -> Result<(), std::io::Error> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut temp_name = OsString::from(".");
+        temp_name.push(path.file_name().unwrap_or_default());
+        temp_name.push(".hecto-save");
+        let temp = path.with_file_name(temp_name);
+
+        let written = fs::File::create(&temp).and_then(|mut file| {
+            for (index, row) in self.rows.iter().enumerate() {
                 file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
-                row.highlight(self.file_type.highlighting_options(), None);
+                if index.saturating_add(1) < self.rows.len() || !self.missing_final_newline {
+                    file.write_all(self.line_ending.as_bytes())?;
+                }
             }
-
-            self.dirty = false;
+            file.sync_all()?;
+            if let Ok(metadata) = fs::metadata(&path) {
+                fs::set_permissions(&temp, metadata.permissions())?;
+            }
+            fs::rename(&temp, &path)
+        });
+        if written.is_err() {
+            // The save failed already; a temporary file left behind is all
+            // that failing to remove it costs.
+            fs::remove_file(&temp).unwrap_or_default();
         }
-        Ok(())
+        written
     }
 
     pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.rows.len() {
+        if at.y > self.rows.len() || self.read_only {
             return;
         }
 
-        self.dirty = true;
+        self.touch();
 
         if c == '\n' {
             return self.insert_newline(at);
         }
 
+        self.history.record(Edit::Insert {
+            at: self.clamp_position(at),
+            text: c.to_string(),
+            new_row: at.y == self.rows.len(),
+        });
+
+        if at.y == self.rows.len() {
+            self.changed(at, "", &format!("{c}\n"));
+        } else {
+            self.changed(at, "", &c.to_string());
+        }
         if at.y == self.rows.len() {
             let mut row = Row::default();
             row.insert(0, c);
-            row.highlight(self.file_type.highlighting_options(), None);
             self.rows.push(row);
         } else {
             #[allow(clippy::indexing_slicing)]
-            let row = &mut self.rows[at.y];
-            row.insert(at.x, c);
-            row.highlight(self.file_type.highlighting_options(), None);
+            self.rows[at.y].insert(at.x, c);
         }
     }
 
@@ -85,82 +321,678 @@ impl Document {
     pub fn delete(&mut self, at: &Position) {
         let len = self.rows.len();
 
-        if at.y >= len {
+        if at.y >= len || self.read_only {
             return;
         }
 
-        self.dirty = true;
-
-        if at.x == self.rows[at.y].len() && at.y + 1 < len {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = &mut self.rows[at.y];
-            row.highlight(self.file_type.highlighting_options(), None);
-            row.append(&next_row);
+        let row_len = self.row_len(at.y).unwrap_or(0);
+        let joins = at.x == row_len && at.y.saturating_add(1) < len;
+        let text = if joins {
+            String::from("\n")
         } else {
-            let row = &mut self.rows[at.y];
+            self.rows
+                .get(at.y)
+                .map(|row| row.substring(at.x, at.x.saturating_add(1)).to_owned())
+                .unwrap_or_default()
+        };
+        if !text.is_empty() {
+            self.history.record(Edit::Delete {
+                at: at.clone(),
+                text: text.clone(),
+            });
+            self.changed(at, &text, "");
+        }
+
+        self.touch();
+
+        if joins {
+            let next_row = self.rows.remove(at.y.saturating_add(1));
+            if let Some(row) = self.rows.get_mut(at.y) {
+                row.append(&next_row);
+            }
+        } else if let Some(row) = self.rows.get_mut(at.y) {
             row.delete(at.x);
-            row.highlight(self.file_type.highlighting_options(), None);
         }
     }
 
+    /// Deletes the grapheme before `at`, or joins row `at.y` to the row above
+    /// it when `at` starts a row, and returns where the cursor goes. Returns
+    /// `None` at the start of the document.
+    #[inline]
+    pub fn delete_backward(&mut self, at: &Position) -> Option<Position> {
+        let before = if at.x > 0 {
+            Position {
+                x: at.x.saturating_sub(1),
+                y: at.y,
+            }
+        } else {
+            let y = at.y.checked_sub(1)?;
+            Position {
+                x: self.row_len(y).unwrap_or(0),
+                y,
+            }
+        };
+        self.delete(&before);
+        Some(before)
+    }
+
     pub fn insert_newline(&mut self, at: &Position) {
-        if at.y > self.rows.len() {
+        if at.y > self.rows.len() || self.read_only {
             return;
         }
 
+        self.touch();
+
         if at.y == self.rows.len() {
+            self.history.record(Edit::Insert {
+                at: Position { x: 0, y: at.y },
+                text: String::new(),
+                new_row: true,
+            });
+            self.changed(at, "", "\n");
             return self.rows.push(Row::default());
         }
 
+        self.history.record(Edit::Insert {
+            at: self.clamp_position(at),
+            text: String::from("\n"),
+            new_row: false,
+        });
+
+        self.changed(at, "", "\n");
         #[allow(clippy::indexing_slicing)]
         let current_row = &mut self.rows[at.y];
-        let mut new_row = current_row.split(at.x);
-
-        current_row.highlight(self.file_type.highlighting_options(), None);
-        new_row.highlight(self.file_type.highlighting_options(), None);
+        let new_row = current_row.split(at.x);
 
         #[allow(clippy::integer_arithmetic)]
         self.rows.insert(at.y + 1, new_row);
     }
 
-    #[must_use]
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
-        if at.y >= self.rows.len() {
-            return None;
+    /// Inserts `text` at `at`, splitting rows on newlines, and returns the
+    /// position right after the inserted text.
+    #[inline]
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        if at.y > self.rows.len() || text.is_empty() || self.read_only {
+            return at.clone();
         }
 
-        let mut position = at.clone();
+        self.touch();
+        self.history.record(Edit::Insert {
+            at: self.clamp_position(at),
+            text: text.to_owned(),
+            new_row: at.y == self.rows.len(),
+        });
 
-        let (start, end) = if direction == SearchDirection::Forward {
-            (at.y, self.rows.len())
+        if at.y == self.rows.len() {
+            self.changed(at, "", &format!("{text}\n"));
+            self.rows.push(Row::default());
         } else {
-            (0, at.y.saturating_add(1))
-        };
+            self.changed(at, "", text);
+        }
+
+        let mut y = at.y;
+        let mut x = cmp::min(at.x, self.row_len(y).unwrap_or(0));
+        let tail = self
+            .rows
+            .get_mut(y)
+            .map(|row| row.split(x))
+            .unwrap_or_default();
+
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                y = y.saturating_add(1);
+                self.rows.insert(y, Row::default());
+                x = 0;
+            }
+            if let Some(row) = self.rows.get_mut(y) {
+                row.insert_str(x, line);
+                x = row.len();
+            }
+        }
+
+        if let Some(row) = self.rows.get_mut(y) {
+            row.append(&tail);
+        }
+        Position { x, y }
+    }
+
+    /// Deletes the text between `start` (inclusive) and `end` (exclusive)
+    /// and returns it.
+    #[inline]
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> String {
+        let (start, end) = self.clamp_range(start, end);
+        if start == end || self.read_only {
+            return String::new();
+        }
+
+        let text = self.text_range(&start, &end);
+        self.touch();
+        self.history.record(Edit::Delete {
+            at: start.clone(),
+            text: text.clone(),
+        });
+        self.changed(&start, &text, "");
+
+        if start.y == end.y {
+            if let Some(row) = self.rows.get_mut(start.y) {
+                row.delete_range(start.x, end.x);
+            }
+        } else {
+            let tail = self
+                .rows
+                .get_mut(end.y)
+                .map(|row| row.split(end.x))
+                .unwrap_or_default();
+            self.rows.drain(start.y.saturating_add(1)..=end.y);
 
-        for _ in start..end {
-            if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(&query, position.x, direction) {
-                    position.x = x;
-                    return Some(position);
+            if let Some(row) = self.rows.get_mut(start.y) {
+                row.split(start.x);
+                row.append(&tail);
+            }
+        }
+        text
+    }
+
+    /// Returns the text between `start` (inclusive) and `end` (exclusive),
+    /// joining rows with newlines.
+    #[must_use]
+    #[inline]
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        let (start, end) = self.clamp_range(start, end);
+        let mut text = String::new();
+
+        for y in start.y..=end.y {
+            if let Some(row) = self.rows.get(y) {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { row.len() };
+                if y > start.y {
+                    text.push('\n');
                 }
-                if direction == SearchDirection::Forward {
-                    position.y = position.y.saturating_add(1);
-                    position.x = 0;
+                text.push_str(row.substring(from, to));
+            }
+        }
+        text
+    }
+
+    /// Reverts the last undo group and returns where the cursor should go, or
+    /// `None` when there is none or the open group already holds edits.
+    #[inline]
+    pub fn undo(&mut self) -> Option<Position> {
+        let edits = self.history.pop_undo()?;
+
+        self.history.set_paused(true);
+        let mut cursor = None;
+        for edit in edits.iter().rev() {
+            cursor = Some(self.revert(edit));
+        }
+        self.history.set_paused(false);
+
+        self.history.push_redo(edits);
+        cursor
+    }
+
+    /// Reapplies the last undone group and returns where the cursor should go.
+    #[inline]
+    pub fn redo(&mut self) -> Option<Position> {
+        let edits = self.history.pop_redo()?;
+
+        self.history.set_paused(true);
+        let mut cursor = None;
+        for edit in &edits {
+            cursor = Some(self.apply(edit));
+        }
+        self.history.set_paused(false);
+
+        self.history.push_undo(edits);
+        cursor
+    }
+
+    /// Groups every edit until the matching `end_undo_group` into a single
+    /// undo step.
+    #[inline]
+    pub fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    #[inline]
+    pub fn end_undo_group(&mut self) {
+        self.history.end_group();
+    }
+
+    /// Whether the open undo group already holds edits, which `undo` won't
+    /// split.
+    #[must_use]
+    #[inline]
+    pub fn has_open_undo_edits(&self) -> bool {
+        self.history.has_open_edits()
+    }
+
+    #[expect(
+        clippy::pattern_type_mismatch,
+        reason = "the fields are only borrowed, which `ref` patterns would spell out"
+    )]
+    fn apply(&mut self, edit: &Edit) -> Position {
+        match edit {
+            Edit::Insert { at, text, new_row } => {
+                if *new_row && text.is_empty() {
+                    self.insert_newline(at);
+                    at.clone()
                 } else {
-                    position.y = position.y.saturating_sub(1);
-                    position.x = self.rows[position.y].len();
+                    self.insert_str(at, text)
+                }
+            }
+            Edit::Delete { at, text } => {
+                self.delete_range(at, &history::end_position(at, text));
+                at.clone()
+            }
+        }
+    }
+
+    #[expect(
+        clippy::pattern_type_mismatch,
+        reason = "the fields are only borrowed, which `ref` patterns would spell out"
+    )]
+    fn revert(&mut self, edit: &Edit) -> Position {
+        match edit {
+            Edit::Insert { at, text, new_row } => {
+                self.delete_range(at, &history::end_position(at, text));
+                if *new_row && self.row_len(at.y) == Some(0) {
+                    self.changed(&Position { x: 0, y: at.y }, "\n", "");
+                    self.rows.remove(at.y);
+                    self.touch();
+                }
+                at.clone()
+            }
+            Edit::Delete { at, text } => {
+                self.insert_str(at, text);
+                at.clone()
+            }
+        }
+    }
+
+    fn clamp_position(&self, at: &Position) -> Position {
+        Position {
+            x: cmp::min(at.x, self.row_len(at.y).unwrap_or(0)),
+            y: at.y,
+        }
+    }
+
+    fn clamp_range(&self, start: &Position, end: &Position) -> (Position, Position) {
+        let clamp = |position: &Position| {
+            if let Some(len) = self.row_len(position.y) {
+                Position {
+                    x: cmp::min(position.x, len),
+                    y: position.y,
                 }
             } else {
-                return None;
+                let y = self.rows.len().saturating_sub(1);
+                Position {
+                    x: self.row_len(y).unwrap_or(0),
+                    y,
+                }
             }
+        };
+
+        let (start, end) = (clamp(start), clamp(end));
+        if start <= end {
+            (start, end)
+        } else {
+            (end, start)
         }
-        None
     }
 
-    pub fn highlight(&mut self, word: Option<&str>) {
-        for row in &mut self.rows {
-            row.highlight(self.file_type.highlighting_options(), word);
+    /// Finds the next match starting at or after `at`, or the previous one
+    /// starting before it, and returns where it starts and ends. Matches may
+    /// span several rows.
+    #[must_use]
+    #[inline]
+    pub fn find_match(
+        &self,
+        matcher: &Matcher,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<(Position, Position)> {
+        if at.y >= self.rows.len() {
+            return None;
         }
+
+        self.with_search_text(|text, row_starts| {
+            let offset = self.byte_offset(row_starts, at);
+            let found = if direction == SearchDirection::Forward {
+                matcher.find_at(text, offset)
+            } else {
+                matcher.find_before(text, offset)
+            }?;
+
+            Some((
+                self.start_position(0, row_starts, found.start),
+                self.end_position(0, row_starts, found.end),
+            ))
+        })
+    }
+
+    /// Where every match starts, in order.
+    #[must_use]
+    #[inline]
+    pub fn match_starts(&self, matcher: &Matcher) -> Vec<Position> {
+        self.with_search_text(|text, row_starts| {
+            matcher
+                .matches(text)
+                .map(|found| self.start_position(0, row_starts, found.start))
+                .collect()
+        })
+    }
+
+    /// Replaces the match starting at `at` and returns the position right
+    /// after the replacement text.
+    #[inline]
+    pub fn replace_match(
+        &mut self,
+        matcher: &Matcher,
+        at: &Position,
+        replacement: &str,
+    ) -> Option<Position> {
+        let (start, end, replacement) = self.with_search_text(|text, row_starts| {
+            let offset = self.byte_offset(row_starts, at);
+            let found = matcher
+                .find_at(text, offset)
+                .filter(|found| found.start == offset)?;
+
+            Some((
+                self.start_position(0, row_starts, found.start),
+                self.end_position(0, row_starts, found.end),
+                matcher.replacement(text, &found, replacement),
+            ))
+        })?;
+        self.delete_range(&start, &end);
+        Some(self.insert_str(&start, &replacement))
+    }
+
+    /// Replaces every match that lies between `start` and `end`, or in the
+    /// whole document, and returns how many were replaced.
+    #[inline]
+    pub fn replace_all(
+        &mut self,
+        matcher: &Matcher,
+        range: Option<(Position, Position)>,
+        replacement: &str,
+    ) -> usize {
+        let replacements: Vec<(Position, Position, String)> =
+            self.with_search_text(|text, row_starts| {
+                let (from, to) = match range {
+                    Some((start, end)) => (
+                        self.byte_offset(row_starts, &start),
+                        self.byte_offset(row_starts, &end),
+                    ),
+                    None => (0, text.len()),
+                };
+
+                matcher
+                    .matches(text)
+                    .filter(|found| found.start >= from && found.end <= to)
+                    .map(|found| {
+                        (
+                            self.start_position(0, row_starts, found.start),
+                            self.end_position(0, row_starts, found.end),
+                            matcher.replacement(text, &found, replacement),
+                        )
+                    })
+                    .collect()
+            });
+
+        let count = replacements.len();
+        // From the last match, so the positions of earlier ones stay valid.
+        for (start, end, text) in replacements.into_iter().rev() {
+            self.delete_range(&start, &end);
+            self.insert_str(&start, &text);
+        }
+        count
+    }
+
+    /// Highlights every match of `matcher`, or clears match highlighting.
+    /// Matches are only looked for in the rows being drawn, by `search_matches`.
+    #[inline]
+    pub fn highlight(&mut self, matcher: Option<&Matcher>) {
+        self.search = matcher.cloned();
+    }
+
+    /// Brings the highlighting of the rows in `rows` up to date. Rows are
+    /// highlighted lazily, when they are about to be drawn after they changed.
+    /// Each row starts in the state the row above ended in, such as inside a
+    /// block comment, so the rows above are brought up to date as well, from
+    /// the first one changed since. A row whose start state changed is
+    /// highlighted again, which carries an edit opening or closing a comment
+    /// down until the states agree again; the rows highlighted before the edit
+    /// are skipped from there.
+    ///
+    /// With a grammar, the document is parsed again after edits instead, and
+    /// the rows in `rows` highlighted from its syntax tree.
+    #[inline]
+    pub fn highlight_rows(&mut self, rows: Range<usize>) {
+        #[cfg(feature = "tree-sitter")]
+        if let Some(syntax) = self.syntax.as_mut() {
+            syntax.update(&self.rows);
+            syntax.highlight(&mut self.rows, rows);
+            return;
+        }
+
+        let end = cmp::min(rows.end, self.rows.len());
+        let mut y = self.highlighted;
+        let mut state = self.end_state(y);
+        while y < end {
+            let unchanged = self
+                .unchanged
+                .clone()
+                .filter(|unchanged| unchanged.start == y);
+            let Some(row) = self.rows.get_mut(y) else {
+                break;
+            };
+            let fresh = !row.is_stale() && row.start_state() == state;
+            if let (true, Some(unchanged)) = (fresh, unchanged) {
+                y = cmp::max(unchanged.end, y.saturating_add(1)).min(self.rows.len());
+                state = self.end_state(y);
+                continue;
+            }
+            if !fresh {
+                row.highlight(self.file_type.highlighting_options(), &[], state);
+            }
+            state = row.end_state();
+            y = y.saturating_add(1);
+        }
+
+        if y > self.highlighted {
+            self.highlighted = y;
+            if self
+                .unchanged
+                .as_ref()
+                .is_some_and(|unchanged| unchanged.start < y)
+            {
+                self.unchanged = None;
+            }
+        }
+    }
+
+    /// The state row `y` starts in: the one the row above ended in.
+    fn end_state(&self, y: usize) -> highlighting::State {
+        y.checked_sub(1)
+            .and_then(|above| self.rows.get(above))
+            .map_or(highlighting::State::Normal, Row::end_state)
+    }
+
+    /// Folds the innermost region `row` is in, and returns whether there was
+    /// one. A region folded already is folded with the one around it.
+    #[inline]
+    pub fn fold(&mut self, row: usize) -> bool {
+        let region = self
+            .fold_regions()
+            .into_iter()
+            .filter(|region| region.contains(row) && self.folds.at(region.start) != Some(*region))
+            .max_by_key(|region| region.start);
+        if let Some(region) = region {
+            self.folds.close(region);
+        }
+        region.is_some()
+    }
+
+    /// Unfolds the fold starting at `row` or hiding it, and returns whether
+    /// there was one.
+    #[inline]
+    pub fn unfold(&mut self, row: usize) -> bool {
+        self.folds.open(row)
+    }
+
+    /// Unfolds everything if anything is folded, and folds every region
+    /// otherwise.
+    #[inline]
+    pub fn toggle_folds(&mut self) {
+        if self.folds.is_empty() {
+            for region in self.fold_regions() {
+                self.folds.close(region);
+            }
+        } else {
+            self.folds.open_all();
+        }
+    }
+
+    /// Unfolds whatever hides `row`, as when the cursor is taken there.
+    #[inline]
+    pub fn reveal(&mut self, row: usize) {
+        self.folds.reveal(row);
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn folds(&self) -> &Folds {
+        &self.folds
+    }
+
+    #[inline]
+    pub fn folds_mut(&mut self) -> &mut Folds {
+        &mut self.folds
+    }
+
+    /// The regions that can be folded, as the file type finds them.
+    fn fold_regions(&mut self) -> Vec<Region> {
+        self.highlight_rows(0..self.rows.len());
+        fold::regions(
+            &self.rows,
+            self.file_type.folding(),
+            self.file_type.indent().width(),
+        )
+    }
+
+    /// The grapheme ranges matched by the highlighted query in each row of
+    /// `rows`. Matches spanning rows are found within `rows` only.
+    #[must_use]
+    #[inline]
+    pub fn search_matches(&self, rows: Range<usize>) -> Vec<Vec<Range<usize>>> {
+        let rows = rows.start..cmp::min(rows.end, self.rows.len());
+        let mut row_matches: Vec<Vec<Range<usize>>> = vec![Vec::new(); rows.len()];
+        let Some(matcher) = self.search.as_ref() else {
+            return row_matches;
+        };
+
+        let (text, row_starts) = self.search_text(rows.clone());
+        for found in matcher.matches(&text) {
+            let start = self.start_position(rows.start, &row_starts, found.start);
+            let end = self.end_position(rows.start, &row_starts, found.end);
+
+            for (y, matches) in row_matches
+                .iter_mut()
+                .enumerate()
+                .take(end.y.saturating_add(1))
+                .skip(start.y)
+            {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { usize::MAX };
+                matches.push(from..to);
+            }
+        }
+        row_matches
+    }
+
+    /// The rows in `rows` joined by newlines, and the byte offset each row
+    /// starts at.
+    fn search_text(&self, rows: Range<usize>) -> (String, Vec<usize>) {
+        let mut text = String::new();
+        let mut row_starts = Vec::with_capacity(rows.len());
+
+        for (index, row) in self.rows.iter().enumerate().take(rows.end).skip(rows.start) {
+            if index > rows.start {
+                text.push('\n');
+            }
+            row_starts.push(text.len());
+            text.push_str(row.as_str());
+        }
+        (text, row_starts)
+    }
+
+    /// Calls `read` with the whole document as `search_text` joins it, building
+    /// it first if no search since the document was opened needed it.
+    fn with_search_text<T>(&self, read: impl FnOnce(&str, &[usize]) -> T) -> T {
+        if self.search_text.borrow().is_none() {
+            let mut text = String::new();
+            let mut row_starts = Vec::with_capacity(self.rows.len().saturating_add(1));
+            for row in &self.rows {
+                row_starts.push(text.len());
+                text.push_str(row.as_str());
+                text.push('\n');
+            }
+            row_starts.push(text.len());
+            *self.search_text.borrow_mut() = Some(SearchText { text, row_starts });
+        }
+
+        match self.search_text.borrow().as_ref() {
+            Some(search) => read(
+                search
+                    .text
+                    .get(..search.text.len().saturating_sub(1))
+                    .unwrap_or_default(),
+                search.row_starts.get(..self.rows.len()).unwrap_or_default(),
+            ),
+            None => read("", &[]),
+        }
+    }
+
+    fn byte_offset(&self, row_starts: &[usize], at: &Position) -> usize {
+        match (row_starts.get(at.y), self.rows.get(at.y)) {
+            (Some(start), Some(row)) => start.saturating_add(row.byte_index(at.x)),
+            _ => row_starts.last().copied().unwrap_or(0),
+        }
+    }
+
+    /// The position of the grapheme containing the byte at `offset` in the
+    /// text of the rows from `first` on. Its row counts from `first`.
+    fn start_position(&self, first: usize, row_starts: &[usize], offset: usize) -> Position {
+        let y = row_starts
+            .partition_point(|start| *start <= offset)
+            .saturating_sub(1);
+        let start = row_starts.get(y).copied().unwrap_or(0);
+
+        let x = self.rows.get(first.saturating_add(y)).map_or(0, |row| {
+            let byte = offset.saturating_sub(start);
+            if byte >= row.as_str().len() {
+                row.len()
+            } else {
+                row.grapheme_index(byte.saturating_add(1)).saturating_sub(1)
+            }
+        });
+        Position { x, y }
+    }
+
+    /// The position right after the grapheme containing the byte before
+    /// `offset`, so that partial graphemes count as matched.
+    fn end_position(&self, first: usize, row_starts: &[usize], offset: usize) -> Position {
+        let y = row_starts
+            .partition_point(|start| *start < offset)
+            .saturating_sub(1);
+        let start = row_starts.get(y).copied().unwrap_or(0);
+
+        let x = self
+            .rows
+            .get(first.saturating_add(y))
+            .map_or(0, |row| row.grapheme_index(offset.saturating_sub(start)));
+        Position { x, y }
     }
 
     #[must_use]
@@ -183,8 +1015,289 @@ impl Document {
         self.rows.len()
     }
 
+    /// Whether edits are refused, as in a search results buffer.
+    #[must_use]
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     #[must_use]
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
+
+    /// A counter bumped on every change, to tell whether anything was edited.
+    #[must_use]
+    #[inline]
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    /// How many edits were made, to tell `edits_since` from when on.
+    #[must_use]
+    #[inline]
+    pub fn edit_count(&self) -> usize {
+        self.edit_count
+    }
+
+    /// The edits made since `edit_count` returned `count`, oldest first, or
+    /// `None` if there were too many to keep.
+    #[inline]
+    pub fn edits_since(&self, count: usize) -> Option<impl Iterator<Item = &RowEdit>> {
+        let made = self.edit_count.checked_sub(count)?;
+        let skipped = self.edits.len().checked_sub(made)?;
+        Some(self.edits.iter().skip(skipped))
+    }
+
+    fn touch(&mut self) {
+        self.dirty = true;
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    /// Keeps what is kept about the rows in step with an edit about to
+    /// replace `removed` at `at` with `inserted`. Rows are counted as each
+    /// followed by a newline, so that a row added at the end is inserted as
+    /// `"\n"`.
+    fn changed(&mut self, at: &Position, removed: &str, inserted: &str) {
+        let removed_rows = removed.matches('\n').count();
+        let inserted_rows = inserted.matches('\n').count();
+        // The row after the last one the edit touches, before and after it.
+        let old_end = at.y.saturating_add(removed_rows).saturating_add(1);
+        let new_end = at.y.saturating_add(inserted_rows).saturating_add(1);
+        let moved = |row: usize| row.saturating_sub(old_end).saturating_add(new_end);
+        let column = self.rows.get(at.y).map_or(0, |row| row.byte_index(at.x));
+
+        if self.edits.len() == KEPT_EDITS {
+            self.edits.pop_front();
+        }
+        self.edits.push_back(RowEdit {
+            row: at.y,
+            removed: removed_rows.saturating_add(1),
+            inserted: inserted_rows.saturating_add(1),
+        });
+        self.edit_count = self.edit_count.wrapping_add(1);
+
+        if let Some(search) = self.search_text.get_mut().as_mut() {
+            let start = search
+                .row_starts
+                .get(at.y)
+                .map(|start| start.saturating_add(column));
+            match start.filter(|start| {
+                search.text.get(*start..start.saturating_add(removed.len())) == Some(removed)
+            }) {
+                Some(start) => {
+                    search
+                        .text
+                        .replace_range(start..start.saturating_add(removed.len()), inserted);
+                    let new_starts = inserted
+                        .match_indices('\n')
+                        .map(|(newline, _)| start.saturating_add(newline).saturating_add(1));
+                    let rows = at.y.saturating_add(1)..old_end.min(search.row_starts.len());
+                    search.row_starts.splice(rows, new_starts);
+                    for row_start in search.row_starts.iter_mut().skip(new_end) {
+                        *row_start = row_start
+                            .saturating_sub(removed.len())
+                            .saturating_add(inserted.len());
+                    }
+                }
+                None => *self.search_text.get_mut() = None,
+            }
+        }
+
+        let unchanged = match self.unchanged.take() {
+            Some(unchanged) if unchanged.end <= at.y => Some(unchanged),
+            Some(unchanged) if unchanged.start >= old_end => {
+                Some(moved(unchanged.start)..moved(unchanged.end))
+            }
+            Some(unchanged) => Some(new_end..moved(unchanged.end)),
+            None => None,
+        };
+        self.unchanged = if at.y < self.highlighted {
+            let end = match unchanged {
+                Some(unchanged) if unchanged.start == moved(self.highlighted) => unchanged.end,
+                _ => moved(self.highlighted),
+            };
+            self.highlighted = at.y;
+            Some(new_end..end)
+        } else {
+            unchanged
+        }
+        .filter(|unchanged| !unchanged.is_empty());
+
+        if removed_rows > 0 {
+            self.folds.rows_removed(at.y.saturating_add(1)..old_end);
+        }
+        if inserted_rows > 0 {
+            self.folds
+                .rows_inserted(at.y.saturating_add(1), inserted_rows);
+        }
+
+        #[cfg(feature = "tree-sitter")]
+        if let Some(syntax) = self.syntax.as_mut() {
+            syntax.edit(&self.rows, at.y, column, removed, inserted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{highlighting::State, SearchOptions};
+
+    fn document(text: &str) -> Document {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), text);
+        document
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        (0..document.len())
+            .filter_map(|y| document.row(y).map(Row::as_str))
+            .collect()
+    }
+
+    fn matcher(query: &str, regex: bool) -> Option<Matcher> {
+        let options = SearchOptions {
+            regex,
+            ..SearchOptions::default()
+        };
+        Matcher::new(query, options).ok()
+    }
+
+    #[test]
+    fn matches_are_replaced_one_at_a_time() {
+        let mut document = document("let a = b;\nlet c = d;");
+        let matcher = matcher(r"let (\w)", true);
+        let replaced = matcher.as_ref().and_then(|matcher| {
+            document.replace_match(matcher, &Position { x: 0, y: 1 }, "const ${1}1")
+        });
+        assert_eq!(replaced, Some(Position { x: 8, y: 1 }));
+        assert_eq!(lines(&document), ["let a = b;", "const c1 = d;"]);
+
+        let missed = matcher
+            .as_ref()
+            .and_then(|matcher| document.replace_match(matcher, &Position { x: 1, y: 0 }, "x"));
+        assert_eq!(missed, None);
+    }
+
+    #[test]
+    fn every_match_in_a_range_is_replaced() {
+        let mut document = document("a-a\na-a\na-a");
+        let matcher = matcher("a-", false);
+        let range = (Position { x: 2, y: 0 }, Position { x: 3, y: 1 });
+        let count = matcher.as_ref().map_or(0, |matcher| {
+            document.replace_all(matcher, Some(range), "b\n")
+        });
+        assert_eq!(count, 1);
+        assert_eq!(lines(&document), ["a-a", "b", "a", "a-a"]);
+
+        let count = matcher
+            .as_ref()
+            .map_or(0, |matcher| document.replace_all(matcher, None, ""));
+        assert_eq!(count, 2);
+        assert_eq!(lines(&document), ["a", "b", "a", "a"]);
+    }
+
+    #[test]
+    fn matches_span_rows() {
+        let document = document("one\ntwo\none");
+        let matcher = matcher("e\nt", false);
+        let found = matcher.as_ref().and_then(|matcher| {
+            document.find_match(matcher, &Position::default(), SearchDirection::Forward)
+        });
+        assert_eq!(
+            found,
+            Some((Position { x: 2, y: 0 }, Position { x: 1, y: 1 }))
+        );
+    }
+
+    #[test]
+    fn match_starts_follow_edits() {
+        let mut document = document("ab\nab ab");
+        let matcher = matcher("ab", false);
+        let starts = |document: &Document| {
+            matcher
+                .as_ref()
+                .map(|matcher| document.match_starts(matcher))
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            starts(&document),
+            [
+                Position { x: 0, y: 0 },
+                Position { x: 0, y: 1 },
+                Position { x: 3, y: 1 }
+            ]
+        );
+
+        document.insert(&Position { x: 1, y: 1 }, 'x');
+        assert_eq!(
+            starts(&document),
+            [Position { x: 0, y: 0 }, Position { x: 4, y: 1 }]
+        );
+
+        let before = matcher.as_ref().and_then(|matcher| {
+            document.find_match(matcher, &Position { x: 4, y: 1 }, SearchDirection::Backward)
+        });
+        assert_eq!(before.map(|(start, _)| start), Some(Position::default()));
+    }
+
+    #[test]
+    fn deleting_backward_joins_the_row_above() {
+        let mut document = document("ab\ncd");
+        let joined = document.delete_backward(&Position { x: 0, y: 1 });
+        assert_eq!(joined, Some(Position { x: 2, y: 0 }));
+        assert_eq!(lines(&document), ["abcd"]);
+
+        let deleted = document.delete_backward(&Position { x: 1, y: 0 });
+        assert_eq!(deleted, Some(Position::default()));
+        assert_eq!(lines(&document), ["bcd"]);
+        assert_eq!(document.delete_backward(&Position::default()), None);
+    }
+
+    #[test]
+    fn deleting_backward_joins_into_a_folded_row() {
+        let mut document = document("0\n1\n2\n3\n4\n5 {\n  6\n  7\n  8\n  9\n10");
+        assert!(document.fold(5));
+        assert_eq!(document.folds().document_row(6), 10);
+
+        let joined = document.delete_backward(&Position { x: 0, y: 10 });
+        assert_eq!(joined, Some(Position { x: 3, y: 9 }));
+        assert_eq!(lines(&document).get(9..), Some(&["  910"][..]));
+    }
+
+    #[test]
+    fn changed_rows_are_highlighted_again() {
+        let mut document = document("a\nb\nc");
+        document.highlight_rows(0..2);
+        let stale = |document: &Document| {
+            (0..document.len())
+                .map(|y| document.row(y).is_some_and(Row::is_stale))
+                .collect::<Vec<bool>>()
+        };
+        assert_eq!(stale(&document), [false, false, true]);
+
+        document.insert(&Position { x: 1, y: 1 }, 'x');
+        assert_eq!(stale(&document), [false, true, true]);
+        document.highlight_rows(0..3);
+        assert_eq!(stale(&document), [false, false, false]);
+    }
+
+    #[test]
+    fn highlighting_resumes_from_the_first_changed_row() {
+        let mut document = document("/* a\nb\nc */\nd");
+        document.file_type = FileTypes::default().detect("main.rs", &[]);
+        document.highlight_rows(0..4);
+        let comment = State::Comment {
+            depth: 1,
+            doc: false,
+        };
+        assert!(document.row(1).map(Row::end_state) == Some(comment));
+
+        document.delete(&Position::default());
+        document.highlight_rows(2..4);
+        assert!(document.row(1).map(Row::end_state) == Some(State::Normal));
+        assert!(document.row(2).map(Row::end_state) == Some(State::Normal));
+    }
 }
@@ -1,35 +1,108 @@
-use crate::{Document, Row, Terminal};
-use std::{
-    env,
-    io::stdout,
-    time::{Duration, Instant},
+use crate::{
+    filter::Filter,
+    goto::{self, Target},
+    keymap::Binding,
+    line_input::{self, Completer, LineInput},
+    macros, motion,
+    project_search::{Hit, ProjectSearch, Update},
+    prompt_history::{PromptHistory, PromptKind},
+    theme, vi, Command, Config, Document, FileTypes, Keymap, KillRing, Macros, Matcher, Row,
+    SearchOptions, Terminal, Theme, Themes, Vi,
+};
+use alloc::collections::VecDeque;
+use core::{
+    cmp::{self, Ordering},
+    mem,
+    ops::Range,
+    time::Duration,
     usize,
 };
-use termion::{color, event::Key, raw::IntoRawMode};
+use std::{env, fs, io::stdout, path::Path, thread, time::Instant};
+use termion::{event::Key, raw::IntoRawMode as _};
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr as _;
 
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const QUIT_TIMES: u8 = 3;
+const MACRO_RUN_LIMIT: usize = 10_000;
+const SEARCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const RESULTS_PER_REFRESH: usize = 1000;
 
 fn die(e: std::io::Error) {
     print!("{}", termion::clear::All);
     panic!(e);
 }
 
-#[derive(Default, Clone)]
+/// The status message shown first: the help, or what was wrong with the
+/// key bindings.
+fn keymap_status(keymap: &Result<Keymap, String>) -> String {
+    match keymap.as_ref() {
+        Ok(keymap) => match keymap.warnings().split_first() {
+            None => keymap.help(),
+            Some((warning, rest)) => {
+                format!("WARN: keys.toml: {}{}", warning, more_warnings(rest))
+            }
+        },
+        Err(error) => format!("ERR: {error}"),
+    }
+}
+
+/// The status message reporting the first of `warnings`, if any.
+fn describe_warnings(warnings: &[String]) -> Option<String> {
+    warnings
+        .split_first()
+        .map(|(warning, rest)| format!("WARN: {}{}", warning, more_warnings(rest)))
+}
+
+/// How many warnings follow the one shown, as ` (+2 more)`.
+fn more_warnings(rest: &[String]) -> String {
+    if rest.is_empty() {
+        String::new()
+    } else {
+        format!(" (+{} more)", rest.len())
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+#[expect(
+    clippy::missing_trait_methods,
+    reason = "the provided methods all follow from `cmp`"
+)]
+impl Ord for Position {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.y.cmp(&other.y).then(self.x.cmp(&other.x))
+    }
+}
+
+#[expect(
+    clippy::missing_trait_methods,
+    reason = "the provided methods all follow from `partial_cmp`"
+)]
+impl PartialOrd for Position {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 #[derive(PartialEq, Clone, Copy)]
 pub enum SearchDirection {
     Forward,
     Backward,
 }
 
+/// The label and text of a prompt, as the message bar shows them.
+struct PromptLine {
+    label: String,
+    input: LineInput,
+}
+
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
@@ -38,6 +111,83 @@ pub struct Editor {
     offset: Position,
     status_message: StatusMessage,
     quit_times: u8,
+    keymap: Keymap,
+    pending_keys: Vec<Key>,
+    vi: Option<Vi>,
+    kill_ring: KillRing,
+    last_command: Option<Command>,
+    last_yank: Option<(Position, Position)>,
+    macros: Macros,
+    config: Config,
+    file_types: FileTypes,
+    themes: Themes,
+    theme: Theme,
+    playback: VecDeque<Key>,
+    playing: bool,
+    /// Keys typed while a project search ran, read before the terminal's.
+    typeahead: VecDeque<Key>,
+    macro_failed: bool,
+    last_sequence_len: usize,
+    search_options: SearchOptions,
+    /// The open prompt, followed in the message bar by a hint that prompt
+    /// callbacks update. A failed prompt shows its text in red, as a search
+    /// without matches.
+    prompt_line: Option<PromptLine>,
+    prompt_hint: String,
+    prompt_failed: bool,
+    prompt_history: PromptHistory,
+    /// The document `switch-buffer` goes to, such as the search results.
+    alternate: Option<(Document, Position)>,
+    /// The file and position of each line of the search results.
+    results: Vec<(String, Position)>,
+    filter: Option<Filter>,
+}
+
+/// How far a project search got.
+#[derive(Default)]
+struct SearchProgress {
+    hits: usize,
+    files: usize,
+    cancelled: bool,
+    /// Why the search stopped early, if reading the keyboard or drawing
+    /// failed.
+    error: Option<String>,
+}
+
+/// A replacement offered by `replace_in_files`, previewed on two rows of the
+/// results from `row` on.
+struct Change {
+    hit: Hit,
+    replacement: String,
+    selected: bool,
+    row: usize,
+}
+
+impl Change {
+    /// The rows showing the change: the line as it is, and as it will be.
+    fn preview(&self) -> [(String, String, Range<usize>); 2] {
+        let hit = &self.hit;
+        let location = format!(
+            "  [{}] {}:{}: ",
+            if self.selected { 'x' } else { ' ' },
+            hit.position.y.saturating_add(1),
+            hit.position.x.saturating_add(1)
+        );
+        let padding = " ".repeat(location.len());
+
+        let before = hit.text.get(..hit.bytes.start).unwrap_or_default();
+        let after = hit.text.get(hit.bytes.end..).unwrap_or_default();
+        let len = self.replacement.graphemes(true).count();
+        let replaced = hit.columns.start..hit.columns.start.saturating_add(len);
+        [
+            (location, hit.text.clone(), hit.columns.clone()),
+            (
+                padding,
+                format!("{}{}{}", before, self.replacement, after),
+                replaced,
+            ),
+        ]
+    }
 }
 
 struct StatusMessage {
@@ -56,12 +206,62 @@ impl StatusMessage {
 
 impl Editor {
     pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status =
-            String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
+        let args: Vec<String> = env::args().skip(1).collect();
+        let (flags, args): (Vec<&String>, Vec<&String>) =
+            args.iter().partition(|arg| arg.starts_with("--"));
+
+        let keymap = Keymap::load();
+        let mut initial_status = keymap_status(&keymap);
+        let keymap = keymap.unwrap_or_default();
+
+        let config = Config::load().unwrap_or_else(|error| {
+            initial_status = format!("ERR: {error}");
+            Config::default()
+        });
+        let vi_mode = config.vi_mode() || flags.iter().any(|flag| *flag == "--vi");
+
+        let macros = if config.persist_macros() {
+            Macros::load().unwrap_or_else(|error| {
+                initial_status = format!("ERR: {error}");
+                Macros::default()
+            })
+        } else {
+            Macros::default()
+        };
+        if let Some(warning) = describe_warnings(macros.warnings()) {
+            initial_status = warning;
+        }
+
+        let prompt_history = PromptHistory::load().unwrap_or_else(|error| {
+            initial_status = format!("ERR: {error}");
+            PromptHistory::default()
+        });
+
+        let file_types = FileTypes::load();
+        if let Some(warning) = describe_warnings(file_types.warnings()) {
+            initial_status = warning;
+        }
+
+        let terminal = Terminal::new(config.colors()).expect("Failed to initialize terminal");
+        let themes = Themes::load(terminal.color_depth());
+        if let Some(warning) = describe_warnings(themes.warnings()) {
+            initial_status = warning;
+        }
+        let theme_name = config.theme().unwrap_or(theme::DEFAULT_THEME);
+        let theme = themes.find(theme_name).unwrap_or_else(|| {
+            initial_status = format!("ERR: Unknown theme \"{theme_name}\"");
+            themes.default_theme()
+        });
+
+        let (line_args, args): (Vec<&String>, Vec<&String>) = args
+            .into_iter()
+            .partition(|arg| goto::parse_line_arg(arg).is_some());
+        let mut target = line_args.last().and_then(|arg| goto::parse_line_arg(arg));
 
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(&file_name);
+        let document = if let Some(arg) = args.first() {
+            let (file_name, file_target) = goto::split_file_name(arg);
+            target = file_target.or(target);
+            let doc = Document::open(file_name, &file_types);
 
             if let Ok(doc) = doc {
                 doc
@@ -73,15 +273,44 @@ impl Editor {
             Document::default()
         };
 
-        Self {
+        let mut editor = Self {
             document,
             should_quit: false,
-            terminal: Terminal::new().expect("Failed to initialize terminal"),
+            terminal,
             offset: Position::default(),
             cursor_position: Position::default(),
             status_message: StatusMessage::from(initial_status),
             quit_times: QUIT_TIMES,
+            keymap,
+            pending_keys: Vec::new(),
+            vi: vi_mode.then(Vi::default),
+            kill_ring: KillRing::default(),
+            last_command: None,
+            last_yank: None,
+            macros,
+            config,
+            file_types,
+            themes,
+            theme,
+            playback: VecDeque::new(),
+            typeahead: VecDeque::new(),
+            playing: false,
+            macro_failed: false,
+            last_sequence_len: 0,
+            search_options: SearchOptions::default(),
+            prompt_line: None,
+            prompt_hint: String::new(),
+            prompt_failed: false,
+            prompt_history,
+            alternate: None,
+            results: Vec::new(),
+            filter: None,
+        };
+        if let Some(target) = target {
+            editor.cursor_position = target.resolve(&editor.document, &Position::default());
+            editor.after_keypress();
         }
+        editor
     }
 
     pub fn run(&mut self) {
@@ -101,64 +330,555 @@ impl Editor {
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
-        match pressed_key {
-            Key::Ctrl('q') => return Ok(self.quit()),
-            Key::Ctrl('s') => self.save(),
-            Key::Ctrl('f') => self.search(),
-            Key::Char(c) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(Key::Right)
-            }
-            Key::Delete => self.document.delete(&self.cursor_position),
-            Key::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(Key::Left);
-                    self.document.delete(&self.cursor_position);
-                }
-            }
-            Key::Up
-            | Key::Down
-            | Key::Left
-            | Key::Right
-            | Key::Home
-            | Key::End
-            | Key::PageUp
-            | Key::PageDown => self.move_cursor(pressed_key),
-            _ => (),
+        let pressed_key = self.read_key()?;
+        self.document.begin_undo_group();
+        self.process_key(pressed_key);
+        self.document.end_undo_group();
+        Ok(())
+    }
+
+    /// Takes the next key from the macro being played, or from those typed
+    /// ahead or the terminal while recording it into the current macro. A
+    /// prompt left open when a macro runs out of keys is cancelled.
+    fn read_key(&mut self) -> Result<Key, std::io::Error> {
+        if let Some(key) = self.playback.pop_front() {
+            return Ok(key);
+        }
+        if self.playing {
+            return Ok(Key::Esc);
+        }
+
+        let key = match self.typeahead.pop_front() {
+            Some(key) => key,
+            None => self.terminal.read_key()?,
+        };
+        self.macros.record(key);
+        Ok(key)
+    }
+
+    fn process_key(&mut self, key: Key) {
+        if self.pending_keys.is_empty() && self.document.is_read_only() && key == Key::Char('\n') {
+            return self.open_result();
+        }
+        if self.pending_keys.is_empty() {
+            if let Some(vi) = self.vi.as_mut() {
+                vi.set_recording_macro(self.macros.recording_register().is_some());
+
+                let was_inserting = vi.mode() == vi::Mode::Insert;
+                let outcome = vi.handle_key(key, &mut self.document, &mut self.cursor_position);
+                let inserting = vi.mode() == vi::Mode::Insert;
+                // A whole insert session is undone at once.
+                if inserting && !was_inserting {
+                    self.document.begin_undo_group();
+                } else if was_inserting && !inserting {
+                    self.document.end_undo_group();
+                }
+
+                match outcome {
+                    vi::Outcome::Unhandled => (),
+                    vi::Outcome::Pending => return,
+                    vi::Outcome::Handled => return self.after_keypress(),
+                    vi::Outcome::Command(command) => return self.run_command(command),
+                    vi::Outcome::Replay(keys) => {
+                        return keys
+                            .into_iter()
+                            .for_each(|replayed| self.process_key(replayed))
+                    }
+                    vi::Outcome::RecordMacro(register) => {
+                        self.start_macro(register);
+                        return self.after_keypress();
+                    }
+                    vi::Outcome::StopMacro => {
+                        self.stop_macro(1);
+                        return self.after_keypress();
+                    }
+                    vi::Outcome::PlayMacro { register, count } => {
+                        return self.play_macro(register, count);
+                    }
+                    vi::Outcome::Failed => {
+                        self.macro_failed = true;
+                        return self.after_keypress();
+                    }
+                }
+            }
+        }
+
+        if !self.pending_keys.is_empty() {
+            if let Binding::Command(Command::Cancel) = self.keymap.lookup(&[key]) {
+                self.pending_keys.clear();
+                return self.run_command(Command::Cancel);
+            }
+        }
+
+        self.pending_keys.push(key);
+        match self.keymap.lookup(&self.pending_keys) {
+            Binding::Command(command) => {
+                self.last_sequence_len = self.pending_keys.len();
+                self.pending_keys.clear();
+                self.run_command(command);
+            }
+            Binding::Prefix => (),
+            Binding::Unbound => {
+                let keys: Vec<Key> = self.pending_keys.drain(..).collect();
+                match keys[..] {
+                    [Key::Char(_)] if self.is_inserting() && self.document.is_read_only() => {
+                        self.macro_failed = true;
+                        self.status_message =
+                            StatusMessage::from("ERR: This buffer is read-only".to_owned());
+                    }
+                    [Key::Char('\t')] if self.is_inserting() && !self.document.indent().tabs() => {
+                        let width = self.document.indent().width().max(1);
+                        let spaces = width
+                            .saturating_sub(self.cursor_position.x.checked_rem(width).unwrap_or(0));
+                        self.cursor_position = self
+                            .document
+                            .insert_str(&self.cursor_position, &" ".repeat(spaces));
+                        self.after_keypress();
+                    }
+                    [Key::Char(ch)] if self.is_inserting() => {
+                        self.document.insert(&self.cursor_position, ch);
+                        self.move_cursor(Key::Right);
+                        self.after_keypress();
+                    }
+                    [_] => self.after_keypress(),
+                    _ => {
+                        self.macro_failed = true;
+                        self.status_message = StatusMessage::from(format!(
+                            "{} is undefined",
+                            self.keymap.describe_keys(&keys)
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn run_command(&mut self, command: Command) {
+        if command == Command::Quit {
+            return self.quit();
         }
+        if command.is_edit() && self.document.is_read_only() {
+            if command == Command::InsertNewline {
+                return self.open_result();
+            }
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from("ERR: This buffer is read-only".to_owned());
+            return;
+        }
+        let before = self.cursor_position.clone();
+        self.execute(command);
+        if command.is_motion() && self.cursor_position == before {
+            self.macro_failed = true;
+        }
+        self.after_keypress();
+        self.last_command = Some(command);
+    }
 
+    fn after_keypress(&mut self) {
+        self.last_command = None;
+        let y = if let Some(filter) = self.filter.as_mut() {
+            filter.update(&self.document, self.cursor_position.y);
+            filter.snap(self.cursor_position.y)
+        } else {
+            let len = self.document.len();
+            self.document.folds_mut().snap(self.cursor_position.y, len)
+        };
+        if y != self.cursor_position.y {
+            let x = cmp::min(
+                self.cursor_position.x,
+                self.document.row_len(y).unwrap_or(0),
+            );
+            self.cursor_position = Position { x, y };
+        }
+        if let Some(vi) = self.vi.as_ref() {
+            vi.clamp(&self.document, &mut self.cursor_position);
+        }
         self.scroll();
         if self.quit_times < QUIT_TIMES {
             self.quit_times = QUIT_TIMES;
             self.status_message = StatusMessage::from(String::new());
         }
-        Ok(())
+    }
+
+    /// Deletes the character before the cursor, or joins its row to the row
+    /// above it in the document, unfolding that row if it is folded away.
+    /// A row a filter hides is not joined into.
+    fn delete_backward(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let hidden = y.checked_sub(1).filter(|above| {
+            x == 0
+                && self
+                    .filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter.is_shown(*above))
+        });
+        if hidden.is_some() {
+            self.macro_failed = true;
+            self.status_message =
+                StatusMessage::from("ERR: The line above is hidden by the filter".to_owned());
+        } else if let Some(position) = self.document.delete_backward(&self.cursor_position) {
+            self.cursor_position = position;
+            self.reveal_cursor();
+        }
+    }
+
+    fn kill_line(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let Some(len) = self.document.row_len(y) else {
+            return;
+        };
+
+        let end = if x < len {
+            Position { x: len, y }
+        } else if y.saturating_add(1) < self.document.len() {
+            Position {
+                x: 0,
+                y: y.saturating_add(1),
+            }
+        } else {
+            return;
+        };
+
+        let text = self.document.delete_range(&self.cursor_position, &end);
+        if self.last_command == Some(Command::KillLine) {
+            self.kill_ring.append(&text);
+        } else {
+            self.kill_ring.push(text);
+        }
+    }
+
+    fn yank(&mut self) {
+        let text = match self.kill_ring.yank() {
+            Some(text) => text.to_owned(),
+            None => return,
+        };
+
+        let start = self.cursor_position.clone();
+        self.cursor_position = self.document.insert_str(&start, &text);
+        self.last_yank = Some((start, self.cursor_position.clone()));
+    }
+
+    fn yank_pop(&mut self) {
+        let (start, end) = if let (Some(Command::Yank | Command::YankPop), Some(range)) =
+            (self.last_command, self.last_yank.as_ref())
+        {
+            range.clone()
+        } else {
+            self.status_message = StatusMessage::from("Previous command was not a yank".to_owned());
+            return;
+        };
+        let text = match self.kill_ring.rotate() {
+            Some(text) => text.to_owned(),
+            None => return,
+        };
+
+        self.document.delete_range(&start, &end);
+        self.cursor_position = self.document.insert_str(&start, &text);
+        self.last_yank = Some((start, self.cursor_position.clone()));
+    }
+
+    fn undo(&mut self) {
+        if let Some(position) = self.document.undo() {
+            self.cursor_position = position;
+            self.reveal_cursor();
+        } else if self.document.has_open_undo_edits() {
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from(
+                "ERR: Cannot undo in the middle of an insert or macro".to_owned(),
+            );
+        } else {
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from("Nothing to undo".to_owned());
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(position) = self.document.redo() {
+            self.cursor_position = position;
+            self.reveal_cursor();
+        } else {
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from("Nothing to redo".to_owned());
+        }
+    }
+
+    fn start_macro(&mut self, register: char) {
+        if let Some(recording) = self.macros.recording_register() {
+            self.status_message =
+                StatusMessage::from(format!("Already recording macro @{recording}"));
+            return;
+        }
+        self.macros.start(register);
+        self.status_message = StatusMessage::from(format!("Recording macro @{register}"));
+    }
+
+    /// Stops recording, dropping the `trailing` keys that asked to stop.
+    fn stop_macro(&mut self, trailing: usize) {
+        if let Some(register) = self.macros.stop(trailing) {
+            self.status_message = StatusMessage::from(format!("Recorded macro @{register}"));
+            self.save_macros();
+        } else {
+            self.status_message = StatusMessage::from("Not recording a macro".to_owned());
+        }
+    }
+
+    fn save_macros(&mut self) {
+        if !self.config.persist_macros() {
+            return;
+        }
+        if let Err(error) = self.macros.save() {
+            self.status_message = StatusMessage::from(format!("ERR: {error}"));
+        }
+    }
+
+    /// Plays a macro `times` times, or until it fails when `times` is 0. A
+    /// run fails when one of its commands does nothing, and a run that
+    /// changes neither the document nor the cursor stops the repetition.
+    fn play_macro(&mut self, register: Option<char>, times: usize) {
+        let (register, keys) = match self.macro_keys(register) {
+            Ok(found) => found,
+            Err(error) => {
+                self.macro_failed = true;
+                self.status_message = StatusMessage::from(error);
+                return;
+            }
+        };
+        self.macros.set_last(register);
+
+        let outer_playback = mem::take(&mut self.playback);
+        let outer_playing = mem::replace(&mut self.playing, true);
+        let limit = if times == 0 { MACRO_RUN_LIMIT } else { times };
+        let mut runs: usize = 0;
+
+        self.document.begin_undo_group();
+        while runs < limit && !self.should_quit {
+            let revision = self.document.revision();
+            let cursor = self.cursor_position.clone();
+
+            self.macro_failed = false;
+            self.playback.clone_from(&keys);
+            while let Some(key) = self.playback.pop_front() {
+                self.process_key(key);
+                if self.macro_failed || self.should_quit {
+                    break;
+                }
+            }
+            self.pending_keys.clear();
+
+            if self.macro_failed {
+                break;
+            }
+            runs = runs.saturating_add(1);
+            if times == 0 && revision == self.document.revision() && cursor == self.cursor_position
+            {
+                break;
+            }
+        }
+        self.document.end_undo_group();
+
+        self.playback = outer_playback;
+        self.playing = outer_playing;
+        self.after_keypress();
+        self.status_message = StatusMessage::from(match runs {
+            1 => format!("Played macro @{register} once"),
+            _ => format!("Played macro @{register} {runs} times"),
+        });
+    }
+
+    fn macro_keys(&self, register: Option<char>) -> Result<(char, VecDeque<Key>), String> {
+        let register = register
+            .or_else(|| self.macros.last())
+            .ok_or("No macro to play")?
+            .to_ascii_lowercase();
+
+        if self
+            .macros
+            .recording_register()
+            .map(|recording| recording.to_ascii_lowercase())
+            == Some(register)
+        {
+            return Err(format!("Cannot play macro @{register} while recording it"));
+        }
+        match self.macros.get(register) {
+            Some(keys) if !keys.is_empty() => Ok((register, keys.iter().copied().collect())),
+            _ => Err(format!("Macro @{register} is empty")),
+        }
+    }
+
+    fn play_macro_times(&mut self) {
+        let input = self
+            .prompt(
+                PromptKind::Macro,
+                "Play macro ([register] [count], 0 = until failure): ",
+                None,
+                |_, _, _| {},
+            )
+            .unwrap_or(None)
+            .unwrap_or_default();
+
+        let mut register = None;
+        let mut times = 1;
+        for word in input.split_whitespace() {
+            if let Ok(count) = word.parse() {
+                times = count;
+            } else if let (Some(ch), 1) = (word.chars().next(), word.chars().count()) {
+                register = Some(ch);
+            } else {
+                self.status_message = StatusMessage::from(format!("ERR: Invalid input: {word}"));
+                return;
+            }
+        }
+        self.play_macro(register, times);
+    }
+
+    fn name_macro(&mut self) {
+        let keys = if let Some(keys) = self.macros.last().and_then(|last| self.macros.get(last)) {
+            keys.to_vec()
+        } else {
+            self.status_message = StatusMessage::from("No macro to name".to_owned());
+            return;
+        };
+
+        let name = self
+            .prompt(
+                PromptKind::Macro,
+                "Name last macro (register): ",
+                None,
+                |_, _, _| {},
+            )
+            .unwrap_or(None);
+        let register = match name.as_deref().map(|name| {
+            let mut chars = name.chars();
+            (chars.next(), chars.next())
+        }) {
+            Some((Some(register), None)) => register,
+            Some(_) => {
+                self.status_message =
+                    StatusMessage::from("ERR: A register is a single character".to_owned());
+                return;
+            }
+            None => return,
+        };
+
+        self.macros.store(register, keys);
+        self.macros.set_last(register);
+        self.status_message = StatusMessage::from(format!("Saved macro as @{register}"));
+        self.save_macros();
+    }
+
+    fn is_inserting(&self) -> bool {
+        self.vi
+            .as_ref()
+            .is_none_or(|vi| vi.mode() == vi::Mode::Insert)
+    }
+
+    fn toggle_vi_mode(&mut self) {
+        if self.vi.as_ref().map(Vi::mode) == Some(vi::Mode::Insert) {
+            self.document.end_undo_group();
+        }
+        self.vi = match self.vi {
+            Some(_) => None,
+            None => Some(Vi::default()),
+        };
+        let state = if self.vi.is_some() {
+            "enabled"
+        } else {
+            "disabled"
+        };
+        self.status_message = StatusMessage::from(format!("vi mode {state}"));
+    }
+
+    fn execute(&mut self, command: Command) {
+        match command {
+            Command::Quit => self.quit(),
+            Command::Save => self.save(),
+            Command::Find | Command::SearchNext | Command::SearchPrevious => self.search(),
+            Command::GotoLine => self.goto_line(),
+            Command::Replace => self.replace(),
+            Command::ReplaceAll => self.replace_all(),
+            Command::InsertNewline => {
+                self.document.insert(&self.cursor_position, '\n');
+                self.cursor_position = Position {
+                    x: 0,
+                    y: self.cursor_position.y.saturating_add(1),
+                };
+            }
+            Command::DeleteForward => self.document.delete(&self.cursor_position),
+            Command::DeleteBackward => self.delete_backward(),
+            Command::MoveUp => self.move_cursor(Key::Up),
+            Command::MoveDown => self.move_cursor(Key::Down),
+            Command::MoveLeft => self.move_cursor(Key::Left),
+            Command::MoveRight => self.move_cursor(Key::Right),
+            Command::MoveLineStart => self.move_cursor(Key::Home),
+            Command::MoveLineEnd => self.move_cursor(Key::End),
+            Command::PageUp => self.move_cursor(Key::PageUp),
+            Command::PageDown => self.move_cursor(Key::PageDown),
+            Command::MoveWordForward => {
+                self.cursor_position = motion::forward_word(&self.document, &self.cursor_position);
+            }
+            Command::MoveWordBackward => {
+                self.cursor_position = motion::backward_word(&self.document, &self.cursor_position);
+            }
+            Command::KillLine => self.kill_line(),
+            Command::Yank => self.yank(),
+            Command::YankPop => self.yank_pop(),
+            Command::Cancel => self.status_message = StatusMessage::from("Quit".to_owned()),
+            Command::Undo => self.undo(),
+            Command::Redo => self.redo(),
+            Command::RecordMacro => {
+                if self.macros.recording_register().is_some() {
+                    self.stop_macro(self.last_sequence_len);
+                } else {
+                    self.start_macro(macros::DEFAULT_REGISTER);
+                }
+            }
+            Command::StartMacro => self.start_macro(macros::DEFAULT_REGISTER),
+            Command::StopMacro => self.stop_macro(self.last_sequence_len),
+            Command::PlayMacro => self.play_macro(None, 1),
+            Command::PlayMacroTimes => self.play_macro_times(),
+            Command::NameMacro => self.name_macro(),
+            Command::ToggleViMode => self.toggle_vi_mode(),
+            Command::SetFileType => self.set_file_type(),
+            Command::SetTheme => self.set_theme(),
+            Command::FindInFiles => self.find_in_files(),
+            Command::ReplaceInFiles => self.replace_in_files(),
+            Command::FilterLines => self.filter_lines(),
+            Command::Fold => self.fold(),
+            Command::Unfold => self.unfold(),
+            Command::ToggleFolds => {
+                self.document.toggle_folds();
+                self.leave_folds();
+            }
+            Command::SwitchBuffer => self.switch_buffer(),
+        }
     }
 
     fn move_cursor(&mut self, key: Key) {
         let terminal_height = self.terminal.size().height as usize;
         let Position { mut x, mut y } = self.cursor_position;
 
-        let height = self.document.len();
+        // Rows are counted as shown, so that the rows hidden by a filter or by
+        // folds are skipped.
+        let row = self.view_row(y);
+        let height = self.view_len();
         let mut width = self.document.row_len(y).unwrap_or(0);
 
         match key {
-            Key::Up => y = y.saturating_sub(1),
-            Key::Down => y = std::cmp::min(y.saturating_add(1), height),
+            Key::Up => y = self.document_row(row.saturating_sub(1)),
+            Key::Down => y = self.document_row(cmp::min(row.saturating_add(1), height)),
             Key::Left => {
                 if x > 0 {
                     x -= 1;
-                } else if y > 0 {
-                    y -= 1;
+                } else if row > 0 {
+                    y = self.document_row(row.saturating_sub(1));
                     x = self.document.row_len(y).unwrap_or(0);
                 }
             }
             Key::Right => {
                 if x < width {
                     x += 1;
-                } else if y < height {
-                    y += 1;
+                } else if row < height {
+                    y = self.document_row(row.saturating_add(1));
                     x = 0;
                 }
             }
@@ -166,17 +886,17 @@ impl Editor {
             Key::Home => x = 0,
             Key::End => x = width,
             Key::PageUp => {
-                y = if y > terminal_height {
-                    y.saturating_sub(terminal_height)
+                y = if row > terminal_height {
+                    self.document_row(row.saturating_sub(terminal_height))
                 } else {
-                    0
+                    self.document_row(0)
                 }
             }
             Key::PageDown => {
-                y = if y.saturating_add(terminal_height) < height {
-                    y.saturating_add(terminal_height)
+                y = if row.saturating_add(terminal_height) < height {
+                    self.document_row(row.saturating_add(terminal_height))
                 } else {
-                    height
+                    self.document_row(height)
                 }
             }
             _ => (),
@@ -191,7 +911,44 @@ impl Editor {
         self.cursor_position = Position { x, y };
     }
 
-    fn refresh_screen(&self) -> Result<(), std::io::Error> {
+    /// The number of rows shown, which a filter or folds make fewer than
+    /// the document's. Folds are shown unfolded while filtering.
+    fn view_len(&self) -> usize {
+        self.filter.as_ref().map_or_else(
+            || self.document.folds().len(self.document.len()),
+            Filter::len,
+        )
+    }
+
+    /// The document row shown at `index`.
+    fn document_row(&self, index: usize) -> usize {
+        self.filter.as_ref().map_or_else(
+            || self.document.folds().document_row(index),
+            |filter| filter.document_row(index),
+        )
+    }
+
+    /// Where document row `y` is shown.
+    fn view_row(&self, y: usize) -> usize {
+        self.filter.as_ref().map_or_else(
+            || self.document.folds().index_of(y),
+            |filter| filter.index_of(y),
+        )
+    }
+
+    /// The width of the line numbers shown next to the rows of a filter.
+    fn gutter_width(&self) -> usize {
+        if self.filter.is_some() {
+            self.document.len().to_string().len().saturating_add(1)
+        } else {
+            0
+        }
+    }
+
+    fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
+        let rows = self.visible_rows();
+        self.document.highlight_rows(rows);
+
         Terminal::cursor_hide();
         Terminal::cursor_position(&Position::default());
 
@@ -202,16 +959,43 @@ impl Editor {
             self.draw_rows();
             self.draw_status_bar();
             self.draw_message_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            });
+            Terminal::reset_style();
+            Terminal::cursor_position(&self.screen_cursor());
         }
 
         Terminal::cursor_show();
         Terminal::flush()
     }
 
+    /// Where the terminal cursor goes: at the answer being typed in a prompt,
+    /// or at the cursor in the document.
+    fn screen_cursor(&self) -> Position {
+        if let (Some(prompt), true) = (self.prompt_line.as_ref(), self.pending_keys.is_empty()) {
+            let width = usize::from(self.terminal.size().width);
+            let x = prompt
+                .label
+                .chars()
+                .count()
+                .saturating_add(prompt.input.cursor_column())
+                .min(width.saturating_sub(1));
+            return Position {
+                x,
+                y: usize::from(self.terminal.size().height.saturating_add(1)),
+            };
+        }
+
+        Position {
+            x: self
+                .cursor_position
+                .x
+                .saturating_sub(self.offset.x)
+                .saturating_add(self.gutter_width()),
+            y: self
+                .view_row(self.cursor_position.y)
+                .saturating_sub(self.offset.y),
+        }
+    }
+
     fn draw_welcome_message(&self) {
         let welcome_message = format!("Hecto editor -- version {}\r", VERSION);
         let width = self.terminal.size().width as usize;
@@ -229,14 +1013,19 @@ impl Editor {
     #[allow(clippy::integer_division, clippy::integer_arithmetic)]
     fn draw_rows(&self) {
         let height = self.terminal.size().height;
+        let visible = self.visible_rows();
+        let matches = self.document.search_matches(visible.clone());
+
         for terminal_row in 0..height {
+            Terminal::set_style(self.theme.text());
             Terminal::clear_current_line();
 
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                self.draw_row(row);
+            let index = self.document_row(self.offset.y.saturating_add(usize::from(terminal_row)));
+            if let Some(row) = self.document.row(index) {
+                let row_matches = matches
+                    .get(index.saturating_sub(visible.start))
+                    .map_or(&[][..], Vec::as_slice);
+                self.draw_row(row, index, row_matches);
             } else if self.document.is_empty() && terminal_row == height / 3 {
                 self.draw_welcome_message();
             } else {
@@ -245,13 +1034,70 @@ impl Editor {
         }
     }
 
-    fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
+    /// The document rows from the first to the last one on screen, including
+    /// those a filter hides in between.
+    fn visible_rows(&self) -> Range<usize> {
+        let height = usize::from(self.terminal.size().height);
+        let first = self.document_row(self.offset.y);
+        let last = self.document_row(
+            self.offset
+                .y
+                .saturating_add(height)
+                .saturating_sub(1)
+                .min(self.view_len()),
+        );
+        first..cmp::min(last.saturating_add(1), self.document.len())
+    }
+
+    fn draw_row(&self, row: &Row, index: usize, matches: &[Range<usize>]) {
+        let gutter = self.gutter_width();
+        if gutter > 0 {
+            Terminal::set_style(self.theme.line_number());
+            print!(
+                "{:>width$} ",
+                index.saturating_add(1),
+                width = gutter.saturating_sub(1)
+            );
+            Terminal::set_style(self.theme.text());
+        }
+
+        let width = usize::from(self.terminal.size().width).saturating_sub(gutter);
         let start = self.offset.x;
         let end = self.offset.x.saturating_add(width);
 
-        let row = row.render(start, end);
-        println!("{}\r", row);
+        let rendered = row.render(
+            &self.theme,
+            start,
+            end,
+            self.selection_in_row(index, row.len()).as_ref(),
+            matches,
+        );
+        print!("{rendered}");
+
+        let fold = self.document.folds().at(index);
+        if let Some(fold) = fold.filter(|_| self.filter.is_none()) {
+            let shown = cmp::min(row.len().saturating_sub(start), width);
+            let lines = if fold.hidden() == 1 { "line" } else { "lines" };
+            let summary: String = format!(" ··· {} {}", fold.hidden(), lines)
+                .chars()
+                .take(width.saturating_sub(shown))
+                .collect();
+            Terminal::set_style(self.theme.fold());
+            print!("{summary}");
+            Terminal::set_style(self.theme.text());
+        }
+        println!("\r");
+    }
+
+    fn selection_in_row(&self, index: usize, len: usize) -> Option<Range<usize>> {
+        let (start, end) = self.vi.as_ref()?.selection(&self.cursor_position)?;
+        if index < start.y || index > end.y {
+            return None;
+        }
+
+        let from = if index == start.y { start.x } else { 0 };
+        let to = if index == end.y { end.x } else { len };
+        Some(from..to)
     }
 
     fn draw_status_bar(&self) {
@@ -261,6 +1107,8 @@ impl Editor {
         let mut file_name = "[No Name]".to_string();
         if let Some(name) = &self.document.file_name {
             file_name = name.graphemes(true).take(20).collect();
+        } else if self.document.is_read_only() {
+            "[Results]".clone_into(&mut file_name);
         }
 
         let modified_indicator = match self.document.is_dirty() {
@@ -268,15 +1116,34 @@ impl Editor {
             false => "",
         };
 
+        let mut mode = match self.vi.as_ref() {
+            Some(vi) => format!("{} | ", vi.mode()),
+            None => String::new(),
+        };
+        if let Some(register) = self.macros.recording_register() {
+            mode = format!("{mode}REC @{register} | ");
+        }
+        if let Some(filter) = self.filter.as_ref() {
+            mode = format!("{}FILTER {}/{} | ", mode, filter.len(), self.document.len());
+        }
+        let pending = match self.vi.as_ref() {
+            Some(vi) if !vi.pending_keys().is_empty() => {
+                format!("{} | ", self.keymap.describe_keys(vi.pending_keys()))
+            }
+            _ => String::new(),
+        };
+
         status = format!(
-            "{} - {} lines{}",
+            "{}{} - {} lines{}",
+            mode,
             file_name,
             self.document.len(),
             modified_indicator
         );
 
         let line_indicator = format!(
-            "{} | {}:{} ",
+            "{}{} | {}:{} ",
+            pending,
             self.document.file_type(),
             self.cursor_position.y.saturating_add(1),
             self.cursor_position.x.saturating_add(1),
@@ -288,16 +1155,40 @@ impl Editor {
         status = format!("{}{}", status, line_indicator);
 
         status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
+        Terminal::set_style(self.theme.status_bar());
         println!("{}\r", status);
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
     }
 
     fn draw_message_bar(&self) {
+        Terminal::set_style(self.theme.text());
         Terminal::clear_current_line();
 
+        if !self.pending_keys.is_empty() {
+            print!("{}-", self.keymap.describe_keys(&self.pending_keys));
+            return;
+        }
+
+        if let Some(prompt) = self.prompt_line.as_ref() {
+            let width = usize::from(self.terminal.size().width);
+            let label: String = prompt.label.chars().take(width).collect();
+            let input_width = width.saturating_sub(label.chars().count());
+            let input = prompt.input.visible(input_width);
+            let hint: String = self
+                .prompt_hint
+                .chars()
+                .take(input_width.saturating_sub(input.width()))
+                .collect();
+
+            print!("{label}");
+            if self.prompt_failed {
+                Terminal::set_style(self.theme.error());
+            }
+            print!("{input}");
+            Terminal::set_style(self.theme.text());
+            print!("{hint}");
+            return;
+        }
+
         let message = &self.status_message;
         if Instant::now() - message.time < Duration::new(5, 0) {
             let mut text = message.text.clone();
@@ -306,11 +1197,20 @@ impl Editor {
         }
     }
 
+    /// Unfolds the folds hiding the cursor after it jumped there, as to a
+    /// search match.
+    fn reveal_cursor(&mut self) {
+        if self.filter.is_none() {
+            self.document.reveal(self.cursor_position.y);
+        }
+    }
+
     fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
+        let x = self.cursor_position.x;
+        let y = self.view_row(self.cursor_position.y);
 
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
+        let width = usize::from(self.terminal.size().width).saturating_sub(self.gutter_width());
+        let height = usize::from(self.terminal.size().height);
 
         let mut offset = &mut self.offset;
 
@@ -327,48 +1227,139 @@ impl Editor {
         }
     }
 
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    fn prompt<C>(
+        &mut self,
+        kind: PromptKind,
+        prompt: &str,
+        completer: Option<Completer<'_>>,
+        callback: C,
+    ) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &str),
+    {
+        Ok(self
+            .prompt_text(kind, prompt, completer, callback)?
+            .filter(|result| !result.is_empty()))
+    }
+
+    /// Like `prompt`, but accepting an empty answer: only a cancelled prompt
+    /// returns `None`. The answer is edited with a `LineInput`; Up and Down go
+    /// through the answers given to earlier prompts of the same kind and Tab
+    /// asks `completer`, if any, to complete it.
+    fn prompt_text<C>(
+        &mut self,
+        kind: PromptKind,
+        prompt: &str,
+        completer: Option<Completer<'_>>,
+        mut callback: C,
+    ) -> Result<Option<String>, std::io::Error>
     where
-        C: FnMut(&mut Self, Key, &String),
+        C: FnMut(&mut Self, Key, &str),
     {
-        let mut result = String::new();
+        let history = self.prompt_history.entries(kind).to_vec();
+        let mut browsing: usize = 0;
+        let mut draft = String::new();
+
+        let width = usize::from(self.terminal.size().width).saturating_sub(prompt.chars().count());
+        let mut input = LineInput::default();
+        let mut cancelled = false;
         loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
-            self.refresh_screen()?;
+            input.scroll(width);
+            self.prompt_line = Some(PromptLine {
+                label: prompt.to_owned(),
+                input: input.clone(),
+            });
+            if !self.playing {
+                self.refresh_screen()?;
+            }
 
-            let key = Terminal::read_key()?;
-            match key {
-                Key::Backspace => {
-                    if !result.is_empty() {
-                        result.pop();
+            let key = self.read_key()?;
+            let command = match self.keymap.lookup(&[key]) {
+                Binding::Command(command) => Some(command),
+                _ => None,
+            };
+            let before = input.text().to_owned();
+            match (key, command) {
+                (Key::Up, _) if browsing < history.len() => {
+                    if browsing == 0 {
+                        draft.clone_from(&before);
+                    }
+                    browsing = browsing.saturating_add(1);
+                    if let Some(entry) = history.get(history.len().saturating_sub(browsing)) {
+                        input.set_text(entry);
+                    }
+                }
+                (Key::Down, _) if browsing > 0 => {
+                    browsing = browsing.saturating_sub(1);
+                    if browsing == 0 {
+                        input.set_text(&mem::take(&mut draft));
+                    } else if let Some(entry) = history.get(history.len().saturating_sub(browsing))
+                    {
+                        input.set_text(entry);
                     }
                 }
-                Key::Ctrl('q') => {
+                (_, Some(Command::Quit)) => {
                     self.should_quit = true;
+                    cancelled = true;
                     break;
                 }
-                Key::Esc => {
-                    result.truncate(0);
+                (Key::Esc, _) | (_, Some(Command::Cancel)) => {
+                    cancelled = true;
                     break;
                 }
-                Key::Char('\n') | Key::Ctrl('s') => break,
-                Key::Char(c) if !c.is_control() => result.push(c),
-                _ => (),
+                (Key::Char('\n'), _) | (_, Some(Command::Save)) => break,
+                (Key::Char('\t'), _) => {
+                    if let Some(complete) = completer {
+                        let candidates = complete(input.text());
+                        input.complete(&candidates);
+                        self.prompt_hint = completion_hint(&candidates);
+                    }
+                }
+                (_, Some(Command::Yank)) => {
+                    if let Some(text) = self.kill_ring.yank() {
+                        input.insert_str(text);
+                    }
+                }
+                _ => {
+                    if input.handle(key, command) && completer.is_some() {
+                        self.prompt_hint.clear();
+                    }
+                }
+            }
+            if input.text() != before && !matches!(key, Key::Up | Key::Down) {
+                browsing = 0;
             }
-            callback(self, key, &result);
+            callback(self, key, input.text());
         }
         self.status_message = StatusMessage::from(String::new());
+        self.prompt_line = None;
+        self.prompt_hint.clear();
+        self.prompt_failed = false;
 
-        return Ok(match result.is_empty() {
-            true => None,
-            false => Some(result),
-        });
+        if cancelled {
+            return Ok(None);
+        }
+        let result = input.text().to_owned();
+        self.prompt_history.add(kind, &result);
+        if !self.playing {
+            if let Err(error) = self.prompt_history.save() {
+                self.status_message = StatusMessage::from(format!("ERR: {error}"));
+            }
+        }
+        Ok(Some(result))
     }
 
     fn quit(&mut self) {
-        if self.quit_times > 0 && self.document.is_dirty() {
+        let alternate_dirty = self
+            .alternate
+            .as_ref()
+            .is_some_and(|alternate| alternate.0.is_dirty());
+        if self.quit_times > 0 && (self.document.is_dirty() || alternate_dirty) {
             self.status_message = StatusMessage::from(format!(
-                "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                "WARNING! File has unsaved changes. Press {} {} more times to quit.",
+                self.keymap
+                    .describe(Command::Quit)
+                    .unwrap_or_else(|| Command::Quit.to_string()),
                 self.quit_times
             ));
             self.quit_times -= 1;
@@ -378,8 +1369,19 @@ impl Editor {
     }
 
     fn save(&mut self) {
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::from("ERR: This buffer is read-only".to_owned());
+            return;
+        }
         if self.document.file_name.is_none() {
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
+            let new_name = self
+                .prompt(
+                    PromptKind::File,
+                    "Save as: ",
+                    Some(&line_input::complete_path),
+                    |_, _, _| {},
+                )
+                .unwrap_or(None);
 
             if new_name.is_none() {
                 self.status_message = StatusMessage::from("Save aborted.".to_string());
@@ -387,6 +1389,7 @@ impl Editor {
             }
 
             self.document.file_name = new_name;
+            self.document.detect_file_type(&self.file_types);
         }
 
         self.status_message = match self.document.save() {
@@ -395,49 +1398,957 @@ impl Editor {
         }
     }
 
-    fn search(&mut self) {
+    /// Asks for a file type, by name or alias, and highlights and indents
+    /// the document as that type.
+    fn set_file_type(&mut self) {
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::from("ERR: This buffer is read-only".to_owned());
+            return;
+        }
+        let names = self.file_types.names();
+        let complete = |input: &str| -> Vec<String> {
+            let input = input.to_lowercase();
+            names
+                .iter()
+                .filter(|name| name.to_lowercase().starts_with(&input))
+                .cloned()
+                .collect()
+        };
+        let name = self
+            .prompt(
+                PromptKind::FileType,
+                "Filetype: ",
+                Some(&complete),
+                |_, _, _| {},
+            )
+            .unwrap_or(None);
+        let Some(name) = name else { return };
+        if let Some(file_type) = self.file_types.find(&name) {
+            let message = format!("Filetype set to {}", file_type.name());
+            self.document.set_file_type(file_type);
+            self.status_message = StatusMessage::from(message);
+        } else {
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from(format!("ERR: Unknown filetype \"{name}\""));
+        }
+    }
+
+    /// Asks for a theme, showing each one named as it is typed, and switches
+    /// to it. Cancelling goes back to the theme from before.
+    fn set_theme(&mut self) {
+        let old_theme = self.theme.clone();
+        let names = self.themes.names();
+        let complete = |input: &str| -> Vec<String> {
+            let input = input.to_lowercase();
+            names
+                .iter()
+                .filter(|name| name.to_lowercase().starts_with(&input))
+                .cloned()
+                .collect()
+        };
+        let preview = |editor: &mut Self, _, input: &str| {
+            editor.theme = editor
+                .themes
+                .find(input)
+                .unwrap_or_else(|| old_theme.clone());
+        };
+        let name = self
+            .prompt(PromptKind::Theme, "Theme: ", Some(&complete), preview)
+            .unwrap_or(None);
+        let Some(name) = name else {
+            self.theme = old_theme;
+            return;
+        };
+        if let Some(theme) = self.themes.find(&name) {
+            self.status_message = StatusMessage::from(format!("Theme set to {}", theme.name()));
+            self.theme = theme;
+        } else {
+            self.theme = old_theme;
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from(format!("ERR: Unknown theme \"{name}\""));
+        }
+    }
+
+    fn goto_line(&mut self) {
         let old_position = self.cursor_position.clone();
-        let mut direction = SearchDirection::Forward;
 
-        let query_handler = |editor: &mut Self, key, query: &String| {
-            let mut moved = false;
+        let preview = |editor: &mut Self, _, input: &str| {
+            if let Some(target) = Target::parse(input) {
+                editor.cursor_position = target.resolve(&editor.document, &old_position);
+                editor.reveal_cursor();
+                editor.scroll();
+            }
+        };
+        let input = self
+            .prompt(
+                PromptKind::Goto,
+                "Go to (line[:col], +N, -N, N%): ",
+                None,
+                preview,
+            )
+            .unwrap_or(None);
 
+        match input.as_deref().map(|input| (Target::parse(input), input)) {
+            Some((Some(target), _)) => {
+                self.cursor_position = target.resolve(&self.document, &old_position);
+                self.reveal_cursor();
+            }
+            Some((None, input)) => {
+                self.cursor_position = old_position;
+                self.macro_failed = true;
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Invalid position: {input}"));
+            }
+            None => self.cursor_position = old_position,
+        }
+        self.scroll();
+    }
+
+    /// Asks for a query, toggling the search options with Alt and
+    /// highlighting its matches in the document as it is typed.
+    fn query_prompt(&mut self, label: &str) -> Option<Matcher> {
+        let mut options = self.search_options;
+        let query_handler = |editor: &mut Self, key, query: &str| {
+            if let Key::Alt(ch) = key {
+                options.toggle(ch);
+            }
+            match Matcher::new(query, options) {
+                Ok(matcher) => {
+                    editor.prompt_hint = search_hint(options);
+                    editor.document.highlight(Some(&matcher));
+                }
+                Err(error) => {
+                    editor.prompt_hint = format!(" [{error}]");
+                    editor.document.highlight(None);
+                }
+            }
+        };
+
+        self.prompt_hint = search_hint(self.search_options);
+        let query = self
+            .prompt(PromptKind::Search, label, None, query_handler)
+            .unwrap_or(None);
+        self.search_options = options;
+        self.document.highlight(None);
+
+        match Matcher::new(&query?, options) {
+            Ok(matcher) => Some(matcher),
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                None
+            }
+        }
+    }
+
+    /// Asks for a query, with the search options, and its replacement.
+    fn replace_prompts(&mut self, label: &str) -> Option<(Matcher, String)> {
+        let matcher = self.query_prompt(label)?;
+        let replacement = self
+            .prompt_text(
+                PromptKind::Replacement,
+                "Replace with: ",
+                None,
+                |_, _, _| {},
+            )
+            .unwrap_or(None)?;
+        Some((matcher, replacement))
+    }
+
+    fn replace(&mut self) {
+        let Some((matcher, replacement)) =
+            self.replace_prompts("Replace (ESC to cancel, Alt-R/C/S/W): ")
+        else {
+            return;
+        };
+
+        let mut at = self.cursor_position.clone();
+        let mut count: usize = 0;
+        self.document.highlight(Some(&matcher));
+        while let Some((start, end)) =
+            self.document
+                .find_match(&matcher, &at, SearchDirection::Forward)
+        {
+            self.cursor_position = start.clone();
+            self.reveal_cursor();
+            self.scroll();
+
+            let answer = self.ask("Replace this match? (y)es, (n)o, (a)ll, (q)uit");
+            match answer {
+                Ok(Key::Char('y')) => {
+                    at = self
+                        .document
+                        .replace_match(&matcher, &start, &replacement)
+                        .unwrap_or(end);
+                    count = count.saturating_add(1);
+                    self.document.highlight(Some(&matcher));
+                }
+                Ok(Key::Char('n')) => match motion::step_forward(&self.document, &start) {
+                    Some(next) => at = next,
+                    None => break,
+                },
+                Ok(Key::Char('a')) => {
+                    let last = self.document.len().saturating_sub(1);
+                    let document_end = Position {
+                        x: self.document.row_len(last).unwrap_or(0),
+                        y: last,
+                    };
+                    count = count.saturating_add(self.document.replace_all(
+                        &matcher,
+                        Some((start, document_end)),
+                        &replacement,
+                    ));
+                    break;
+                }
+                Ok(Key::Char('q') | Key::Esc) | Err(_) => break,
+                Ok(_) => (),
+            }
+        }
+        self.document.highlight(None);
+        self.status_message = StatusMessage::from(replaced_message(count));
+    }
+
+    /// Replaces every match in the visual selection, or in the whole document.
+    fn replace_all(&mut self) {
+        let selection = self
+            .vi
+            .as_ref()
+            .and_then(|vi| vi.selection(&self.cursor_position));
+        let Some((matcher, replacement)) =
+            self.replace_prompts("Replace (ESC to cancel, Alt-R/C/S/W): ")
+        else {
+            return;
+        };
+
+        let count = self
+            .document
+            .replace_all(&matcher, selection.clone(), &replacement);
+        if let (Some(vi), Some((start, _))) = (self.vi.as_mut(), selection) {
+            vi.leave_visual();
+            self.cursor_position = start;
+        }
+        if count == 0 {
+            self.macro_failed = true;
+        }
+        self.status_message = StatusMessage::from(replaced_message(count));
+    }
+
+    /// Shows only the rows matching a pattern, or with Alt-V those that don't,
+    /// filtering as the pattern is typed. Run again, it shows every row.
+    fn filter_lines(&mut self) {
+        if self.filter.take().is_some() {
+            self.status_message = StatusMessage::from("Showing all lines".to_owned());
+            self.offset = Position::default();
+            return self.scroll();
+        }
+
+        let mut options = self.search_options;
+        let mut inverted = false;
+        let preview = |editor: &mut Self, key, query: &str| {
             match key {
-                Key::Right | Key::Down => {
-                    direction = SearchDirection::Forward;
-                    editor.move_cursor(Key::Right);
-                    moved = true;
+                Key::Alt('v') => inverted = !inverted,
+                Key::Alt(ch) => options.toggle(ch),
+                _ => (),
+            }
+            let mut hint = search_hint(options);
+            if inverted {
+                hint = format!("{hint} [invert]");
+            }
+
+            editor.filter = None;
+            editor.prompt_failed = false;
+            match Matcher::new(query, options) {
+                Ok(_) if query.is_empty() => (),
+                Ok(matcher) => {
+                    let filter = Filter::new(&editor.document, matcher, inverted);
+                    if filter.is_empty() {
+                        editor.prompt_failed = true;
+                    } else {
+                        editor.filter = Some(filter);
+                    }
                 }
-                Key::Left | Key::Up => {
-                    direction = SearchDirection::Backward;
+                Err(error) => {
+                    hint = format!("{hint} [{error}]");
+                    editor.prompt_failed = true;
                 }
-                _ => direction = SearchDirection::Forward,
             }
+            editor.prompt_hint = hint;
+            editor.offset = Position::default();
+            editor.scroll();
+        };
 
-            if let Some(position) =
+        self.prompt_hint = search_hint(self.search_options);
+        let query = self
+            .prompt(
+                PromptKind::Search,
+                "Filter (ESC to cancel, Alt-R/C/S/W/V): ",
+                None,
+                preview,
+            )
+            .unwrap_or(None);
+        self.search_options = options;
+
+        if query.is_none() || self.filter.is_none() {
+            if query.is_some() {
+                self.macro_failed = true;
+                self.status_message = StatusMessage::from("No matching lines".to_owned());
+            }
+            self.filter = None;
+        }
+        self.offset = Position::default();
+        self.after_keypress();
+    }
+
+    /// Folds the innermost region around the cursor, or the one around that
+    /// if it is folded already.
+    fn fold(&mut self) {
+        if self.document.fold(self.cursor_position.y) {
+            self.leave_folds();
+        } else {
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from("Nothing to fold here".to_owned());
+        }
+    }
+
+    fn unfold(&mut self) {
+        if !self.document.unfold(self.cursor_position.y) {
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from("No fold here".to_owned());
+        }
+    }
+
+    /// Moves the cursor out of a fold that was just folded over it, to the
+    /// row shown in its place.
+    fn leave_folds(&mut self) {
+        if let Some(fold) = self.document.folds().hiding(self.cursor_position.y) {
+            let x = cmp::min(
+                self.cursor_position.x,
+                self.document.row_len(fold.start).unwrap_or(0),
+            );
+            self.cursor_position = Position { x, y: fold.start };
+        }
+    }
+
+    /// Searches the files under the working directory, listing the hits in a
+    /// read-only results buffer as they are found until Esc cancels.
+    fn find_in_files(&mut self) {
+        let Some(matcher) = self.query_prompt("Find in files (ESC to cancel, Alt-R/C/S/W): ")
+        else {
+            return;
+        };
+
+        self.show_results();
+        let progress = self.run_project_search(matcher, |editor, hit| {
+            editor
+                .document
+                .push_result(&hit.location(), &hit.text, &hit.columns);
+            editor.results.push((hit.path, hit.position));
+        });
+
+        let summary = matches_message(progress.hits, progress.files);
+        if let Some(error) = progress.error.as_ref() {
+            self.macro_failed = true;
+            self.status_message =
+                StatusMessage::from(format!("ERR: Search stopped: {error} ({summary})"));
+        } else if progress.hits == 0 && !progress.cancelled {
+            self.switch_buffer();
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from("No matches found".to_owned());
+        } else if progress.cancelled {
+            self.status_message = StatusMessage::from(format!("Search cancelled: {summary}"));
+        } else {
+            self.status_message = StatusMessage::from(summary);
+        }
+    }
+
+    /// Replaces matches in the files under the working directory. Every
+    /// replacement is previewed, grouped by file, and can be toggled off
+    /// before the selected ones are applied and each file is saved.
+    fn replace_in_files(&mut self) {
+        let Some((matcher, replacement)) =
+            self.replace_prompts("Replace in files (ESC to cancel, Alt-R/C/S/W): ")
+        else {
+            return;
+        };
+
+        self.show_results();
+        let mut changes: Vec<Change> = Vec::new();
+        let progress = self.run_project_search(matcher.clone(), |editor, hit| {
+            if changes.last().map(|change| &change.hit.path) != Some(&hit.path) {
+                editor.document.push_result("", &hit.path, &(0..0));
                 editor
-                    .document
-                    .find(&query[..], &editor.cursor_position, direction)
-            {
-                editor.cursor_position = position;
+                    .results
+                    .push((hit.path.clone(), hit.position.clone()));
+            }
+            let change = Change {
+                replacement: matcher.replacement(&hit.text, &hit.bytes, &replacement),
+                row: editor.document.len(),
+                selected: true,
+                hit,
+            };
+            for (location, text, columns) in change.preview() {
+                editor.document.push_result(&location, &text, &columns);
+                editor
+                    .results
+                    .push((change.hit.path.clone(), change.hit.position.clone()));
+            }
+            changes.push(change);
+        });
+
+        if changes.is_empty() || progress.cancelled || progress.error.is_some() {
+            self.switch_buffer();
+            self.macro_failed = changes.is_empty() || progress.error.is_some();
+            self.status_message = StatusMessage::from(if let Some(error) = progress.error {
+                format!("ERR: Search stopped: {error}")
+            } else if progress.cancelled {
+                "Replace cancelled".to_owned()
+            } else {
+                "No matches found".to_owned()
+            });
+            return;
+        }
+
+        self.cursor_position = Position {
+            x: 0,
+            y: changes.first().map_or(0, |change| change.row),
+        };
+        self.scroll();
+        if self.review_changes(&mut changes) {
+            self.apply_changes(&matcher, &replacement, &changes);
+        } else {
+            self.switch_buffer();
+            self.status_message = StatusMessage::from("Replace cancelled".to_owned());
+        }
+    }
+
+    /// Lets the changes previewed in the results buffer be toggled with Space,
+    /// or all at once with `a`, and returns whether Enter accepted them.
+    fn review_changes(&mut self, changes: &mut [Change]) -> bool {
+        loop {
+            let selected = changes.iter().filter(|change| change.selected).count();
+            self.status_message = StatusMessage::from(format!(
+                "{} of {} selected. Space toggles, A all, Enter applies, Esc cancels",
+                selected,
+                changes.len()
+            ));
+            if !self.playing && self.refresh_screen().is_err() {
+                return false;
+            }
+            let Ok(key) = self.read_key() else {
+                return false;
+            };
+
+            match (key, self.keymap.lookup(&[key])) {
+                (Key::Char('\n'), _) | (_, Binding::Command(Command::Save)) => return true,
+                (Key::Char('q'), _) => return false,
+                (key, _) if self.is_cancel(key) => return false,
+                (Key::Char(' '), _) => {
+                    let y = self.cursor_position.y;
+                    let current = changes
+                        .iter()
+                        .position(|change| y == change.row || y == change.row.saturating_add(1));
+                    if let Some(index) = current {
+                        if let Some(change) = changes.get_mut(index) {
+                            change.selected = !change.selected;
+                            self.show_change(change);
+                        }
+                        if let Some(next) = changes.get(index.saturating_add(1)) {
+                            self.cursor_position.y = next.row;
+                        }
+                    }
+                }
+                (Key::Char('a' | 'A'), _) => {
+                    let select = changes.iter().any(|change| !change.selected);
+                    for change in changes.iter_mut() {
+                        change.selected = select;
+                        self.show_change(change);
+                    }
+                }
+                (Key::Char('j'), _) => self.move_cursor(Key::Down),
+                (Key::Char('k'), _) => self.move_cursor(Key::Up),
+                (_, Binding::Command(command))
+                    if command.is_motion()
+                        || command == Command::MoveLineStart
+                        || command == Command::MoveLineEnd =>
+                {
+                    self.execute(command);
+                }
+                _ => (),
+            }
+            self.scroll();
+        }
+    }
+
+    fn show_change(&mut self, change: &Change) {
+        for (row, (location, text, columns)) in (change.row..).zip(change.preview()) {
+            self.document.set_result(row, &location, &text, &columns);
+        }
+    }
+
+    /// Applies the selected changes file by file and replaces the preview with
+    /// a summary of the changed files.
+    fn apply_changes(&mut self, matcher: &Matcher, replacement: &str, changes: &[Change]) {
+        let mut summary = Document::results();
+        let mut results = Vec::new();
+        let mut replaced: usize = 0;
+        let mut files: usize = 0;
+
+        for group in changes.chunk_by(|left, right| left.hit.path == right.hit.path) {
+            let selected: Vec<&Change> = group.iter().filter(|change| change.selected).collect();
+            let first = match selected.first() {
+                Some(first) => &first.hit,
+                None => continue,
+            };
+
+            let outcome = match self.apply_file_changes(matcher, replacement, &selected) {
+                Ok((count, saved)) => {
+                    replaced = replaced.saturating_add(count);
+                    if count > 0 {
+                        files = files.saturating_add(1);
+                    }
+                    let skipped = selected.len().saturating_sub(count);
+                    let mut outcome = replaced_message(count);
+                    if skipped > 0 {
+                        outcome = format!("{outcome}, skipped {skipped} (file changed)");
+                    }
+                    if !saved {
+                        outcome.push_str(", not saved: the open buffer has unsaved changes");
+                    }
+                    outcome
+                }
+                Err(error) => format!("ERR: {error}"),
+            };
+            summary.push_result(&first.location(), &outcome, &(0..0));
+            results.push((first.path.clone(), first.position.clone()));
+        }
+
+        self.replace_document(summary, Position::default());
+        self.results = results;
+        self.status_message = StatusMessage::from(format!(
+            "{} in {} {}",
+            replaced_message(replaced),
+            files,
+            if files == 1 { "file" } else { "files" }
+        ));
+    }
+
+    /// Applies `changes`, all to the same file, through its `Document` and
+    /// saves it. A file open in the other buffer is changed there, and only
+    /// saved if it had no unsaved changes. Returns how many changes applied,
+    /// as a file changed since the search may not match any more, and whether
+    /// the file was saved.
+    fn apply_file_changes(
+        &mut self,
+        matcher: &Matcher,
+        replacement: &str,
+        changes: &[&Change],
+    ) -> Result<(usize, bool), String> {
+        let path = changes
+            .first()
+            .map_or("", |change| change.hit.path.as_str());
+        let open = self
+            .alternate
+            .as_mut()
+            .map(|alternate| &mut alternate.0)
+            .filter(|document| {
+                document
+                    .file_name
+                    .as_deref()
+                    .is_some_and(|name| is_same_file(name, path))
+            });
+
+        let mut opened;
+        let (document, save) = if let Some(document) = open {
+            let save = !document.is_dirty();
+            (document, save)
+        } else {
+            opened = Document::open(path, &self.file_types)
+                .map_err(|error| format!("Could not open {path}: {error}"))?;
+            (&mut opened, true)
+        };
+
+        document.begin_undo_group();
+        let count = changes
+            .iter()
+            .rev()
+            .filter(|change| {
+                document
+                    .replace_match(matcher, &change.hit.position, replacement)
+                    .is_some()
+            })
+            .count();
+        document.end_undo_group();
+
+        if save && count > 0 {
+            document
+                .save()
+                .map_err(|error| format!("Could not save {path}: {error}"))?;
+        }
+        Ok((count, save))
+    }
+
+    /// Shows an empty results buffer, keeping the document as the other
+    /// buffer unless it is an earlier results buffer.
+    fn show_results(&mut self) {
+        if self.document.is_read_only() {
+            self.replace_document(Document::results(), Position::default());
+        } else {
+            let previous = self.replace_document(Document::results(), Position::default());
+            self.alternate = Some(previous);
+        }
+        self.results.clear();
+    }
+
+    /// Runs a project search for `matcher`, handing each hit to `add` as it
+    /// arrives, until it is done or Esc cancels it. Other keys typed in the
+    /// meantime are kept for after the search.
+    fn run_project_search<F>(&mut self, matcher: Matcher, mut add: F) -> SearchProgress
+    where
+        F: FnMut(&mut Self, Hit),
+    {
+        let search = ProjectSearch::start(Path::new("."), matcher);
+        let mut progress = SearchProgress::default();
+        let mut last_path: Option<String> = None;
+
+        'search: loop {
+            for _ in 0..RESULTS_PER_REFRESH {
+                match search.poll() {
+                    Some(Update::Hit(hit)) => {
+                        if last_path.as_ref() != Some(&hit.path) {
+                            progress.files = progress.files.saturating_add(1);
+                            last_path = Some(hit.path.clone());
+                        }
+                        progress.hits = progress.hits.saturating_add(1);
+                        add(self, hit);
+                    }
+                    Some(Update::Done) => break 'search,
+                    None => break,
+                }
+            }
+
+            self.status_message = StatusMessage::from(format!(
+                "Searching... {} (ESC to cancel)",
+                matches_message(progress.hits, progress.files)
+            ));
+            if self.playing {
+                // The keys of a macro are meant for after the search.
+                thread::sleep(SEARCH_POLL_INTERVAL);
+                continue;
+            }
+            let key = self
+                .refresh_screen()
+                .and_then(|()| self.terminal.poll_key(SEARCH_POLL_INTERVAL));
+            match key {
+                Ok(Some(key)) if self.is_cancel(key) => {
+                    progress.cancelled = true;
+                    break;
+                }
+                Ok(Some(key)) => self.typeahead.push_back(key),
+                Ok(None) => (),
+                Err(error) => {
+                    progress.error = Some(error.to_string());
+                    break;
+                }
+            }
+        }
+        search.cancel();
+        progress
+    }
+
+    fn is_cancel(&self, key: Key) -> bool {
+        key == Key::Esc
+            || matches!(
+                self.keymap.lookup(&[key]),
+                Binding::Command(Command::Cancel)
+            )
+    }
+
+    /// Opens the file of the search result under the cursor, at its position.
+    /// The results become the other buffer, so `switch-buffer` goes back.
+    fn open_result(&mut self) {
+        let (path, position) = match self.results.get(self.cursor_position.y) {
+            Some(result) => result.clone(),
+            None => return,
+        };
+
+        let same_file = self.alternate.as_ref().is_some_and(|alternate| {
+            alternate
+                .0
+                .file_name
+                .as_deref()
+                .is_some_and(|name| is_same_file(name, &path))
+        });
+        let document = match self.alternate.take() {
+            Some((document, _)) if same_file => document,
+            alternate => {
+                if let Some(dirty) = alternate.as_ref().filter(|dirty| dirty.0.is_dirty()) {
+                    let name = dirty.0.file_name.as_deref().unwrap_or("[No Name]");
+                    self.status_message = StatusMessage::from(format!(
+                        "ERR: {name} has unsaved changes; switch to it and save it first"
+                    ));
+                    self.alternate = alternate;
+                    return;
+                }
+                match Document::open(&path, &self.file_types) {
+                    Ok(document) => document,
+                    Err(error) => {
+                        self.status_message =
+                            StatusMessage::from(format!("ERR: Could not open {path}: {error}"));
+                        self.alternate = alternate;
+                        return;
+                    }
+                }
+            }
+        };
+
+        let results = self.replace_document(document, position);
+        self.alternate = Some(results);
+        let y = cmp::min(
+            self.cursor_position.y,
+            self.document.len().saturating_sub(1),
+        );
+        let x = cmp::min(
+            self.cursor_position.x,
+            self.document.row_len(y).unwrap_or(0),
+        );
+        self.cursor_position = Position { x, y };
+        self.reveal_cursor();
+        self.after_keypress();
+    }
+
+    /// Swaps the document with the other buffer, if there is one.
+    fn switch_buffer(&mut self) {
+        if let Some((document, position)) = self.alternate.take() {
+            let previous = self.replace_document(document, position);
+            self.alternate = Some(previous);
+            self.after_keypress();
+        } else {
+            self.macro_failed = true;
+            self.status_message = StatusMessage::from("No other buffer".to_owned());
+        }
+    }
+
+    /// Shows `document` with the cursor at `position`, returning the document
+    /// it replaces and the cursor in it.
+    fn replace_document(&mut self, document: Document, position: Position) -> (Document, Position) {
+        // Each document keeps its own undo groups balanced.
+        self.document.end_undo_group();
+        let previous = mem::replace(&mut self.document, document);
+        let previous_position = mem::replace(&mut self.cursor_position, position);
+        self.document.begin_undo_group();
+        self.filter = None;
+
+        self.offset = Position::default();
+        self.scroll();
+        (previous, previous_position)
+    }
+
+    /// Shows `question` in the message bar and waits for a key.
+    fn ask(&mut self, question: &str) -> Result<Key, std::io::Error> {
+        self.status_message = StatusMessage::from(question.to_owned());
+        if !self.playing {
+            self.refresh_screen()?;
+        }
+        self.read_key()
+    }
+
+    /// Searches as the query is typed, with the search-next (or find) and
+    /// search-previous keys moving to the next or previous match and wrapping around the document. Matches are only
+    /// collected again when the query or the options change.
+    fn search(&mut self) {
+        let old_position = self.cursor_position.clone();
+        let mut options = self.search_options;
+        let mut searched: Option<(String, SearchOptions)> = None;
+        let mut starts: Vec<Position> = Vec::new();
+        let mut found = false;
+
+        let query_handler = |editor: &mut Self, key, query: &str| {
+            let mut direction = SearchDirection::Forward;
+            let mut skip_current = false;
+            match (key, editor.keymap.lookup(&[key])) {
+                (_, Binding::Command(Command::SearchNext | Command::Find)) => skip_current = true,
+                (_, Binding::Command(Command::SearchPrevious)) => {
+                    direction = SearchDirection::Backward;
+                }
+                (Key::Alt(ch), _) => options.toggle(ch),
+                _ => (),
+            }
+
+            if searched.as_ref() != Some(&(query.to_owned(), options)) {
+                searched = Some((query.to_owned(), options));
+                match Matcher::new(query, options) {
+                    Ok(matcher) => {
+                        starts = editor.document.match_starts(&matcher);
+                        editor.document.highlight(Some(&matcher));
+                    }
+                    Err(error) => {
+                        starts.clear();
+                        editor.document.highlight(None);
+                        editor.prompt_hint = format!(" [{error}]");
+                        editor.prompt_failed = true;
+                        found = false;
+                        return;
+                    }
+                }
+            }
+
+            let flags = search_hint(options);
+            if starts.is_empty() {
+                editor.prompt_hint = format!("{flags} no matches");
+                editor.prompt_failed = !query.is_empty();
+                found = false;
+                return;
+            }
+
+            let from = &editor.cursor_position;
+            let (index, wrapped) = if direction == SearchDirection::Forward {
+                let next = starts.partition_point(|start| {
+                    if skip_current {
+                        start <= from
+                    } else {
+                        start < from
+                    }
+                });
+                if next < starts.len() {
+                    (next, false)
+                } else {
+                    (0, true)
+                }
+            } else {
+                match starts.partition_point(|start| start < from) {
+                    0 => (starts.len().saturating_sub(1), true),
+                    next => (next.saturating_sub(1), false),
+                }
+            };
+
+            if let Some(start) = starts.get(index) {
+                editor.cursor_position = start.clone();
+                editor.reveal_cursor();
                 editor.scroll();
-            } else if moved {
-                editor.move_cursor(Key::Left);
             }
-            editor.document.highlight(Some(query));
+            editor.prompt_hint = format!(
+                "{} match {} of {}{}",
+                flags,
+                index.saturating_add(1),
+                starts.len(),
+                if wrapped { ", search wrapped" } else { "" }
+            );
+            editor.prompt_failed = false;
+            found = true;
         };
 
+        self.prompt_hint = search_hint(self.search_options);
+        let label = search_label(&self.keymap);
         let query = self
-            .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
-                query_handler,
-            )
+            .prompt(PromptKind::Search, &label, None, query_handler)
             .unwrap_or(None);
+        self.search_options = options;
 
         if query.is_none() {
             self.cursor_position = old_position;
             self.scroll();
+        } else if !found {
+            self.macro_failed = true;
         }
         self.document.highlight(None);
     }
 }
+
+fn replaced_message(count: usize) -> String {
+    match count {
+        1 => "Replaced 1 occurrence".to_owned(),
+        _ => format!("Replaced {count} occurrences"),
+    }
+}
+
+/// Lists the completions offered by Tab when there is more than one.
+fn completion_hint(candidates: &[String]) -> String {
+    match candidates.len() {
+        0 => " [no completions]".to_owned(),
+        1 => String::new(),
+        _ => format!(" {{{}}}", candidates.join(", ")),
+    }
+}
+
+fn matches_message(matches: usize, files: usize) -> String {
+    format!(
+        "{} {} in {} {}",
+        matches,
+        if matches == 1 { "match" } else { "matches" },
+        files,
+        if files == 1 { "file" } else { "files" }
+    )
+}
+
+/// Whether two paths name the same file, however they are spelled.
+fn is_same_file(first: &str, second: &str) -> bool {
+    match (fs::canonicalize(first), fs::canonicalize(second)) {
+        (Ok(first_path), Ok(second_path)) => first_path == second_path,
+        _ => first == second,
+    }
+}
+
+/// The search prompt, naming the keys that move between matches.
+fn search_label(keymap: &Keymap) -> String {
+    let next = keymap
+        .describe(Command::SearchNext)
+        .or_else(|| keymap.describe(Command::Find));
+    let previous = keymap.describe(Command::SearchPrevious);
+    let moves = match (next, previous) {
+        (Some(next), Some(previous)) => format!(", {next}/{previous} next/previous"),
+        (Some(next), None) => format!(", {next} next"),
+        (None, Some(previous)) => format!(", {previous} previous"),
+        (None, None) => String::new(),
+    };
+    format!("Search (ESC to cancel{moves}, Alt-R/C/S/W): ")
+}
+
+fn search_hint(options: SearchOptions) -> String {
+    let names = options.describe();
+    if names.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_show_the_line_before_and_after() {
+        let change = Change {
+            hit: Hit {
+                path: String::from("src/main.rs"),
+                position: Position { x: 5, y: 9 },
+                bytes: 6..9,
+                columns: 5..8,
+                text: String::from("let \u{e9}old = 1;"),
+            },
+            replacement: String::from("new_name"),
+            selected: true,
+            row: 0,
+        };
+        let [before, after] = change.preview();
+        assert_eq!(before.0, "  [x] 10:6: ");
+        assert_eq!(before.1, "let \u{e9}old = 1;");
+        assert_eq!(before.2, 5..8);
+        assert_eq!(after.0, " ".repeat(before.0.len()));
+        assert_eq!(after.1, "let \u{e9}new_name = 1;");
+        assert_eq!(after.2, 5..13);
+    }
+
+    #[test]
+    fn search_labels_name_the_bound_keys() {
+        assert_eq!(
+            search_label(&Keymap::default()),
+            "Search (ESC to cancel, Ctrl-N/Ctrl-P next/previous, Alt-R/C/S/W): "
+        );
+        assert_eq!(
+            Keymap::preset("emacs").as_ref().map(search_label),
+            Some(String::from(
+                "Search (ESC to cancel, C-s/C-r next/previous, Alt-R/C/S/W): "
+            ))
+        );
+    }
+}
@@ -1,44 +1,739 @@
+use std::{fs, path::Path};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{config, Folding};
+
+/// The definitions shipped with hecto, in the format of the files in the
+/// `filetypes` directory of the config directory.
+const BUNDLED: &[(&str, &str)] = &[
+    ("rust.toml", include_str!("../filetypes/rust.toml")),
+    ("c.toml", include_str!("../filetypes/c.toml")),
+    ("python.toml", include_str!("../filetypes/python.toml")),
+    (
+        "javascript.toml",
+        include_str!("../filetypes/javascript.toml"),
+    ),
+    (
+        "typescript.toml",
+        include_str!("../filetypes/typescript.toml"),
+    ),
+    ("go.toml", include_str!("../filetypes/go.toml")),
+    ("markdown.toml", include_str!("../filetypes/markdown.toml")),
+    ("json.toml", include_str!("../filetypes/json.toml")),
+    ("toml.toml", include_str!("../filetypes/toml.toml")),
+    ("yaml.toml", include_str!("../filetypes/yaml.toml")),
+    ("shell.toml", include_str!("../filetypes/shell.toml")),
+    ("make.toml", include_str!("../filetypes/make.toml")),
+    (
+        "dockerfile.toml",
+        include_str!("../filetypes/dockerfile.toml"),
+    ),
+];
+
+const FILETYPES_DIR: &str = "filetypes";
+
+/// A language: which files it is used for, how they are highlighted and how
+/// they are indented. File types are defined in TOML files, see `BUNDLED`.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct FileType {
     name: String,
+    /// Extensions, without the dot, as in `rs`.
+    #[serde(default)]
+    extensions: Vec<String>,
+    /// Whole file names, as in `Cargo.lock`.
+    #[serde(default)]
+    filenames: Vec<String>,
+    /// Patterns matched against the file name and against the whole path, as
+    /// in `*.rs.in` or `**/.cargo/config`.
+    #[serde(default)]
+    globs: Vec<String>,
+    /// Other names for the file type in modelines and `set-filetype`, as
+    /// `py` for Python. The name itself is always accepted.
+    #[serde(default)]
+    aliases: Vec<String>,
+    /// Programs named by the shebang of scripts, as `python` in
+    /// `#!/usr/bin/env python3`. A version may follow the name.
+    #[serde(default)]
+    interpreters: Vec<String>,
+    /// Regular expressions matched against the first line of files that
+    /// nothing else told apart, as `^<\?xml`.
+    #[serde(default)]
+    content: Vec<String>,
+    #[serde(default, rename = "highlighting")]
     hl_opts: HighlightingOptions,
+    /// The tree-sitter grammar parsing the file type, as `rust`, when hecto
+    /// is built with one. `highlighting` is used otherwise.
+    grammar: Option<String>,
+    /// How the regions that can be folded are found.
+    #[serde(default)]
+    folding: Folding,
+    #[serde(default)]
+    indent: Indent,
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "each flag turns on one kind of highlighting"
+)]
 pub struct HighlightingOptions {
-    numbers: bool,
+    /// How numbers look, or `None` not to highlight them.
+    numbers: Option<NumberRules>,
+    #[serde(skip)]
+    locations: bool,
+    /// The tokens opening and closing string literals, as `"`.
+    strings: Vec<String>,
+    /// The same for string literals that may span lines, as `"""`.
+    multiline_strings: Vec<String>,
+    characters: bool,
+    lifetimes: bool,
+    line_comments: Vec<String>,
+    /// The tokens opening and closing block comments, as `["/*", "*/"]`.
+    block_comment: Option<(String, String)>,
+    nested_comments: bool,
+    doc_comments: Vec<String>,
+    primary_keywords: Vec<String>,
+    secondary_keywords: Vec<String>,
+    variables: bool,
+    headings: Vec<String>,
+    code_fences: Vec<String>,
+    keys: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NumberRules {
+    prefixes: bool,
+    separator: Option<char>,
+    suffixes: bool,
+}
+
+/// How a file type is indented: the Tab key inserts a tab, or spaces up to
+/// the next multiple of `width` when `tabs` is off.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Indent {
+    width: usize,
+    tabs: bool,
 }
 
 impl Default for FileType {
     fn default() -> Self {
         Self {
             name: String::from("No filetype"),
+            extensions: Vec::new(),
+            filenames: Vec::new(),
+            globs: Vec::new(),
+            aliases: Vec::new(),
+            interpreters: Vec::new(),
+            content: Vec::new(),
             hl_opts: HighlightingOptions::default(),
+            grammar: None,
+            folding: Folding::default(),
+            indent: Indent::default(),
+        }
+    }
+}
+
+impl Default for Indent {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            width: 4,
+            tabs: true,
         }
     }
 }
 
 impl FileType {
+    #[must_use]
     pub fn name(&self) -> String {
         self.name.clone()
     }
 
-    pub fn highlighting_options(&self) -> HighlightingOptions {
-        self.hl_opts
+    #[must_use]
+    #[inline]
+    pub fn highlighting_options(&self) -> &HighlightingOptions {
+        &self.hl_opts
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn indent(&self) -> Indent {
+        self.indent
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn grammar(&self) -> Option<&str> {
+        self.grammar.as_deref()
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn folding(&self) -> Folding {
+        self.folding
+    }
+
+    /// The file type of a project search results buffer, whose lines start
+    /// with a `path:line:col:` location.
+    #[must_use]
+    #[inline]
+    pub fn results() -> Self {
+        Self {
+            name: String::from("Search results"),
+            hl_opts: HighlightingOptions {
+                locations: true,
+                ..HighlightingOptions::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Whether `name` is the file type's name or one of its aliases,
+    /// ignoring case.
+    fn is_called(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether `program`, from a shebang, runs this file type's scripts.
+    fn is_interpreter(&self, program: &str) -> bool {
+        self.interpreters.iter().any(|interpreter| {
+            program
+                .strip_prefix(interpreter.as_str())
+                .is_some_and(|version| version.chars().all(|ch| ch.is_ascii_digit() || ch == '.'))
+        })
+    }
+}
+
+/// A file type with its globs and content patterns compiled.
+struct Definition {
+    file_type: FileType,
+    globs: GlobSet,
+    content: Vec<Regex>,
+}
+
+/// The file types known to the editor: the bundled ones, and those defined
+/// in the `filetypes` directory of the config directory, one per `.toml` file.
+/// A file type defined there replaces the bundled one of the same name, and
+/// is looked at before the bundled ones.
+pub struct FileTypes {
+    definitions: Vec<Definition>,
+    warnings: Vec<String>,
+}
+
+impl Default for FileTypes {
+    #[inline]
+    fn default() -> Self {
+        let mut file_types = Self {
+            definitions: Vec::new(),
+            warnings: Vec::new(),
+        };
+        for &(name, contents) in BUNDLED {
+            file_types.add(name, contents);
+        }
+        file_types
     }
+}
 
-    pub fn from(file_name: &str) -> Self {
-        if file_name.ends_with(".rs") {
-            return Self {
-                name: String::from("Rust"),
-                hl_opts: HighlightingOptions { numbers: true },
-            };
+impl FileTypes {
+    /// Loads the bundled file types and the user's. Files that cannot be read
+    /// or parsed are skipped and reported by `warnings`.
+    #[must_use]
+    #[inline]
+    pub fn load() -> Self {
+        let mut file_types = Self::default();
+        let dir = match config::config_dir() {
+            Some(dir) => dir.join(FILETYPES_DIR),
+            None => return file_types,
+        };
+        let mut paths: Vec<_> = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "toml")
+                })
+                .collect(),
+            Err(_) => return file_types,
+        };
+        // Each file is put first, so add them backwards to keep them in order.
+        paths.sort();
+        for path in paths.iter().rev() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            match fs::read_to_string(path) {
+                Ok(contents) => file_types.add(&format!("{FILETYPES_DIR}/{name}"), &contents),
+                Err(error) => {
+                    file_types.warnings.push(format!(
+                        "Could not read {}: {}",
+                        path.display(),
+                        error
+                    ));
+                }
+            }
         }
-        Self::default()
+        file_types
+    }
+
+    /// Problems found in the user's file types.
+    #[must_use]
+    #[inline]
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// The file type of the file at `path`, whose first and last few lines
+    /// are `lines`, or the default one. A modeline in `lines`, as `vim: ft=sh`
+    /// or `-*- mode: python -*-`, comes first; then the file name, exactly
+    /// and by extension or glob; then the shebang, and the first line.
+    #[must_use]
+    #[inline]
+    pub fn detect(&self, path: &str, lines: &[&str]) -> FileType {
+        let path = Path::new(path);
+        let name = path.file_name().map_or(path, Path::new);
+        let file_name = name.to_str().unwrap_or_default();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let first_line = lines.first().copied().unwrap_or_default();
+        let program = shebang_program(first_line);
+
+        let found = lines
+            .iter()
+            .find_map(|line| modeline_file_type(line))
+            .and_then(|modeline| self.find(modeline))
+            .or_else(|| {
+                self.find_by(|definition| {
+                    definition
+                        .file_type
+                        .filenames
+                        .iter()
+                        .any(|candidate| candidate == file_name)
+                })
+            })
+            .or_else(|| {
+                self.find_by(|definition| {
+                    extension.is_some_and(|extension| {
+                        definition
+                            .file_type
+                            .extensions
+                            .iter()
+                            .any(|candidate| candidate == extension)
+                    }) || definition.globs.is_match(path)
+                        || definition.globs.is_match(name)
+                })
+            })
+            .or_else(|| {
+                self.find_by(|definition| {
+                    program.is_some_and(|program| definition.file_type.is_interpreter(program))
+                })
+            })
+            .or_else(|| {
+                self.find_by(|definition| {
+                    definition
+                        .content
+                        .iter()
+                        .any(|pattern| pattern.is_match(first_line))
+                })
+            });
+        found.unwrap_or_default()
+    }
+
+    /// The file type called `name`, or aliased so, ignoring case.
+    #[must_use]
+    #[inline]
+    pub fn find(&self, name: &str) -> Option<FileType> {
+        self.find_by(|definition| definition.file_type.is_called(name))
+    }
+
+    /// The names of the file types, sorted.
+    #[must_use]
+    #[inline]
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .definitions
+            .iter()
+            .map(|definition| definition.file_type.name())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn find_by<P>(&self, predicate: P) -> Option<FileType>
+    where
+        P: Fn(&Definition) -> bool,
+    {
+        self.definitions
+            .iter()
+            .find(|definition| predicate(definition))
+            .map(|definition| definition.file_type.clone())
+    }
+
+    /// Parses the definition in `contents`, read from `source`, and puts it
+    /// first, replacing any of the same name.
+    fn add(&mut self, source: &str, contents: &str) {
+        let file_type: FileType = match toml::from_str(contents) {
+            Ok(file_type) => file_type,
+            Err(error) => {
+                self.warnings.push(format!("{source}: {error}"));
+                return;
+            }
+        };
+        let globs = match compile_globs(&file_type.globs) {
+            Ok(globs) => globs,
+            Err(error) => {
+                self.warnings.push(format!("{source}: {error}"));
+                return;
+            }
+        };
+        let content = match file_type
+            .content
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(content) => content,
+            Err(error) => {
+                self.warnings.push(format!("{source}: {error}"));
+                return;
+            }
+        };
+        self.definitions
+            .retain(|definition| definition.file_type.name != file_type.name);
+        self.definitions.insert(
+            0,
+            Definition {
+                file_type,
+                globs,
+                content,
+            },
+        );
+    }
+}
+
+/// The program a shebang line runs, without its directory: `python3` for
+/// `#!/usr/bin/python3` and for `#!/usr/bin/env -S python3 -u`.
+fn shebang_program(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program != "env" {
+        return Some(program);
+    }
+    // Skip the options and variables given to env.
+    words.find(|word| !word.starts_with('-') && !word.contains('='))
+}
+
+/// The file type a Vim (`vim: set ft=sh:`) or Emacs (`-*- mode: sh -*-`)
+/// modeline in `line` asks for.
+fn modeline_file_type(line: &str) -> Option<&str> {
+    if let Some(start) = line.find("-*-") {
+        let rest = line.get(start.saturating_add(3)..).unwrap_or_default();
+        let variables = rest.get(..rest.find("-*-")?).unwrap_or_default();
+        let mode = if variables.contains(':') {
+            variables.split(';').find_map(|variable| {
+                let (name, value) = variable.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("mode")
+                    .then(|| value.trim())
+            })?
+        } else {
+            variables.trim()
+        };
+        return Some(mode).filter(|mode| !mode.is_empty());
+    }
+
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|&(index, _)| {
+                line.get(..index).is_some_and(|before| {
+                    before.is_empty() || before.ends_with(char::is_whitespace)
+                })
+            })
+            .map(|(index, _)| index.saturating_add(marker.len()))
+    })?;
+    line.get(start..)
+        .unwrap_or_default()
+        .split(|ch: char| ch == ':' || ch.is_whitespace())
+        .find_map(|option| {
+            option
+                .strip_prefix("ft=")
+                .or_else(|| option.strip_prefix("filetype="))
+        })
+        .filter(|file_type| !file_type.is_empty())
+}
+
+fn compile_globs(globs: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
     }
+    builder.build()
 }
 
 impl HighlightingOptions {
-    pub fn numbers(self) -> bool {
-        self.numbers
+    #[must_use]
+    #[inline]
+    pub fn numbers(&self) -> Option<&NumberRules> {
+        self.numbers.as_ref()
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn locations(&self) -> bool {
+        self.locations
+    }
+
+    /// The tokens that open a string literal, each closed by itself.
+    #[must_use]
+    #[inline]
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+
+    /// The tokens that open a string literal that may span lines, each
+    /// closed by itself. They are looked for before `strings`.
+    #[must_use]
+    #[inline]
+    pub fn multiline_strings(&self) -> &[String] {
+        &self.multiline_strings
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn characters(&self) -> bool {
+        self.characters
+    }
+
+    /// Whether a quote followed by a word, as in `'a`, is a lifetime rather
+    /// than an unclosed character literal.
+    #[must_use]
+    #[inline]
+    pub fn lifetimes(&self) -> bool {
+        self.lifetimes
+    }
+
+    /// The tokens that start a comment running to the end of the line.
+    #[must_use]
+    #[inline]
+    pub fn line_comments(&self) -> &[String] {
+        &self.line_comments
+    }
+
+    /// The tokens that open and close a comment, which may span lines.
+    #[must_use]
+    #[inline]
+    pub fn block_comment(&self) -> Option<(&str, &str)> {
+        self.block_comment
+            .as_ref()
+            .map(|comment| (comment.0.as_str(), comment.1.as_str()))
+    }
+
+    /// Whether a block comment opened inside another one needs its own
+    /// closing token.
+    #[must_use]
+    #[inline]
+    pub fn nested_comments(&self) -> bool {
+        self.nested_comments
+    }
+
+    /// The tokens that start doc comments, as `///` or `/**`. A token
+    /// followed by its last character again, as in `////`, starts a plain
+    /// comment.
+    #[must_use]
+    #[inline]
+    pub fn doc_comments(&self) -> &[String] {
+        &self.doc_comments
+    }
+
+    /// Keywords of the language, such as `fn` or `let`.
+    #[must_use]
+    #[inline]
+    pub fn primary_keywords(&self) -> &[String] {
+        &self.primary_keywords
+    }
+
+    /// Words colored apart from the keywords, such as built-in types.
+    #[must_use]
+    #[inline]
+    pub fn secondary_keywords(&self) -> &[String] {
+        &self.secondary_keywords
+    }
+
+    /// Whether `$NAME`, `${NAME}` and `$1` are variables, also inside strings
+    /// opened by `"`, as in shell scripts.
+    #[must_use]
+    #[inline]
+    pub fn variables(&self) -> bool {
+        self.variables
+    }
+
+    /// The tokens that make a line a heading when it starts with one, as `#`
+    /// in Markdown or `[` in TOML.
+    #[must_use]
+    #[inline]
+    pub fn headings(&self) -> &[String] {
+        &self.headings
+    }
+
+    /// The tokens that open and close a fenced code block when a line starts
+    /// with one, as `~~~` in Markdown.
+    #[must_use]
+    #[inline]
+    pub fn code_fences(&self) -> &[String] {
+        &self.code_fences
+    }
+
+    /// The token that follows a key, as `:` in JSON or `=` in TOML. A string
+    /// followed by it is a key, as is a word starting a line.
+    #[must_use]
+    #[inline]
+    pub fn keys(&self) -> Option<&str> {
+        self.keys.as_deref()
+    }
+}
+
+impl NumberRules {
+    /// Whether `0x`, `0o` and `0b` start hexadecimal, octal and binary
+    /// numbers.
+    #[must_use]
+    pub fn prefixes(&self) -> bool {
+        self.prefixes
+    }
+
+    /// A character allowed between digits, as `_` in `1_000`.
+    #[must_use]
+    pub fn separator(&self) -> Option<char> {
+        self.separator
+    }
+
+    /// Whether letters may follow the digits, as in `10u8` or `1.5f32`.
+    #[must_use]
+    pub fn suffixes(&self) -> bool {
+        self.suffixes
+    }
+}
+
+impl Indent {
+    #[must_use]
+    #[inline]
+    pub fn width(self) -> usize {
+        self.width
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn tabs(self) -> bool {
+        self.tabs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(file_types: &FileTypes, path: &str) -> String {
+        file_types.detect(path, &[]).name()
+    }
+
+    #[test]
+    fn file_types_are_detected_by_name() {
+        let mut file_types = FileTypes::default();
+        file_types.add(
+            "filetypes/cargo.toml",
+            "name = \"Cargo config\"\nfilenames = [\"Cargo.toml\"]\nglobs = [\"**/.cargo/config\"]",
+        );
+        assert_eq!(detected(&file_types, "src/main.rs"), "Rust");
+        assert_eq!(detected(&file_types, "Cargo.toml"), "Cargo config");
+        assert_eq!(
+            detected(&file_types, "/home/me/.cargo/config"),
+            "Cargo config"
+        );
+        assert_eq!(detected(&file_types, "readme"), "No filetype");
+    }
+
+    #[test]
+    fn user_file_types_replace_bundled_ones() {
+        let mut file_types = FileTypes::default();
+        file_types.add(
+            "filetypes/rust.toml",
+            "name = \"Rust\"\nextensions = [\"rs2\"]",
+        );
+        file_types.add("filetypes/bad.toml", "name = \"Bad\"\ncolour = true");
+        assert_eq!(detected(&file_types, "main.rs"), "No filetype");
+        assert_eq!(detected(&file_types, "main.rs2"), "Rust");
+        assert_eq!(file_types.warnings().len(), 1);
+    }
+
+    #[test]
+    fn bundled_file_types_load() {
+        let file_types = FileTypes::default();
+        assert!(file_types.warnings().is_empty());
+        for &(path, name) in &[
+            ("a.c", "C"),
+            ("a.go", "Go"),
+            ("a.mjs", "JavaScript"),
+            ("a.json", "JSON"),
+            ("a.md", "Markdown"),
+            ("a.py", "Python"),
+            ("a.bash", "Shell"),
+            ("a.toml", "TOML"),
+            ("a.tsx", "TypeScript"),
+            ("a.yml", "YAML"),
+        ] {
+            assert_eq!(detected(&file_types, path), name);
+        }
+    }
+
+    #[test]
+    fn scripts_are_detected_by_their_shebang() {
+        let file_types = FileTypes::default();
+        let detect = |lines: &[&str]| file_types.detect("script", lines).name();
+        assert_eq!(detect(&["#!/usr/bin/env python3"]), "Python");
+        assert_eq!(detect(&["#!/bin/sh"]), "Shell");
+        assert_eq!(detect(&["#!/bin/sh", "# vim: ft=python"]), "Python");
+        assert_eq!(detect(&["#!/usr/bin/env pythonista"]), "No filetype");
+    }
+
+    #[test]
+    fn emacs_modelines_name_a_mode() {
+        assert_eq!(modeline_file_type("# -*- mode: sh -*-"), Some("sh"));
+        assert_eq!(
+            modeline_file_type("// -*- mode: c++; tab-width: 4 -*-"),
+            Some("c++")
+        );
+        assert_eq!(modeline_file_type("# -*- python -*-"), Some("python"));
+        assert_eq!(modeline_file_type("# -*- coding: utf-8 -*-"), None);
+        assert_eq!(modeline_file_type("# -*- mode: sh"), None);
+    }
+
+    #[test]
+    fn vim_modelines_set_a_file_type() {
+        assert_eq!(modeline_file_type("# vim: set ft=sh:"), Some("sh"));
+        assert_eq!(modeline_file_type("/* vi:filetype=c */"), Some("c"));
+        assert_eq!(modeline_file_type("# vim: ts=4 sw=4"), None);
+        assert_eq!(modeline_file_type("avim:ft=sh"), None);
+    }
+
+    #[test]
+    fn shebangs_name_the_program_run() {
+        assert_eq!(shebang_program("#!/bin/sh"), Some("sh"));
+        assert_eq!(shebang_program("#!/usr/bin/env python3"), Some("python3"));
+        assert_eq!(
+            shebang_program("#!/usr/bin/env -S python3 -u"),
+            Some("python3")
+        );
+        assert_eq!(
+            shebang_program("#!/usr/bin/env NODE_ENV=test node"),
+            Some("node")
+        );
+        assert_eq!(shebang_program("# not a shebang"), None);
     }
 }
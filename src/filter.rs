@@ -0,0 +1,228 @@
+use core::ops::Range;
+
+use crate::{Document, Matcher};
+
+/// Hides the rows of a document that don't match a pattern, or those that do
+/// when inverted, as Emacs' `occur` lists them. The editor draws and moves
+/// through `rows`, the indices of the rows still shown, while edits go to the
+/// document as usual.
+pub struct Filter {
+    matcher: Matcher,
+    inverted: bool,
+    rows: Vec<usize>,
+    /// The number of rows in the document, standing for the line past its end.
+    end: usize,
+    /// The document's edit count when the rows were last matched.
+    edits: usize,
+    /// A row shown only because it was being edited, matched again once the
+    /// cursor leaves it.
+    kept: Option<usize>,
+    /// The last row the cursor was on, to tell which way it was moving.
+    last_row: usize,
+}
+
+impl Filter {
+    #[must_use]
+    pub fn new(document: &Document, matcher: Matcher, inverted: bool) -> Self {
+        let mut filter = Self {
+            matcher,
+            inverted,
+            rows: Vec::new(),
+            end: 0,
+            edits: document.edit_count(),
+            kept: None,
+            last_row: 0,
+        };
+        filter.rebuild(document, None);
+        filter
+    }
+
+    /// Matches the rows the document's edits changed again, and shifts the
+    /// rows below them. Row `keep`, the one being edited, stays shown even if
+    /// it doesn't match any more.
+    pub fn update(&mut self, document: &Document, keep: usize) {
+        if document.edit_count() == self.edits {
+            return;
+        }
+        let Some(edits) = document.edits_since(self.edits) else {
+            return self.rebuild(document, Some(keep));
+        };
+        self.edits = document.edit_count();
+
+        // The rows to match again, where they are after the edits so far.
+        let mut changed: Option<Range<usize>> =
+            self.kept.take().map(|row| row..row.saturating_add(1));
+        for edit in edits {
+            let old_end = edit.row.saturating_add(edit.removed);
+            let new_end = edit.row.saturating_add(edit.inserted);
+            let moved = |row: usize| {
+                if row >= old_end {
+                    row.saturating_sub(old_end).saturating_add(new_end)
+                } else {
+                    row.min(new_end)
+                }
+            };
+
+            self.rows.retain(|row| *row < edit.row || *row >= old_end);
+            for row in &mut self.rows {
+                *row = moved(*row);
+            }
+            changed = Some(match changed {
+                Some(rows) => moved(rows.start).min(edit.row)..moved(rows.end).max(new_end),
+                None => edit.row..new_end,
+            });
+        }
+
+        if let Some(changed) = changed {
+            let changed = changed.start..changed.end.min(document.len());
+            self.rows.retain(|row| !changed.contains(row));
+            let matched: Vec<usize> = changed
+                .filter(|index| self.shows(document, *index, Some(keep)))
+                .collect();
+            let at = self.index_of(matched.first().copied().unwrap_or(0));
+            self.rows.splice(at..at, matched);
+        }
+        self.end = document.len();
+    }
+
+    fn rebuild(&mut self, document: &Document, keep: Option<usize>) {
+        self.end = document.len();
+        self.edits = document.edit_count();
+        self.kept = None;
+        self.rows = (0..document.len())
+            .filter(|index| self.shows(document, *index, keep))
+            .collect();
+    }
+
+    /// Whether row `index` of `document` is shown, as it matches or as the
+    /// row `keep` being edited.
+    fn shows(&mut self, document: &Document, index: usize, keep: Option<usize>) -> bool {
+        let matches = document
+            .row(index)
+            .and_then(|row| self.matcher.find_at(row.as_str(), 0))
+            .is_some();
+        if matches != self.inverted {
+            true
+        } else if Some(index) == keep {
+            self.kept = keep;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The number of rows shown.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The document row shown at `index`, or the line past the end of the
+    /// document after the last one.
+    #[must_use]
+    pub fn document_row(&self, index: usize) -> usize {
+        self.rows.get(index).copied().unwrap_or(self.end)
+    }
+
+    /// Where document row `row`, or the next row shown after it, is shown.
+    #[must_use]
+    pub fn index_of(&self, row: usize) -> usize {
+        self.rows.partition_point(|shown| *shown < row)
+    }
+
+    /// Whether document row `row` is shown.
+    #[must_use]
+    pub fn is_shown(&self, row: usize) -> bool {
+        self.rows.binary_search(&row).is_ok()
+    }
+
+    /// Moves a hidden `row` to the nearest row shown in the direction the
+    /// cursor was going.
+    pub fn snap(&mut self, row: usize) -> usize {
+        let index = self.index_of(row);
+        let snapped = if self.rows.get(index) == Some(&row) || row >= self.end {
+            row
+        } else if row < self.last_row && index > 0 {
+            self.document_row(index.saturating_sub(1))
+        } else if index < self.rows.len() {
+            self.document_row(index)
+        } else {
+            self.document_row(index.saturating_sub(1))
+        };
+        self.last_row = snapped;
+        snapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Position, SearchOptions};
+
+    fn document(text: &str) -> Document {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), text);
+        document
+    }
+
+    fn filter(document: &Document, query: &str, inverted: bool) -> Option<Filter> {
+        Matcher::new(query, SearchOptions::default())
+            .ok()
+            .map(|matcher| Filter::new(document, matcher, inverted))
+    }
+
+    fn shown(filter: &Filter) -> Vec<usize> {
+        (0..filter.len())
+            .map(|index| filter.document_row(index))
+            .collect()
+    }
+
+    #[test]
+    fn rows_that_match_are_shown() {
+        let document = document("fn a\nlet b\nfn c\n");
+        let matching = filter(&document, "fn", false);
+        assert_eq!(matching.as_ref().map(shown), Some(vec![0, 2]));
+        let inverted = filter(&document, "fn", true);
+        assert_eq!(inverted.as_ref().map(shown), Some(vec![1, 3]));
+        assert_eq!(matching.map(|filter| filter.document_row(2)), Some(4));
+    }
+
+    #[test]
+    fn inserted_rows_shift_the_rows_below() {
+        let mut document = document("fn a\nlet b\nfn c");
+        let mut filter = filter(&document, "fn", false);
+        document.insert_newline(&Position { x: 4, y: 0 });
+        document.insert_str(&Position { x: 0, y: 1 }, "fn new");
+        if let Some(filter) = filter.as_mut() {
+            filter.update(&document, 1);
+        }
+        assert_eq!(filter.as_ref().map(shown), Some(vec![0, 1, 3]));
+    }
+
+    #[test]
+    fn deleted_rows_are_dropped() {
+        let mut document = document("fn a\nlet b\nfn c\nfn d");
+        let mut filter = filter(&document, "fn", false);
+        document.delete_range(&Position { x: 0, y: 1 }, &Position { x: 0, y: 3 });
+        if let Some(filter) = filter.as_mut() {
+            filter.update(&document, 1);
+        }
+        assert_eq!(filter.as_ref().map(shown), Some(vec![0, 1]));
+    }
+
+    #[test]
+    fn the_row_being_edited_stays_shown() {
+        let mut document = document("fn a\nlet b\nfn c");
+        let mut filter = filter(&document, "fn", false);
+        document.delete(&Position { x: 0, y: 2 });
+        if let Some(filter) = filter.as_mut() {
+            filter.update(&document, 2);
+        }
+        assert_eq!(filter.as_ref().map(shown), Some(vec![0, 2]));
+    }
+}
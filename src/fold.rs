@@ -0,0 +1,426 @@
+use core::ops::Range;
+
+use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation as _;
+
+use crate::{highlighting, Row};
+
+/// How the regions of a file type that can be folded are found: by
+/// indentation, as for Python and YAML, or between matching brackets.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Folding {
+    #[default]
+    Indent,
+    Braces,
+}
+
+/// Rows that can be folded away: those after `start` up to `end`, while
+/// `start` stays shown in their place.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Region {
+    /// Whether `row` is in the region, its first row included.
+    #[must_use]
+    pub fn contains(self, row: usize) -> bool {
+        self.start <= row && row <= self.end
+    }
+
+    /// Whether the region hides `row` when it is folded.
+    #[must_use]
+    pub fn hides(self, row: usize) -> bool {
+        self.start < row && row <= self.end
+    }
+
+    /// The number of rows hidden when the region is folded.
+    #[must_use]
+    pub fn hidden(self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// The folded regions of a document. The editor draws and moves through the
+/// rows still shown, while edits shift the folds below them.
+#[derive(Default)]
+pub struct Folds {
+    /// Ordered by their first row.
+    closed: Vec<Region>,
+    /// The last row the cursor was on, to tell which way it was moving.
+    last_row: usize,
+}
+
+impl Folds {
+    #[must_use]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty()
+    }
+
+    /// Folds `region`, unless it is folded already.
+    #[inline]
+    pub fn close(&mut self, region: Region) {
+        if !self.closed.contains(&region) {
+            let index = self
+                .closed
+                .partition_point(|fold| fold.start <= region.start);
+            self.closed.insert(index, region);
+        }
+    }
+
+    /// Unfolds the folds starting at `row`, or those hiding it if none does,
+    /// and returns whether there were any.
+    #[inline]
+    pub fn open(&mut self, row: usize) -> bool {
+        let before = self.closed.len();
+        if self.closed.iter().any(|fold| fold.start == row) {
+            self.closed.retain(|fold| fold.start != row);
+        } else {
+            self.closed.retain(|fold| !fold.hides(row));
+        }
+        self.closed.len() != before
+    }
+
+    /// Unfolds the folds hiding `row`.
+    #[inline]
+    pub fn reveal(&mut self, row: usize) {
+        self.closed.retain(|fold| !fold.hides(row));
+    }
+
+    #[inline]
+    pub fn open_all(&mut self) {
+        self.closed.clear();
+    }
+
+    /// The outermost fold hiding `row`.
+    #[must_use]
+    #[inline]
+    pub fn hiding(&self, row: usize) -> Option<Region> {
+        self.closed.iter().copied().find(|fold| fold.hides(row))
+    }
+
+    /// The fold shown as a summary at `row`, if `row` is shown.
+    #[must_use]
+    #[inline]
+    pub fn at(&self, row: usize) -> Option<Region> {
+        if self.hiding(row).is_some() {
+            return None;
+        }
+        self.closed
+            .iter()
+            .filter(|fold| fold.start == row)
+            .copied()
+            .max_by_key(|fold| fold.end)
+    }
+
+    /// The rows hidden by the folds, in order and without overlaps.
+    fn hidden(&self) -> Vec<Range<usize>> {
+        let mut hidden: Vec<Range<usize>> = Vec::new();
+        for fold in &self.closed {
+            let rows = fold.start.saturating_add(1)..fold.end.saturating_add(1);
+            match hidden.last_mut() {
+                Some(last) if rows.start <= last.end => last.end = last.end.max(rows.end),
+                _ => hidden.push(rows),
+            }
+        }
+        hidden
+    }
+
+    /// The number of rows shown out of `len`.
+    #[must_use]
+    #[inline]
+    pub fn len(&self, len: usize) -> usize {
+        let hidden: usize = self
+            .hidden()
+            .iter()
+            .map(|rows| rows.end.min(len).saturating_sub(rows.start))
+            .sum();
+        len.saturating_sub(hidden)
+    }
+
+    /// The document row shown at `index`.
+    #[must_use]
+    #[inline]
+    pub fn document_row(&self, index: usize) -> usize {
+        let mut row = index;
+        for rows in self.hidden() {
+            if rows.start > row {
+                break;
+            }
+            row = row.saturating_add(rows.len());
+        }
+        row
+    }
+
+    /// Where document row `row` is shown, or the fold hiding it.
+    #[must_use]
+    #[inline]
+    pub fn index_of(&self, row: usize) -> usize {
+        let mut index = row;
+        for rows in self.hidden() {
+            if rows.start > row {
+                break;
+            }
+            let hidden = rows
+                .end
+                .min(row.saturating_add(1))
+                .saturating_sub(rows.start);
+            index = index.saturating_sub(hidden);
+        }
+        index
+    }
+
+    /// Moves a hidden `row` out of its fold, in the direction the cursor was
+    /// going: past the fold when going down, and to its first row otherwise.
+    #[inline]
+    pub fn snap(&mut self, row: usize, len: usize) -> usize {
+        let snapped = match self.hiding(row) {
+            Some(fold) if row > self.last_row && fold.end.saturating_add(1) < len => {
+                fold.end.saturating_add(1)
+            }
+            Some(fold) => fold.start,
+            None => row,
+        };
+        self.last_row = snapped;
+        snapped
+    }
+
+    /// Shifts the folds below `count` rows inserted at `at`. Folds the new
+    /// rows land in are unfolded, so that nothing changes out of sight.
+    #[inline]
+    pub fn rows_inserted(&mut self, at: usize, count: usize) {
+        self.closed.retain(|fold| !fold.hides(at));
+        for fold in &mut self.closed {
+            if fold.start >= at {
+                fold.start = fold.start.saturating_add(count);
+                fold.end = fold.end.saturating_add(count);
+            }
+        }
+    }
+
+    /// Shifts the folds below the rows in `rows`, which were removed. Folds
+    /// they overlap are unfolded.
+    #[inline]
+    pub fn rows_removed(&mut self, rows: Range<usize>) {
+        self.closed
+            .retain(|fold| fold.end < rows.start || fold.start >= rows.end);
+        for fold in &mut self.closed {
+            if fold.start >= rows.end {
+                fold.start = fold.start.saturating_sub(rows.len());
+                fold.end = fold.end.saturating_sub(rows.len());
+            }
+        }
+    }
+}
+
+/// The regions of `rows` that can be folded, ordered by their first row.
+/// Rows must be highlighted, so that brackets in strings and comments are
+/// told apart.
+#[must_use]
+pub fn regions(rows: &[Row], folding: Folding, tab_width: usize) -> Vec<Region> {
+    let mut regions = match folding {
+        Folding::Indent => indent_regions(rows, tab_width),
+        Folding::Braces => brace_regions(rows),
+    };
+    regions.sort_by_key(|region| (region.start, region.end));
+    regions.dedup();
+    regions
+}
+
+/// Rows indented deeper than the row above them, up to the last one before
+/// the indentation goes back. Blank rows belong to a region only between
+/// rows that do.
+fn indent_regions(rows: &[Row], tab_width: usize) -> Vec<Region> {
+    let mut regions = Vec::new();
+    // The rows starting regions not closed yet, with their indentation.
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut last = None;
+    for (index, row) in rows.iter().enumerate() {
+        let Some(indentation) = indentation(row.as_str(), tab_width) else {
+            continue;
+        };
+        while let Some(&(start, _)) = open.last().filter(|&&(_, width)| width >= indentation) {
+            open.pop();
+            push_region(&mut regions, start, last);
+        }
+        open.push((index, indentation));
+        last = Some(index);
+    }
+    while let Some((start, _)) = open.pop() {
+        push_region(&mut regions, start, last);
+    }
+    regions
+}
+
+fn push_region(regions: &mut Vec<Region>, start: usize, end: Option<usize>) {
+    if let Some(end) = end.filter(|end| *end > start) {
+        regions.push(Region { start, end });
+    }
+}
+
+/// The width of the whitespace `line` starts with, or `None` if it is blank.
+fn indentation(line: &str, tab_width: usize) -> Option<usize> {
+    let mut width: usize = 0;
+    for ch in line.chars() {
+        match ch {
+            ' ' => width = width.saturating_add(1),
+            '\t' => width = width.saturating_add(tab_width.max(1)),
+            _ if ch.is_whitespace() => (),
+            _ => return Some(width),
+        }
+    }
+    None
+}
+
+/// The rows between a bracket ending its row and the one closing it, the
+/// closing one left shown below the fold so that `} else {` starts the next.
+fn brace_regions(rows: &[Row]) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let mut open: Vec<(char, usize)> = Vec::new();
+    for (index, row) in rows.iter().enumerate() {
+        for (at, grapheme) in row.as_str().graphemes(true).enumerate() {
+            if is_literal(row.highlighting_at(at)) {
+                continue;
+            }
+            match grapheme {
+                "{" | "[" | "(" => open.extend(grapheme.chars().map(|ch| (ch, index))),
+                "}" | "]" | ")" => {
+                    let opening = match grapheme {
+                        "}" => '{',
+                        "]" => '[',
+                        _ => '(',
+                    };
+                    // A stray closing bracket closes nothing.
+                    if let Some(position) = open.iter().rposition(|&(ch, _)| ch == opening) {
+                        let (_, start) = open.get(position).copied().unwrap_or_default();
+                        open.truncate(position);
+                        push_region(&mut regions, start, index.checked_sub(1));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    regions
+}
+
+/// Whether brackets highlighted as `kind` are text rather than code.
+fn is_literal(kind: Option<highlighting::Type>) -> bool {
+    matches!(
+        kind,
+        Some(
+            highlighting::Type::String
+                | highlighting::Type::Character
+                | highlighting::Type::Comment
+                | highlighting::Type::DocComment
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(lines: &[&str]) -> Vec<Row> {
+        lines.iter().map(|line| Row::from(*line)).collect()
+    }
+
+    fn folded(regions: &[(usize, usize)]) -> Folds {
+        let mut folds = Folds::default();
+        for &(start, end) in regions {
+            folds.close(Region { start, end });
+        }
+        folds
+    }
+
+    fn closed(folds: &Folds) -> Vec<(usize, usize)> {
+        folds
+            .closed
+            .iter()
+            .map(|fold| (fold.start, fold.end))
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_counted_around_a_fold() {
+        let folds = folded(&[(2, 5)]);
+        assert_eq!(folds.len(10), 7);
+        assert_eq!(folds.document_row(2), 2);
+        assert_eq!(folds.document_row(3), 6);
+        assert_eq!(folds.index_of(4), 2);
+        assert_eq!(folds.index_of(6), 3);
+    }
+
+    #[test]
+    fn nested_folds_hide_the_outer_region() {
+        let folds = folded(&[(2, 8), (3, 5)]);
+        assert_eq!(folds.len(10), 4);
+        assert_eq!(folds.document_row(3), 9);
+        assert_eq!(folds.index_of(4), 2);
+        assert_eq!(folds.hiding(4), Some(Region { start: 2, end: 8 }));
+        assert_eq!(folds.at(2), Some(Region { start: 2, end: 8 }));
+        assert_eq!(folds.at(3), None);
+    }
+
+    #[test]
+    fn overlapping_folds_hide_their_union() {
+        let folds = folded(&[(2, 5), (4, 8)]);
+        assert_eq!(folds.len(10), 4);
+        assert_eq!(folds.document_row(3), 9);
+        assert_eq!(folds.index_of(9), 3);
+    }
+
+    #[test]
+    fn edits_above_a_fold_shift_it() {
+        let mut folds = folded(&[(5, 8)]);
+        folds.rows_inserted(2, 3);
+        assert_eq!(closed(&folds), [(8, 11)]);
+        folds.rows_removed(1..3);
+        assert_eq!(closed(&folds), [(6, 9)]);
+    }
+
+    #[test]
+    fn edits_inside_a_fold_unfold_it() {
+        let mut inserted = folded(&[(5, 8), (10, 12)]);
+        inserted.rows_inserted(6, 1);
+        assert_eq!(closed(&inserted), [(11, 13)]);
+
+        let mut removed = folded(&[(5, 8), (10, 12)]);
+        removed.rows_removed(6..7);
+        assert_eq!(closed(&removed), [(9, 11)]);
+    }
+
+    #[test]
+    fn indented_rows_are_regions() {
+        let rows = rows(&["def a():", "    x", "", "    if y:", "        z", "w"]);
+        assert_eq!(
+            regions(&rows, Folding::Indent, 4),
+            [Region { start: 0, end: 4 }, Region { start: 3, end: 4 }]
+        );
+    }
+
+    #[test]
+    fn brackets_enclose_regions() {
+        let rows = rows(&[
+            "fn main() {",
+            "    if x {",
+            "        y();",
+            "    } else {",
+            "        z();",
+            "    }",
+            "}",
+        ]);
+        assert_eq!(
+            regions(&rows, Folding::Braces, 4),
+            [
+                Region { start: 0, end: 5 },
+                Region { start: 1, end: 2 },
+                Region { start: 3, end: 4 },
+            ]
+        );
+    }
+}
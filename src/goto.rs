@@ -0,0 +1,216 @@
+use core::cmp;
+use std::path::Path;
+
+use crate::{motion::first_non_blank, Document, Position};
+
+enum Line {
+    Absolute(usize),
+    Forward(usize),
+    Backward(usize),
+    Percent(usize),
+}
+
+/// A place to jump to: `line`, `line:col`, `+N`/`-N` lines from the cursor
+/// or `N%` of the document. Lines and columns count from 1.
+pub struct Target {
+    line: Line,
+    column: Option<usize>,
+}
+
+impl Target {
+    #[must_use]
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        let (line, column) = match input.split_once(':') {
+            Some((line, column)) => (line, Some(column.parse().ok()?)),
+            None => (input, None),
+        };
+
+        let line = if let Some(count) = line.strip_prefix('+') {
+            Line::Forward(count.parse().ok()?)
+        } else if let Some(count) = line.strip_prefix('-') {
+            Line::Backward(count.parse().ok()?)
+        } else if let Some(percent) = line.strip_suffix('%') {
+            Line::Percent(percent.parse().ok()?)
+        } else {
+            Line::Absolute(line.parse().ok()?)
+        };
+
+        Some(Self { line, column })
+    }
+
+    /// The position in `document` this target points to, relative to `from`.
+    /// Without a column, the cursor goes to the first non-blank grapheme.
+    #[must_use]
+    pub fn resolve(&self, document: &Document, from: &Position) -> Position {
+        let last = document.len().saturating_sub(1);
+        let y = match self.line {
+            Line::Absolute(line) => line.saturating_sub(1),
+            Line::Forward(count) => from.y.saturating_add(count),
+            Line::Backward(count) => from.y.saturating_sub(count),
+            Line::Percent(percent) => {
+                #[expect(
+                    clippy::integer_division,
+                    clippy::integer_division_remainder_used,
+                    reason = "rounds to the nearest line"
+                )]
+                let line = document.len().saturating_mul(percent).saturating_add(50) / 100;
+                line.saturating_sub(1)
+            }
+        };
+        let y = cmp::min(y, last);
+
+        let x = match self.column {
+            Some(column) => cmp::min(column.saturating_sub(1), document.row_len(y).unwrap_or(0)),
+            None => first_non_blank(document, y),
+        };
+        Position { x, y }
+    }
+}
+
+/// Splits a `file:line[:col]` command-line argument into the file name and
+/// the position to open it at. Arguments naming an existing file are left
+/// alone.
+#[must_use]
+pub fn split_file_name(arg: &str) -> (&str, Option<Target>) {
+    if Path::new(arg).exists() {
+        return (arg, None);
+    }
+
+    let (rest, last) = match arg.rsplit_once(':') {
+        Some((rest, last)) if !rest.is_empty() => (rest, last),
+        _ => return (arg, None),
+    };
+    let Ok(last) = last.parse() else {
+        return (arg, None);
+    };
+
+    if let Some((name, line)) = rest.rsplit_once(':') {
+        if let (false, Ok(line)) = (name.is_empty(), line.parse()) {
+            let target = Target {
+                line: Line::Absolute(line),
+                column: Some(last),
+            };
+            return (name, Some(target));
+        }
+    }
+
+    let target = Target {
+        line: Line::Absolute(last),
+        column: None,
+    };
+    (rest, Some(target))
+}
+
+/// Parses a `+N` command-line argument, which opens the file at line N.
+#[must_use]
+pub fn parse_line_arg(arg: &str) -> Option<Target> {
+    let line = arg.strip_prefix('+')?.parse().ok()?;
+    Some(Target {
+        line: Line::Absolute(line),
+        column: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn targets_are_parsed() {
+        assert!(matches!(
+            Target::parse("12"),
+            Some(Target {
+                line: Line::Absolute(12),
+                column: None
+            })
+        ));
+        assert!(matches!(
+            Target::parse(" 12:3 "),
+            Some(Target {
+                line: Line::Absolute(12),
+                column: Some(3)
+            })
+        ));
+        assert!(matches!(
+            Target::parse("+5"),
+            Some(Target {
+                line: Line::Forward(5),
+                ..
+            })
+        ));
+        assert!(matches!(
+            Target::parse("-5"),
+            Some(Target {
+                line: Line::Backward(5),
+                ..
+            })
+        ));
+        assert!(matches!(
+            Target::parse("50%"),
+            Some(Target {
+                line: Line::Percent(50),
+                ..
+            })
+        ));
+        assert!(Target::parse("12:").is_none());
+        assert!(Target::parse("line").is_none());
+    }
+
+    #[test]
+    fn file_names_are_split_from_positions() {
+        assert!(matches!(
+            split_file_name("missing.rs:12:3"),
+            (
+                "missing.rs",
+                Some(Target {
+                    line: Line::Absolute(12),
+                    column: Some(3)
+                })
+            )
+        ));
+        assert!(matches!(
+            split_file_name("missing.rs:12"),
+            (
+                "missing.rs",
+                Some(Target {
+                    line: Line::Absolute(12),
+                    column: None
+                })
+            )
+        ));
+        assert!(matches!(
+            split_file_name("missing.rs"),
+            ("missing.rs", None)
+        ));
+        assert!(matches!(
+            split_file_name("missing.rs:x"),
+            ("missing.rs:x", None)
+        ));
+        assert!(matches!(split_file_name(":12"), (":12", None)));
+    }
+
+    #[test]
+    fn existing_files_are_not_split() {
+        let path = env::temp_dir().join("hecto-goto-test:12");
+        fs::write(&path, "").unwrap_or_default();
+        let name = path.to_string_lossy();
+        let (file_name, target) = split_file_name(&name);
+        assert_eq!(file_name, name);
+        assert!(target.is_none());
+        fs::remove_file(&path).unwrap_or_default();
+    }
+
+    #[test]
+    fn line_arguments_need_a_plus() {
+        assert!(matches!(
+            parse_line_arg("+7"),
+            Some(Target {
+                line: Line::Absolute(7),
+                column: None
+            })
+        ));
+        assert!(parse_line_arg("7").is_none());
+    }
+}
@@ -1,18 +1,34 @@
-use termion::color;
-
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Clone, Copy)]
 pub enum Type {
     None,
     Number,
     Match,
+    Location,
+    String,
+    Character,
+    Lifetime,
+    Comment,
+    DocComment,
+    PrimaryKeywords,
+    SecondaryKeywords,
+    Key,
+    Heading,
+    Variable,
+    /// Function and macro names, as a parser finds them.
+    Function,
 }
 
-impl Type {
-    pub fn to_color(&self) -> impl color::Color {
-        match self {
-            Type::Number => color::Rgb(220, 163, 163),
-            Type::Match => color::Rgb(38, 139, 210),
-            _ => color::Rgb(255, 255, 255),
-        }
-    }
+/// What a row ends inside of, carrying over to the start of the next row.
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
+pub enum State {
+    #[default]
+    Normal,
+    /// Inside a block comment, nested `depth` deep, which is a doc comment if
+    /// `doc` is set.
+    Comment { depth: usize, doc: bool },
+    /// Inside a string opened by the multi-line string delimiter at this
+    /// index of the file type's list.
+    String(usize),
+    /// Inside a fenced code block opened by the fence at this index.
+    Fence(usize),
 }
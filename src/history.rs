@@ -0,0 +1,183 @@
+use core::mem;
+
+use unicode_segmentation::UnicodeSegmentation as _;
+
+use crate::Position;
+
+/// A single change to a `Document`, with enough information to revert it.
+/// `new_row` marks insertions that created the row after the last one.
+#[derive(Clone)]
+pub enum Edit {
+    Insert {
+        at: Position,
+        text: String,
+        new_row: bool,
+    },
+    Delete {
+        at: Position,
+        text: String,
+    },
+}
+
+/// Undo and redo stacks. Edits recorded while a group is open are undone
+/// together; groups can be nested and only the outermost one counts.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    group: Vec<Edit>,
+    depth: usize,
+    paused: bool,
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit) {
+        if self.paused {
+            return;
+        }
+
+        self.redo.clear();
+        if self.depth == 0 {
+            self.undo.push(vec![edit]);
+        } else {
+            self.group.push(edit);
+        }
+    }
+
+    pub fn begin_group(&mut self) {
+        self.depth = self.depth.saturating_add(1);
+    }
+
+    pub fn end_group(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            self.commit_group();
+        }
+    }
+
+    /// Returns the last undo group, or `None` while the open group already
+    /// holds edits: undoing past them would split it.
+    pub fn pop_undo(&mut self) -> Option<Vec<Edit>> {
+        if self.has_open_edits() {
+            return None;
+        }
+        self.undo.pop()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Vec<Edit>> {
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, edits: Vec<Edit>) {
+        self.undo.push(edits);
+    }
+
+    pub fn push_redo(&mut self, edits: Vec<Edit>) {
+        self.redo.push(edits);
+    }
+
+    /// Whether edits were recorded into a group that is still open.
+    #[must_use]
+    pub fn has_open_edits(&self) -> bool {
+        !self.group.is_empty()
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    fn commit_group(&mut self) {
+        if !self.group.is_empty() {
+            self.undo.push(mem::take(&mut self.group));
+        }
+    }
+}
+
+/// The position right after `text` once it is inserted at `at`.
+#[must_use]
+pub fn end_position(at: &Position, text: &str) -> Position {
+    let mut end = at.clone();
+
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            end.y = end.y.saturating_add(1);
+            end.x = 0;
+        }
+        end.x = end.x.saturating_add(line.graphemes(true).count());
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(x: usize, text: &str) -> Edit {
+        Edit::Insert {
+            at: Position { x, y: 0 },
+            text: text.to_owned(),
+            new_row: false,
+        }
+    }
+
+    #[test]
+    fn grouped_edits_are_undone_together() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.begin_group();
+        history.record(insert(1, "b"));
+        history.begin_group();
+        history.record(insert(2, "c"));
+        history.end_group();
+        history.record(insert(3, "d"));
+        history.end_group();
+
+        assert_eq!(history.pop_undo().map(|edits| edits.len()), Some(3));
+        assert_eq!(history.pop_undo().map(|edits| edits.len()), Some(1));
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn open_groups_with_edits_are_not_undone() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.begin_group();
+        assert_eq!(history.pop_undo().map(|edits| edits.len()), Some(1));
+        history.record(insert(0, "b"));
+        assert!(history.has_open_edits());
+        assert!(history.pop_undo().is_none());
+        history.record(insert(1, "c"));
+        history.end_group();
+
+        assert!(!history.has_open_edits());
+        assert_eq!(history.pop_undo().map(|edits| edits.len()), Some(2));
+    }
+
+    #[test]
+    fn new_edits_clear_the_redo_stack() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        let edits = history.pop_undo().unwrap_or_default();
+        history.push_redo(edits);
+        history.set_paused(true);
+        history.record(insert(0, "ignored"));
+        history.set_paused(false);
+        assert!(history.pop_undo().is_none());
+        assert!(history.pop_redo().is_some());
+
+        history.push_redo(vec![insert(0, "a")]);
+        history.record(insert(0, "b"));
+        assert!(history.pop_redo().is_none());
+    }
+
+    #[test]
+    fn end_positions_follow_inserted_lines() {
+        let at = Position { x: 2, y: 3 };
+        assert_eq!(end_position(&at, "abc"), Position { x: 5, y: 3 });
+        assert_eq!(
+            end_position(&at, "ab\ncd\ne\u{301}"),
+            Position { x: 1, y: 5 }
+        );
+        assert_eq!(end_position(&at, ""), at);
+    }
+}
@@ -0,0 +1,542 @@
+use alloc::collections::BTreeMap;
+use std::fs;
+
+use serde::Deserialize;
+use termion::event::Key;
+
+use crate::{config, Command};
+
+const KEYS_FILE: &str = "keys.toml";
+const UNBIND: &str = "none";
+
+const DEFAULT_BINDINGS: &[(&str, Command)] = &[
+    ("ctrl-f", Command::Find),
+    ("ctrl-n", Command::SearchNext),
+    ("ctrl-p", Command::SearchPrevious),
+    ("ctrl-s", Command::Save),
+    ("ctrl-q", Command::Quit),
+    ("ctrl-g", Command::GotoLine),
+    ("ctrl-r", Command::Replace),
+    ("alt-r", Command::ReplaceAll),
+    ("alt-f", Command::FindInFiles),
+    ("alt-h", Command::ReplaceInFiles),
+    ("alt-l", Command::FilterLines),
+    ("alt-z", Command::Fold),
+    ("alt-u", Command::Unfold),
+    ("alt-a", Command::ToggleFolds),
+    ("alt-o", Command::SwitchBuffer),
+    ("up", Command::MoveUp),
+    ("down", Command::MoveDown),
+    ("left", Command::MoveLeft),
+    ("right", Command::MoveRight),
+    ("home", Command::MoveLineStart),
+    ("end", Command::MoveLineEnd),
+    ("pageup", Command::PageUp),
+    ("pagedown", Command::PageDown),
+    ("enter", Command::InsertNewline),
+    ("delete", Command::DeleteForward),
+    ("backspace", Command::DeleteBackward),
+    ("ctrl-z", Command::Undo),
+    ("ctrl-y", Command::Redo),
+    ("f3", Command::RecordMacro),
+    ("f4", Command::PlayMacro),
+    ("f5", Command::PlayMacroTimes),
+    ("f6", Command::NameMacro),
+    ("ctrl-t", Command::ToggleViMode),
+    ("alt-t", Command::SetFileType),
+    ("alt-c", Command::SetTheme),
+];
+
+const EMACS_BINDINGS: &[(&str, Command)] = &[
+    ("ctrl-s", Command::Find),
+    ("ctrl-r", Command::SearchPrevious),
+    ("ctrl-x ctrl-s", Command::Save),
+    ("ctrl-x ctrl-c", Command::Quit),
+    ("alt-g g", Command::GotoLine),
+    ("alt-g alt-g", Command::GotoLine),
+    ("alt-%", Command::Replace),
+    ("alt-s g", Command::FindInFiles),
+    ("alt-s r", Command::ReplaceInFiles),
+    ("alt-s o", Command::FilterLines),
+    ("ctrl-c @ ctrl-h", Command::Fold),
+    ("ctrl-c @ ctrl-s", Command::Unfold),
+    ("ctrl-c @ ctrl-t", Command::ToggleFolds),
+    ("ctrl-x b", Command::SwitchBuffer),
+    ("ctrl-p", Command::MoveUp),
+    ("ctrl-n", Command::MoveDown),
+    ("ctrl-b", Command::MoveLeft),
+    ("ctrl-f", Command::MoveRight),
+    ("ctrl-a", Command::MoveLineStart),
+    ("ctrl-e", Command::MoveLineEnd),
+    ("alt-f", Command::MoveWordForward),
+    ("alt-b", Command::MoveWordBackward),
+    ("alt-v", Command::PageUp),
+    ("ctrl-v", Command::PageDown),
+    ("ctrl-d", Command::DeleteForward),
+    ("ctrl-k", Command::KillLine),
+    ("ctrl-y", Command::Yank),
+    ("alt-y", Command::YankPop),
+    ("ctrl-g", Command::Cancel),
+    ("ctrl-x u", Command::Undo),
+    ("ctrl-x (", Command::StartMacro),
+    ("ctrl-x )", Command::StopMacro),
+    ("ctrl-x e", Command::PlayMacro),
+    ("ctrl-x ctrl-k r", Command::PlayMacroTimes),
+    ("ctrl-x ctrl-k n", Command::NameMacro),
+    ("ctrl-c t", Command::SetFileType),
+    ("ctrl-c c", Command::SetTheme),
+    ("f3", Command::StartMacro),
+    ("f4", Command::StopMacro),
+    ("up", Command::MoveUp),
+    ("down", Command::MoveDown),
+    ("left", Command::MoveLeft),
+    ("right", Command::MoveRight),
+    ("home", Command::MoveLineStart),
+    ("end", Command::MoveLineEnd),
+    ("pageup", Command::PageUp),
+    ("pagedown", Command::PageDown),
+    ("enter", Command::InsertNewline),
+    ("delete", Command::DeleteForward),
+    ("backspace", Command::DeleteBackward),
+];
+
+const NAMED_KEYS: &[(&str, Key)] = &[
+    ("up", Key::Up),
+    ("down", Key::Down),
+    ("left", Key::Left),
+    ("right", Key::Right),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("pageup", Key::PageUp),
+    ("pagedown", Key::PageDown),
+    ("delete", Key::Delete),
+    ("del", Key::Delete),
+    ("insert", Key::Insert),
+    ("backspace", Key::Backspace),
+    ("backtab", Key::BackTab),
+    ("tab", Key::Char('\t')),
+    ("enter", Key::Char('\n')),
+    ("return", Key::Char('\n')),
+    ("space", Key::Char(' ')),
+    ("esc", Key::Esc),
+    ("escape", Key::Esc),
+];
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Notation {
+    Default,
+    Emacs,
+}
+
+pub enum Binding {
+    Command(Command),
+    Prefix,
+    Unbound,
+}
+
+struct Entry {
+    keys: Vec<Key>,
+    command: Command,
+    user: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct KeymapFile {
+    preset: Option<String>,
+    #[serde(default)]
+    bindings: BTreeMap<String, String>,
+}
+
+pub struct Keymap {
+    entries: Vec<Entry>,
+    notation: Notation,
+    warnings: Vec<String>,
+}
+
+impl Default for Keymap {
+    #[inline]
+    fn default() -> Self {
+        Self::from_bindings(DEFAULT_BINDINGS, Notation::Default)
+    }
+}
+
+impl Keymap {
+    /// Returns one of the built-in keymaps: `default` or `emacs`.
+    #[must_use]
+    #[inline]
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "emacs" => Some(Self::from_bindings(EMACS_BINDINGS, Notation::Emacs)),
+            _ => None,
+        }
+    }
+
+    fn from_bindings(bindings: &[(&str, Command)], notation: Notation) -> Self {
+        let mut keymap = Self {
+            entries: Vec::new(),
+            notation,
+            warnings: Vec::new(),
+        };
+
+        for &(sequence, command) in bindings {
+            if let Ok(keys) = parse_sequence(sequence) {
+                keymap.entries.push(Entry {
+                    keys,
+                    command,
+                    user: false,
+                });
+            }
+        }
+
+        keymap
+    }
+
+    /// Loads the default keymap and applies the user bindings found in
+    /// `keys.toml` inside the config directory, if the file exists.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error message if the file exists but cannot be read or parsed.
+    #[inline]
+    pub fn load() -> Result<Self, String> {
+        let path = match config::config_dir() {
+            Some(dir) => dir.join(KEYS_FILE),
+            None => return Ok(Self::default()),
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        let file: KeymapFile = toml::from_str(&contents)
+            .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+        let preset = file.preset.as_deref().unwrap_or("default");
+        let mut keymap =
+            Self::preset(preset).ok_or_else(|| format!("Unknown keymap preset \"{preset}\""))?;
+
+        for (sequence, name) in &file.bindings {
+            keymap.bind_user(sequence, name);
+        }
+
+        Ok(keymap)
+    }
+
+    /// Problems found while applying user bindings: unknown keys or commands
+    /// and sequences that conflict with another user binding.
+    #[must_use]
+    #[inline]
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn lookup(&self, keys: &[Key]) -> Binding {
+        let mut is_prefix = false;
+
+        for entry in &self.entries {
+            if entry.keys == keys {
+                return Binding::Command(entry.command);
+            }
+            if entry.keys.starts_with(keys) {
+                is_prefix = true;
+            }
+        }
+
+        if is_prefix {
+            Binding::Prefix
+        } else {
+            Binding::Unbound
+        }
+    }
+
+    /// Human readable description of the first sequence bound to `command`.
+    #[must_use]
+    #[inline]
+    pub fn describe(&self, command: Command) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|entry| entry.command == command)
+            .map(|entry| self.describe_keys(&entry.keys))
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn describe_keys(&self, keys: &[Key]) -> String {
+        keys.iter()
+            .map(|key| describe_key(*key, self.notation))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn help(&self) -> String {
+        let help: Vec<String> = [Command::Find, Command::Save, Command::Quit]
+            .iter()
+            .filter_map(|command| {
+                self.describe(*command)
+                    .map(|keys| format!("{keys} = {command}"))
+            })
+            .collect();
+
+        format!("HELP: {}", help.join(" | "))
+    }
+
+    fn bind_user(&mut self, sequence: &str, name: &str) {
+        let keys = match parse_sequence(sequence) {
+            Ok(keys) => keys,
+            Err(error) => return self.warnings.push(error),
+        };
+
+        if name == UNBIND {
+            return self.entries.retain(|entry| entry.keys != keys);
+        }
+
+        let Some(command) = Command::from_name(name) else {
+            return self
+                .warnings
+                .push(format!("Unknown command \"{name}\" for \"{sequence}\""));
+        };
+
+        let conflicts =
+            |entry: &Entry| entry.keys.starts_with(&keys) || keys.starts_with(&entry.keys);
+
+        if let Some(existing) = self
+            .entries
+            .iter()
+            .find(|entry| entry.user && conflicts(entry))
+        {
+            return self.warnings.push(format!(
+                "\"{}\" conflicts with \"{}\"",
+                sequence,
+                self.describe_keys(&existing.keys)
+            ));
+        }
+
+        self.entries.retain(|entry| !conflicts(entry));
+        self.entries.push(Entry {
+            keys,
+            command,
+            user: true,
+        });
+    }
+}
+
+/// Parses a whitespace separated list of key chords, such as `ctrl-x ctrl-s`.
+///
+/// # Errors
+///
+/// Will return an error message if any of the chords is not a valid key.
+pub fn parse_sequence(sequence: &str) -> Result<Vec<Key>, String> {
+    let keys = sequence
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Result<Vec<Key>, String>>()?;
+
+    if keys.is_empty() {
+        return Err(String::from("Empty key sequence"));
+    }
+    Ok(keys)
+}
+
+fn strip_prefix_ignore_case<'text>(text: &'text str, prefix: &str) -> Option<&'text str> {
+    let rest = text.get(prefix.len()..).filter(|rest| !rest.is_empty())?;
+    text.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| rest)
+}
+
+fn parse_key(chord: &str) -> Result<Key, String> {
+    let invalid = || format!("Invalid key \"{chord}\"");
+
+    let ctrl = ["ctrl-", "c-"]
+        .iter()
+        .find_map(|prefix| strip_prefix_ignore_case(chord, prefix));
+    let alt = ["alt-", "meta-", "m-"]
+        .iter()
+        .find_map(|prefix| strip_prefix_ignore_case(chord, prefix));
+
+    let name = ctrl.or(alt).unwrap_or(chord);
+    let mut chars = name.chars();
+    let single = match (chars.next(), chars.next()) {
+        (Some(ch), None) => Some(ch),
+        _ => None,
+    };
+    let named = NAMED_KEYS
+        .iter()
+        .find(|&&(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+        .or_else(|| parse_function_key(name))
+        .or_else(|| parse_code_point(name));
+
+    match (ctrl.is_some(), alt.is_some(), single, named) {
+        (true, _, Some(ch), _) if ch.is_ascii_alphanumeric() => {
+            Ok(Key::Ctrl(ch.to_ascii_lowercase()))
+        }
+        (true, _, _, Some(Key::Char(' '))) => Ok(Key::Null),
+        (_, true, Some(ch), _) | (_, true, None, Some(Key::Char(ch))) => Ok(Key::Alt(ch)),
+        (false, false, Some(ch), _) => Ok(Key::Char(ch)),
+        (false, false, None, Some(key)) => Ok(key),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_function_key(name: &str) -> Option<Key> {
+    let number = strip_prefix_ignore_case(name, "f")?.parse::<u8>().ok()?;
+    (1..=12).contains(&number).then_some(Key::F(number))
+}
+
+/// The character written as `U+` and its code point in hex.
+fn parse_code_point(name: &str) -> Option<Key> {
+    let hex = strip_prefix_ignore_case(name, "u+")?;
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .map(Key::Char)
+}
+
+/// Formats keys so that `parse_sequence` reads them back.
+#[must_use]
+pub fn format_sequence(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| describe_key(*key, Notation::Default))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn describe_key(key: Key, notation: Notation) -> String {
+    if notation == Notation::Emacs {
+        return match key {
+            Key::Ctrl(ch) => format!("C-{ch}"),
+            Key::Alt(ch) => format!("M-{ch}"),
+            Key::Null => String::from("C-SPC"),
+            Key::Char('\n') => String::from("RET"),
+            Key::Char('\t') => String::from("TAB"),
+            Key::Char(' ') => String::from("SPC"),
+            Key::Char(ch) => ch.to_string(),
+            Key::Esc => String::from("ESC"),
+            Key::Backspace => String::from("DEL"),
+            other => format!(
+                "<{}>",
+                describe_key(other, Notation::Default).to_lowercase()
+            ),
+        };
+    }
+
+    match key {
+        Key::Ctrl(ch) => format!("Ctrl-{}", ch.to_ascii_uppercase()),
+        Key::Alt(ch) => format!("Alt-{}", describe_char(ch)),
+        Key::Null => String::from("Ctrl-Space"),
+        Key::Char(ch) => describe_char(ch),
+        Key::F(n) => format!("F{n}"),
+        Key::Up => String::from("Up"),
+        Key::Down => String::from("Down"),
+        Key::Left => String::from("Left"),
+        Key::Right => String::from("Right"),
+        Key::Home => String::from("Home"),
+        Key::End => String::from("End"),
+        Key::PageUp => String::from("PageUp"),
+        Key::PageDown => String::from("PageDown"),
+        Key::Delete => String::from("Delete"),
+        Key::Insert => String::from("Insert"),
+        Key::Backspace => String::from("Backspace"),
+        Key::BackTab => String::from("BackTab"),
+        Key::Esc => String::from("Esc"),
+        _ => String::from("Unknown"),
+    }
+}
+
+/// Names a character so that it reads back as one chord: by its key name, or
+/// as `U+0085` when it is blank or a control character.
+fn describe_char(ch: char) -> String {
+    match ch {
+        '\n' => String::from("Enter"),
+        '\t' => String::from("Tab"),
+        ' ' => String::from("Space"),
+        _ if ch.is_whitespace() || ch.is_control() => format!("U+{:04X}", u32::from(ch)),
+        _ => ch.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chords_are_parsed() {
+        assert_eq!(parse_key("ctrl-s"), Ok(Key::Ctrl('s')));
+        assert_eq!(parse_key("C-S"), Ok(Key::Ctrl('s')));
+        assert_eq!(parse_key("alt-x"), Ok(Key::Alt('x')));
+        assert_eq!(parse_key("meta-x"), Ok(Key::Alt('x')));
+        assert_eq!(parse_key("ctrl-space"), Ok(Key::Null));
+        assert_eq!(parse_key("PageUp"), Ok(Key::PageUp));
+        assert_eq!(parse_key("enter"), Ok(Key::Char('\n')));
+        assert_eq!(parse_key("f12"), Ok(Key::F(12)));
+        assert_eq!(parse_key("x"), Ok(Key::Char('x')));
+        assert!(parse_key("f13").is_err());
+        assert!(parse_key("ctrl-").is_err());
+        assert!(parse_key("hyper-x").is_err());
+        assert_eq!(
+            parse_sequence(" ctrl-x  ctrl-s "),
+            Ok(vec![Key::Ctrl('x'), Key::Ctrl('s')])
+        );
+        assert!(parse_sequence("").is_err());
+        assert!(parse_sequence("ctrl-x nope").is_err());
+    }
+
+    #[test]
+    fn descriptions_parse_back() {
+        let keymap = Keymap::default();
+        for &(sequence, command) in DEFAULT_BINDINGS {
+            let described = keymap.describe(command).unwrap_or_default();
+            assert_eq!(parse_sequence(&described), parse_sequence(sequence));
+        }
+    }
+
+    #[test]
+    fn user_bindings_replace_defaults() {
+        let mut keymap = Keymap::default();
+        keymap.bind_user("ctrl-s", "quit");
+        keymap.bind_user("ctrl-q", "none");
+        assert!(keymap.warnings().is_empty());
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('s')]),
+            Binding::Command(Command::Quit)
+        ));
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('q')]), Binding::Unbound));
+    }
+
+    #[test]
+    fn conflicting_user_bindings_are_reported() {
+        let mut keymap = Keymap::default();
+        keymap.bind_user("ctrl-k", "save");
+        keymap.bind_user("ctrl-k ctrl-k", "quit");
+        keymap.bind_user("ctrl-j", "no-such-command");
+        keymap.bind_user("hyper-j", "save");
+        assert_eq!(keymap.warnings().len(), 3);
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('k')]),
+            Binding::Command(Command::Save)
+        ));
+    }
+
+    #[test]
+    fn prefix_keys_wait_for_the_rest_of_the_sequence() {
+        let keymap = Keymap::preset("emacs").unwrap_or_default();
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('x')]), Binding::Prefix));
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('x'), Key::Ctrl('s')]),
+            Binding::Command(Command::Save)
+        ));
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('x'), Key::Char('k')]),
+            Binding::Unbound
+        ));
+        assert_eq!(keymap.describe(Command::Save).as_deref(), Some("C-x C-s"));
+        assert!(Keymap::preset("nano").is_none());
+    }
+}
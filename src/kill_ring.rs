@@ -0,0 +1,82 @@
+use alloc::collections::VecDeque;
+
+const CAPACITY: usize = 60;
+
+/// Killed text, most recent first, as in Emacs.
+#[derive(Default)]
+pub struct KillRing {
+    entries: VecDeque<String>,
+    yank_index: usize,
+}
+
+impl KillRing {
+    #[inline]
+    pub fn push(&mut self, text: String) {
+        self.entries.push_front(text);
+        self.entries.truncate(CAPACITY);
+        self.yank_index = 0;
+    }
+
+    /// Appends to the most recent kill, so consecutive kills yank as one.
+    #[inline]
+    pub fn append(&mut self, text: &str) {
+        match self.entries.front_mut() {
+            Some(entry) => entry.push_str(text),
+            None => self.push(text.to_owned()),
+        }
+    }
+
+    #[inline]
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_index = 0;
+        self.entries.front().map(String::as_str)
+    }
+
+    /// Moves to the next older kill, wrapping around at the end of the ring.
+    #[inline]
+    pub fn rotate(&mut self) -> Option<&str> {
+        self.yank_index = self
+            .yank_index
+            .saturating_add(1)
+            .checked_rem(self.entries.len())
+            .unwrap_or(0);
+        self.entries.get(self.yank_index).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_kills_are_appended() {
+        let mut ring = KillRing::default();
+        assert_eq!(ring.yank(), None);
+        ring.append("one");
+        ring.append(" two");
+        assert_eq!(ring.yank(), Some("one two"));
+    }
+
+    #[test]
+    fn rotating_goes_back_through_older_kills() {
+        let mut ring = KillRing::default();
+        ring.push(String::from("first"));
+        ring.push(String::from("second"));
+        ring.push(String::from("third"));
+        assert_eq!(ring.yank(), Some("third"));
+        assert_eq!(ring.rotate(), Some("second"));
+        assert_eq!(ring.rotate(), Some("first"));
+        assert_eq!(ring.rotate(), Some("third"));
+        assert_eq!(ring.yank(), Some("third"));
+    }
+
+    #[test]
+    fn old_kills_are_dropped() {
+        let mut ring = KillRing::default();
+        for index in 0..=CAPACITY {
+            ring.push(index.to_string());
+        }
+        assert_eq!(ring.entries.len(), CAPACITY);
+        assert_eq!(ring.entries.back().map(String::as_str), Some("1"));
+    }
+}
@@ -0,0 +1,279 @@
+use core::ops::Range;
+use std::{fs, path::Path};
+
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation as _;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    motion::{classify, Class},
+    Command,
+};
+
+/// Lists the completions of a prompt's input.
+pub type Completer<'completer> = &'completer dyn Fn(&str) -> Vec<String>;
+
+/// A single line of text being edited, such as the answer to a prompt. The
+/// cursor counts graphemes, so an accented letter is deleted as a whole, and
+/// `offset` is the first grapheme shown when the text is wider than the space
+/// it is drawn in. Scrolling goes by the columns graphemes take on screen, two
+/// for most CJK characters and emoji.
+#[derive(Default, Clone)]
+pub struct LineInput {
+    text: String,
+    cursor: usize,
+    offset: usize,
+}
+
+impl LineInput {
+    #[must_use]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text, leaving the cursor at its end.
+    pub fn set_text(&mut self, text: &str) {
+        text.clone_into(&mut self.text);
+        self.cursor = self.len();
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.text.graphemes(true).count()
+    }
+
+    /// Inserts `text` at the cursor, dropping control characters so pasted
+    /// tabs or line breaks cannot end up in the answer.
+    pub fn insert_str(&mut self, text: &str) {
+        let text: String = text.chars().filter(|ch| !ch.is_control()).collect();
+        let at = self.byte_index(self.cursor);
+        self.text.insert_str(at, &text);
+        self.cursor = self.cursor.saturating_add(text.graphemes(true).count());
+    }
+
+    /// Applies an editing key, or the command bound to it, and returns whether
+    /// it was one. Left/Right, Home/End (Ctrl-A/E), Alt-B/F, Backspace, Delete,
+    /// Ctrl-W or Alt-Backspace (word before the cursor), Alt-D (word after it),
+    /// Ctrl-U (up to the cursor) and Ctrl-K (from the cursor) are understood.
+    pub fn handle(&mut self, key: Key, command: Option<Command>) -> bool {
+        match (key, command) {
+            (Key::Left, _) | (_, Some(Command::MoveLeft)) => {
+                self.cursor = self.cursor.saturating_sub(1);
+            }
+            (Key::Right, _) | (_, Some(Command::MoveRight)) => {
+                self.cursor = self.len().min(self.cursor.saturating_add(1));
+            }
+            (Key::Home | Key::Ctrl('a'), _) | (_, Some(Command::MoveLineStart)) => self.cursor = 0,
+            (Key::End | Key::Ctrl('e'), _) | (_, Some(Command::MoveLineEnd)) => {
+                self.cursor = self.len();
+            }
+            (Key::Alt('b'), _) | (_, Some(Command::MoveWordBackward)) => {
+                self.cursor = self.word_start();
+            }
+            (Key::Alt('f'), _) | (_, Some(Command::MoveWordForward)) => {
+                self.cursor = self.word_end();
+            }
+            (Key::Backspace, _) | (_, Some(Command::DeleteBackward)) => {
+                self.delete_to(self.cursor.saturating_sub(1));
+            }
+            (Key::Delete, _) | (_, Some(Command::DeleteForward)) => {
+                self.delete_to(self.cursor.saturating_add(1));
+            }
+            (Key::Ctrl('w') | Key::Alt('\x7f'), _) => self.delete_to(self.word_start()),
+            (Key::Alt('d'), _) => self.delete_to(self.word_end()),
+            (Key::Ctrl('u'), _) => self.delete_to(0),
+            (Key::Ctrl('k'), _) | (_, Some(Command::KillLine)) => self.delete_to(self.len()),
+            (Key::Char(ch), _) if !ch.is_control() => self.insert_str(ch.encode_utf8(&mut [0; 4])),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Completes the text with `candidates`: a single candidate replaces it,
+    /// several extend it to the prefix they share.
+    pub fn complete(&mut self, candidates: &[String]) {
+        let mut candidates = candidates.iter();
+        let first = match candidates.next() {
+            Some(first) => first.as_str(),
+            None => return,
+        };
+        let prefix = candidates.fold(first, |prefix, candidate| {
+            let len = prefix
+                .char_indices()
+                .zip(candidate.chars())
+                .find(|&((_, expected), found)| expected != found)
+                .map_or(prefix.len().min(candidate.len()), |((index, _), _)| index);
+            prefix.get(..len).unwrap_or_default()
+        });
+        if prefix.len() >= self.text.len() {
+            self.set_text(prefix);
+        }
+    }
+
+    /// Scrolls horizontally so the cursor stays within `width` columns.
+    pub fn scroll(&mut self, width: usize) {
+        let width = width.max(1);
+        let widths = self.widths();
+        let columns = |range: Range<usize>| -> usize {
+            widths.get(range).map_or(0, |widths| widths.iter().sum())
+        };
+
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        }
+        while self.offset < self.cursor && columns(self.offset..self.cursor) >= width {
+            self.offset = self.offset.saturating_add(1);
+        }
+        // Don't leave blank space behind the text once it is shortened, but
+        // keep a column for the cursor after it.
+        while self.offset > 0 && columns(self.offset.saturating_sub(1)..widths.len()) < width {
+            self.offset = self.offset.saturating_sub(1);
+        }
+    }
+
+    /// The part of the text drawn in `width` columns.
+    #[must_use]
+    pub fn visible(&self, width: usize) -> String {
+        let mut columns: usize = 0;
+        self.text
+            .graphemes(true)
+            .skip(self.offset)
+            .take_while(|grapheme| {
+                columns = columns.saturating_add(grapheme.width());
+                columns <= width
+            })
+            .collect()
+    }
+
+    /// The column of the cursor within the visible part.
+    #[must_use]
+    pub fn cursor_column(&self) -> usize {
+        self.text
+            .graphemes(true)
+            .take(self.cursor)
+            .skip(self.offset)
+            .map(UnicodeWidthStr::width)
+            .sum()
+    }
+
+    /// The columns each grapheme takes on screen.
+    fn widths(&self) -> Vec<usize> {
+        self.text
+            .graphemes(true)
+            .map(UnicodeWidthStr::width)
+            .collect()
+    }
+
+    fn byte_index(&self, at: usize) -> usize {
+        self.text
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.text.len(), |(index, _)| index)
+    }
+
+    /// Deletes the graphemes between the cursor and `to`, leaving the cursor
+    /// at the start of the deleted text.
+    fn delete_to(&mut self, to: usize) {
+        let to = to.min(self.len());
+        let (start, end) = if to < self.cursor {
+            (to, self.cursor)
+        } else {
+            (self.cursor, to)
+        };
+        let range = self.byte_index(start)..self.byte_index(end);
+        self.text.replace_range(range, "");
+        self.cursor = start;
+    }
+
+    /// The start of the word before the cursor, skipping non-word graphemes
+    /// first, as Emacs' `backward-word` does.
+    fn word_start(&self) -> usize {
+        let classes: Vec<Class> = self
+            .text
+            .graphemes(true)
+            .take(self.cursor)
+            .map(classify)
+            .collect();
+        let end = classes
+            .iter()
+            .rposition(|class| *class == Class::Word)
+            .map_or(0, |index| index.saturating_add(1));
+        classes
+            .get(..end)
+            .and_then(|word| word.iter().rposition(|class| *class != Class::Word))
+            .map_or(0, |index| index.saturating_add(1))
+    }
+
+    /// The end of the word after the cursor.
+    fn word_end(&self) -> usize {
+        let mut graphemes = self.text.graphemes(true).skip(self.cursor).peekable();
+        let mut at = self.cursor;
+        while graphemes
+            .next_if(|grapheme| classify(grapheme) != Class::Word)
+            .is_some()
+        {
+            at = at.saturating_add(1);
+        }
+        while graphemes
+            .next_if(|grapheme| classify(grapheme) == Class::Word)
+            .is_some()
+        {
+            at = at.saturating_add(1);
+        }
+        at
+    }
+}
+
+/// Completes a file name: the entries of the directory typed so far whose
+/// names start with the rest of the input, directories ending with `/`.
+#[must_use]
+pub fn complete_path(input: &str) -> Vec<String> {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(index) => input.split_at(index.saturating_add(1)),
+        None => ("", input),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(dir)
+    }) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{slash}"))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_graphemes_scroll_by_their_columns() {
+        let mut input = LineInput::default();
+        // "日本語テキスト", two columns a grapheme.
+        input.set_text("\u{65e5}\u{672c}\u{8a9e}\u{30c6}\u{30ad}\u{30b9}\u{30c8}");
+        input.scroll(10);
+        assert_eq!(input.visible(10), "\u{30c6}\u{30ad}\u{30b9}\u{30c8}");
+        assert_eq!(input.cursor_column(), 8);
+
+        input.handle(Key::Home, None);
+        input.scroll(10);
+        assert_eq!(
+            input.visible(10),
+            "\u{65e5}\u{672c}\u{8a9e}\u{30c6}\u{30ad}"
+        );
+        assert_eq!(input.cursor_column(), 0);
+    }
+}
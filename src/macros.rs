@@ -0,0 +1,236 @@
+use alloc::collections::BTreeMap;
+use std::{collections::HashMap, fs};
+
+use serde::{Deserialize, Serialize};
+use termion::event::Key;
+
+use crate::{config, keymap};
+
+pub const DEFAULT_REGISTER: char = '"';
+const MACROS_FILE: &str = "macros.toml";
+
+#[derive(Serialize, Deserialize, Default)]
+struct MacrosFile {
+    #[serde(default)]
+    macros: BTreeMap<String, String>,
+}
+
+/// Recorded key sequences, stored in registers named by a single character.
+#[derive(Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<Key>>,
+    recording: Option<(char, Vec<Key>)>,
+    last: Option<char>,
+    warnings: Vec<String>,
+}
+
+impl Macros {
+    /// Loads the macros saved in the data directory by `save`.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error message if the file exists but cannot be read or parsed.
+    #[inline]
+    pub fn load() -> Result<Self, String> {
+        let path = match config::data_dir() {
+            Some(dir) => dir.join(MACROS_FILE),
+            None => return Ok(Self::default()),
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        let file: MacrosFile = toml::from_str(&contents)
+            .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+        Ok(Self::from_file(file))
+    }
+
+    /// Reads the registers of a saved file, keeping a warning for each entry
+    /// that cannot be loaded.
+    fn from_file(file: MacrosFile) -> Self {
+        let mut macros = Self::default();
+        for (name, sequence) in file.macros {
+            let mut chars = name.chars();
+            let (Some(register), None) = (chars.next(), chars.next()) else {
+                macros.warnings.push(format!(
+                    "{MACROS_FILE}: \"{name}\" is not a single-character register"
+                ));
+                continue;
+            };
+            match keymap::parse_sequence(&sequence) {
+                Ok(keys) => {
+                    macros.registers.insert(register, keys);
+                }
+                Err(error) => macros
+                    .warnings
+                    .push(format!("{MACROS_FILE}: macro @{register}: {error}")),
+            }
+        }
+        macros
+    }
+
+    /// The saved macros that could not be loaded.
+    #[must_use]
+    #[inline]
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// # Errors
+    ///
+    /// Will return an error message if the macros file cannot be written.
+    #[inline]
+    pub fn save(&self) -> Result<(), String> {
+        let dir = config::data_dir().ok_or("Could not find the data directory")?;
+        let path = dir.join(MACROS_FILE);
+
+        let file = MacrosFile {
+            macros: self
+                .registers
+                .iter()
+                .filter(|&(_, keys)| !keys.is_empty())
+                .map(|(register, keys)| (register.to_string(), keymap::format_sequence(keys)))
+                .collect(),
+        };
+        let contents = toml::to_string(&file).map_err(|error| error.to_string())?;
+
+        fs::create_dir_all(&dir)
+            .and_then(|()| fs::write(&path, contents))
+            .map_err(|error| format!("Could not write {}: {}", path.display(), error))
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn recording_register(&self) -> Option<char> {
+        self.recording.as_ref().map(|recording| recording.0)
+    }
+
+    #[inline]
+    pub fn start(&mut self, register: char) {
+        self.recording = Some((register, Vec::new()));
+    }
+
+    #[inline]
+    pub fn record(&mut self, key: Key) {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.1.push(key);
+        }
+    }
+
+    /// Stops recording and stores the macro, leaving out the `trailing` keys
+    /// that asked to stop. Uppercase registers append to the lowercase one.
+    #[inline]
+    pub fn stop(&mut self, trailing: usize) -> Option<char> {
+        let (register, mut keys) = self.recording.take()?;
+        keys.truncate(keys.len().saturating_sub(trailing));
+
+        let name = register.to_ascii_lowercase();
+        if register.is_ascii_uppercase() {
+            self.registers.entry(name).or_default().extend(keys);
+        } else {
+            self.registers.insert(name, keys);
+        }
+        self.last = Some(name);
+        Some(name)
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn get(&self, register: char) -> Option<&[Key]> {
+        self.registers
+            .get(&register.to_ascii_lowercase())
+            .map(Vec::as_slice)
+    }
+
+    #[inline]
+    pub fn store(&mut self, register: char, keys: Vec<Key>) {
+        self.registers.insert(register.to_ascii_lowercase(), keys);
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn last(&self) -> Option<char> {
+        self.last
+    }
+
+    #[inline]
+    pub fn set_last(&mut self, register: char) {
+        self.last = Some(register.to_ascii_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_leaves_out_the_trailing_keys() {
+        let mut macros = Macros::default();
+        macros.start('a');
+        assert_eq!(macros.recording_register(), Some('a'));
+        for key in &[Key::Char('x'), Key::Down, Key::F(3)] {
+            macros.record(*key);
+        }
+        assert_eq!(macros.stop(1), Some('a'));
+        assert_eq!(macros.recording_register(), None);
+        assert_eq!(macros.get('a'), Some(&[Key::Char('x'), Key::Down][..]));
+        assert_eq!(macros.last(), Some('a'));
+    }
+
+    #[test]
+    fn uppercase_registers_append() {
+        let mut macros = Macros::default();
+        macros.store('a', vec![Key::Char('x')]);
+        macros.start('A');
+        macros.record(Key::Char('y'));
+        assert_eq!(macros.stop(0), Some('a'));
+        assert_eq!(macros.get('A'), Some(&[Key::Char('x'), Key::Char('y')][..]));
+
+        macros.start('a');
+        macros.record(Key::Char('z'));
+        macros.stop(0);
+        assert_eq!(macros.get('a'), Some(&[Key::Char('z')][..]));
+    }
+
+    #[test]
+    fn saved_sequences_parse_back() {
+        let keys = [
+            Key::Char('x'),
+            Key::Char(' '),
+            Key::Char('\n'),
+            Key::Ctrl('s'),
+            Key::Alt('f'),
+            Key::F(5),
+            Key::PageDown,
+            Key::Backspace,
+            Key::Null,
+            Key::Esc,
+            Key::BackTab,
+            Key::Ctrl('4'),
+            Key::Char('-'),
+            Key::Alt('-'),
+            Key::Char('\t'),
+            Key::Char('\r'),
+            Key::Char('\u{a0}'),
+            Key::Alt('\n'),
+            Key::Alt(' '),
+            Key::Alt('\u{1b}'),
+        ];
+        let sequence = keymap::format_sequence(&keys);
+        assert_eq!(keymap::parse_sequence(&sequence), Ok(keys.to_vec()));
+    }
+
+    #[test]
+    fn unreadable_entries_are_reported() {
+        let file: MacrosFile =
+            toml::from_str("[macros]\na = \"ctrl-s\"\nab = \"x\"\nb = \"ctrl-x nope\"\n")
+                .unwrap_or_default();
+        let macros = Macros::from_file(file);
+        assert_eq!(macros.get('a'), Some(&[Key::Ctrl('s')][..]));
+        assert!(macros.get('b').is_none());
+        assert_eq!(macros.warnings().len(), 2);
+    }
+}
@@ -6,24 +6,70 @@
     clippy::shadow_reuse,
     clippy::print_stdout,
     clippy::wildcard_enum_match_arm,
-    clippy::else_if_without_else
+    clippy::else_if_without_else,
+    // Types are re-exported from the crate root, as `Document` and `Row`
+    // always were.
+    clippy::pub_use,
+    // Errors are passed up with `?`, which `clippy::question_mark` asks for.
+    clippy::question_mark_used,
+    // Long functions are split into helpers, as `clippy::too_many_lines`
+    // asks, and most of those are called once.
+    clippy::single_call_fn,
+    // Items are grouped by what they do rather than sorted by name.
+    clippy::arbitrary_source_item_ordering
 )]
 
+extern crate alloc;
+
+mod command;
+mod config;
 mod document;
 mod editor;
 mod filetype;
+mod filter;
+mod fold;
+mod goto;
 mod highlighting;
+mod history;
+mod keymap;
+mod kill_ring;
+mod line_input;
+mod macros;
+mod motion;
+mod project_search;
+mod prompt_history;
+mod registers;
 mod row;
+mod search;
+#[cfg(feature = "tree-sitter")]
+mod syntax;
 mod terminal;
+mod theme;
+mod vi;
 
+pub use command::Command;
+pub use config::Config;
 pub use document::Document;
 use editor::Editor;
 pub use editor::Position;
 pub use editor::SearchDirection;
 pub use filetype::FileType;
+pub use filetype::FileTypes;
 pub use filetype::HighlightingOptions;
+pub use filetype::Indent;
+pub use fold::Folding;
+pub use fold::Folds;
+pub use keymap::Keymap;
+pub use kill_ring::KillRing;
+pub use macros::Macros;
 pub use row::Row;
+pub use search::Matcher;
+pub use search::SearchOptions;
+pub use terminal::ColorDepth;
 pub use terminal::Terminal;
+pub use theme::Theme;
+pub use theme::Themes;
+pub use vi::Vi;
 
 fn main() {
     Editor::default().run();
@@ -0,0 +1,113 @@
+use crate::{Document, Position};
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Class {
+    Space,
+    Word,
+    Punctuation,
+    EmptyLine,
+}
+
+pub fn classify(grapheme: &str) -> Class {
+    match grapheme.chars().next() {
+        Some(ch) if ch.is_whitespace() => Class::Space,
+        Some(ch) if ch.is_alphanumeric() || ch == '_' => Class::Word,
+        Some(_) => Class::Punctuation,
+        None => Class::Space,
+    }
+}
+
+/// The class of the grapheme at `position`, where the position right after
+/// the end of a row stands for its line break.
+pub fn class_at(document: &Document, position: &Position) -> Class {
+    match document.row(position.y) {
+        Some(row) if row.is_empty() => Class::EmptyLine,
+        Some(row) => row.grapheme(position.x).map_or(Class::Space, classify),
+        None => Class::Space,
+    }
+}
+
+pub fn step_forward(document: &Document, position: &Position) -> Option<Position> {
+    let len = document.row_len(position.y)?;
+    if position.x < len {
+        Some(Position {
+            x: position.x.saturating_add(1),
+            y: position.y,
+        })
+    } else if position.y.saturating_add(1) < document.len() {
+        Some(Position {
+            x: 0,
+            y: position.y.saturating_add(1),
+        })
+    } else {
+        None
+    }
+}
+
+pub fn step_backward(document: &Document, position: &Position) -> Option<Position> {
+    if position.x > 0 {
+        Some(Position {
+            x: position.x.saturating_sub(1),
+            y: position.y,
+        })
+    } else if position.y > 0 {
+        let y = position.y.saturating_sub(1);
+        Some(Position {
+            x: document.row_len(y).unwrap_or(0),
+            y,
+        })
+    } else {
+        None
+    }
+}
+
+pub fn first_non_blank(document: &Document, y: usize) -> usize {
+    document.row(y).map_or(0, |row| {
+        row.as_str()
+            .chars()
+            .take_while(|ch| ch.is_whitespace())
+            .count()
+    })
+}
+
+/// Moves past any non-word graphemes and then to the end of the next word,
+/// as Emacs' `forward-word` does.
+pub fn forward_word(document: &Document, start: &Position) -> Position {
+    let mut position = start.clone();
+
+    while class_at(document, &position) != Class::Word {
+        match step_forward(document, &position) {
+            Some(next) => position = next,
+            None => return position,
+        }
+    }
+    while class_at(document, &position) == Class::Word {
+        match step_forward(document, &position) {
+            Some(next) => position = next,
+            None => return position,
+        }
+    }
+    position
+}
+
+/// Moves back past any non-word graphemes and then to the start of the
+/// previous word, as Emacs' `backward-word` does.
+pub fn backward_word(document: &Document, start: &Position) -> Position {
+    let Some(mut position) = step_backward(document, start) else {
+        return start.clone();
+    };
+
+    while class_at(document, &position) != Class::Word {
+        match step_backward(document, &position) {
+            Some(previous) => position = previous,
+            None => return position,
+        }
+    }
+    while let Some(previous) = step_backward(document, &position) {
+        if class_at(document, &previous) != Class::Word {
+            break;
+        }
+        position = previous;
+    }
+    position
+}
@@ -0,0 +1,213 @@
+use alloc::sync::Arc;
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::{
+    fs,
+    path::Path,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use ignore::WalkBuilder;
+use unicode_segmentation::UnicodeSegmentation as _;
+
+use crate::{Matcher, Position};
+
+/// How much of a file is checked for NUL bytes to tell binary files apart.
+const BINARY_CHECK_LEN: usize = 8192;
+
+/// A match found by a project search. `bytes` and `columns` are the byte and
+/// grapheme ranges of the match in `text`, the whole line it was found on.
+pub struct Hit {
+    pub path: String,
+    pub position: Position,
+    pub bytes: Range<usize>,
+    pub columns: Range<usize>,
+    pub text: String,
+}
+
+impl Hit {
+    /// The `path:line:col: ` prefix of the hit's line in the results.
+    #[must_use]
+    pub fn location(&self) -> String {
+        format!(
+            "{}:{}:{}: ",
+            self.path,
+            self.position.y.saturating_add(1),
+            self.position.x.saturating_add(1)
+        )
+    }
+}
+
+/// What the search thread sends back.
+pub enum Update {
+    Hit(Hit),
+    /// The search went through every file, or was cancelled.
+    Done,
+}
+
+/// A search through the files under a directory, running on its own thread
+/// and sending hits as they are found. Files ignored by `.gitignore`, hidden
+/// files and binary files are skipped. Dropping the search cancels it.
+pub struct ProjectSearch {
+    updates: Receiver<Hit>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ProjectSearch {
+    #[must_use]
+    pub fn start(root: &Path, matcher: Matcher) -> Self {
+        let (sender, updates) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let walker = WalkBuilder::new(root)
+            .require_git(false)
+            .sort_by_file_path(Ord::cmp)
+            .build();
+        let stop = Arc::clone(&cancelled);
+        thread::spawn(move || {
+            for entry in walker.filter_map(Result::ok) {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                if entry.file_type().is_none_or(|kind| kind.is_dir()) {
+                    continue;
+                }
+                for hit in search_file(entry.path(), &matcher) {
+                    if sender.send(hit).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Self { updates, cancelled }
+    }
+
+    /// The next hit if one is ready, without waiting.
+    #[must_use]
+    pub fn poll(&self) -> Option<Update> {
+        match self.updates.try_recv() {
+            Ok(hit) => Some(Update::Hit(hit)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Update::Done),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ProjectSearch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Every match in the file at `path`, or none if it is binary or unreadable.
+fn search_file(path: &Path, matcher: &Matcher) -> Vec<Hit> {
+    let Ok(bytes) = fs::read(path) else {
+        return Vec::new();
+    };
+    let head = bytes.get(..BINARY_CHECK_LEN).unwrap_or(&bytes);
+    if head.contains(&0) {
+        return Vec::new();
+    }
+    let Ok(contents) = String::from_utf8(bytes) else {
+        return Vec::new();
+    };
+
+    let display = path.strip_prefix(".").unwrap_or(path).display().to_string();
+    let mut hits = Vec::new();
+    for (y, line) in contents.lines().enumerate() {
+        for found in matcher.matches(line) {
+            let start = line
+                .get(..found.start)
+                .unwrap_or_default()
+                .graphemes(true)
+                .count();
+            let len = line
+                .get(found.clone())
+                .unwrap_or_default()
+                .graphemes(true)
+                .count();
+            hits.push(Hit {
+                path: display.clone(),
+                position: Position { x: start, y },
+                bytes: found,
+                columns: start..start.saturating_add(len),
+                text: line.to_owned(),
+            });
+        }
+    }
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchOptions;
+    use core::time::Duration;
+    use std::env;
+
+    fn matcher(query: &str) -> Option<Matcher> {
+        Matcher::new(query, SearchOptions::default()).ok()
+    }
+
+    #[test]
+    fn hits_give_the_grapheme_columns() {
+        let path = env::temp_dir().join("hecto-project-search-test.txt");
+        fs::write(&path, "no\n\u{e9}t\u{e9} et \u{e9}t\u{e9}\n").unwrap_or_default();
+        let hits = matcher("t\u{e9}").map_or_else(Vec::new, |matcher| search_file(&path, &matcher));
+        fs::remove_file(&path).unwrap_or_default();
+
+        let found: Vec<(Position, Range<usize>)> = hits
+            .iter()
+            .map(|hit| (hit.position.clone(), hit.columns.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Position { x: 1, y: 1 }, 1..3),
+                (Position { x: 8, y: 1 }, 8..10)
+            ]
+        );
+        assert!(hits
+            .iter()
+            .all(|hit| hit.text == "\u{e9}t\u{e9} et \u{e9}t\u{e9}"));
+    }
+
+    #[test]
+    fn binary_files_are_skipped() {
+        let path = env::temp_dir().join("hecto-project-search-test.bin");
+        fs::write(&path, b"match\0match").unwrap_or_default();
+        let hits = matcher("match").map_or_else(Vec::new, |matcher| search_file(&path, &matcher));
+        fs::remove_file(&path).unwrap_or_default();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn searches_send_hits_then_finish() {
+        let root = env::temp_dir().join("hecto-project-search-test");
+        fs::create_dir_all(root.join("sub")).unwrap_or_default();
+        fs::write(root.join("a.txt"), "needle\n").unwrap_or_default();
+        fs::write(root.join("sub").join("b.txt"), "hay\nneedle needle\n").unwrap_or_default();
+
+        let mut lines = Vec::new();
+        if let Some(matcher) = matcher("needle") {
+            let search = ProjectSearch::start(&root, matcher);
+            loop {
+                match search.poll() {
+                    Some(Update::Hit(hit)) => lines.push(hit.position.y),
+                    Some(Update::Done) => break,
+                    None => thread::sleep(Duration::from_millis(1)),
+                }
+            }
+        }
+        fs::remove_dir_all(&root).unwrap_or_default();
+        assert_eq!(lines, [0, 1, 1]);
+    }
+}
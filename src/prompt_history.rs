@@ -0,0 +1,122 @@
+use alloc::collections::BTreeMap;
+use std::fs;
+
+use crate::config;
+
+const HISTORY_FILE: &str = "history.toml";
+const MAX_ENTRIES: usize = 100;
+
+/// The prompts that keep a history of their answers. Search and replace
+/// share their queries.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum PromptKind {
+    Search,
+    Replacement,
+    Goto,
+    File,
+    Macro,
+    FileType,
+    Theme,
+}
+
+impl PromptKind {
+    fn name(self) -> &'static str {
+        match self {
+            PromptKind::Search => "search",
+            PromptKind::Replacement => "replacement",
+            PromptKind::Goto => "goto",
+            PromptKind::File => "file",
+            PromptKind::Macro => "macro",
+            PromptKind::FileType => "filetype",
+            PromptKind::Theme => "theme",
+        }
+    }
+}
+
+/// Answers to past prompts, oldest first, saved in the data directory.
+#[derive(Default)]
+pub struct PromptHistory {
+    entries: BTreeMap<String, Vec<String>>,
+}
+
+impl PromptHistory {
+    /// # Errors
+    ///
+    /// Will return an error message if the file exists but cannot be read or parsed.
+    pub fn load() -> Result<Self, String> {
+        let path = match config::data_dir() {
+            Some(dir) => dir.join(HISTORY_FILE),
+            None => return Ok(Self::default()),
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        let entries = toml::from_str(&contents)
+            .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+        Ok(Self { entries })
+    }
+
+    /// # Errors
+    ///
+    /// Will return an error message if the history file cannot be written.
+    pub fn save(&self) -> Result<(), String> {
+        let dir = config::data_dir().ok_or("Could not find the data directory")?;
+        let path = dir.join(HISTORY_FILE);
+        let contents = toml::to_string(&self.entries).map_err(|error| error.to_string())?;
+
+        fs::create_dir_all(&dir)
+            .and_then(|()| fs::write(&path, contents))
+            .map_err(|error| format!("Could not write {}: {}", path.display(), error))
+    }
+
+    #[must_use]
+    pub fn entries(&self, kind: PromptKind) -> &[String] {
+        self.entries.get(kind.name()).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds `entry` as the most recent answer, removing an earlier copy of it.
+    pub fn add(&mut self, kind: PromptKind, entry: &str) {
+        if entry.is_empty() {
+            return;
+        }
+
+        let entries = self.entries.entry(kind.name().to_owned()).or_default();
+        entries.retain(|existing| existing != entry);
+        entries.push(entry.to_owned());
+        if entries.len() > MAX_ENTRIES {
+            entries.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_answers_move_to_the_end() {
+        let mut history = PromptHistory::default();
+        history.add(PromptKind::Search, "fn");
+        history.add(PromptKind::Search, "");
+        history.add(PromptKind::Search, "let");
+        history.add(PromptKind::Search, "fn");
+        history.add(PromptKind::Goto, "12");
+        assert_eq!(history.entries(PromptKind::Search), ["let", "fn"]);
+        assert_eq!(history.entries(PromptKind::Goto), ["12"]);
+        assert!(history.entries(PromptKind::File).is_empty());
+    }
+
+    #[test]
+    fn the_oldest_answers_are_dropped() {
+        let mut history = PromptHistory::default();
+        for answer in 0..=MAX_ENTRIES {
+            history.add(PromptKind::Macro, &answer.to_string());
+        }
+        let entries = history.entries(PromptKind::Macro);
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries.first().map(String::as_str), Some("1"));
+    }
+}
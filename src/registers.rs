@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+pub const UNNAMED: char = '"';
+pub const YANK: char = '0';
+pub const BLACK_HOLE: char = '_';
+
+/// Linewise registers hold whole lines joined by newlines, without a
+/// trailing one.
+#[derive(Default, Clone)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
+
+#[derive(Default)]
+pub struct Registers {
+    registers: HashMap<char, Register>,
+}
+
+impl Registers {
+    #[must_use]
+    pub fn get(&self, name: char) -> Option<&Register> {
+        self.registers.get(&name.to_ascii_lowercase())
+    }
+
+    /// Stores a yanked text in `name`, the yank register and the unnamed one.
+    pub fn yank(&mut self, name: char, register: Register) {
+        if name != UNNAMED {
+            self.store(name, register.clone());
+        }
+        if name != BLACK_HOLE {
+            self.store(YANK, register.clone());
+            self.store(UNNAMED, register);
+        }
+    }
+
+    /// Stores a deleted text in `name` and the unnamed register.
+    pub fn delete(&mut self, name: char, register: Register) {
+        if name != UNNAMED {
+            self.store(name, register.clone());
+        }
+        if name != BLACK_HOLE {
+            self.store(UNNAMED, register);
+        }
+    }
+
+    /// Uppercase names append to the matching lowercase register, as in vi.
+    fn store(&mut self, name: char, register: Register) {
+        if name == BLACK_HOLE {
+            return;
+        }
+
+        if name.is_ascii_uppercase() {
+            let existing = self.registers.entry(name.to_ascii_lowercase()).or_default();
+            if !existing.text.is_empty() && (existing.linewise || register.linewise) {
+                existing.text.push('\n');
+            }
+            existing.text.push_str(&register.text);
+            existing.linewise |= register.linewise;
+        } else {
+            self.registers.insert(name, register);
+        }
+    }
+}
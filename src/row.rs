@@ -1,13 +1,24 @@
-use std::cmp;
-use termion::color;
+use core::{cmp, ops::Range};
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{highlighting, HighlightingOptions, SearchDirection};
+use crate::{
+    highlighting::{self, State},
+    HighlightingOptions, Theme,
+};
+
+/// How many characters after an escaped one are searched for the closing quote
+/// of a character literal, enough for `'\u{10FFFF}'`.
+const MAX_ESCAPE_LEN: usize = 9;
 
 #[derive(Default)]
 pub struct Row {
     string: String,
     highlighting: Vec<highlighting::Type>,
+    /// Whether `highlighting` is out of date with `string`.
+    stale: bool,
+    /// The state the row was highlighted from, and the one it ends in.
+    start_state: State,
+    end_state: State,
     len: usize,
 }
 
@@ -16,6 +27,9 @@ impl From<&str> for Row {
         Self {
             string: String::from(slice),
             highlighting: Vec::new(),
+            stale: true,
+            start_state: State::Normal,
+            end_state: State::Normal,
             len: slice.graphemes(true).count(),
         }
     }
@@ -23,12 +37,22 @@ impl From<&str> for Row {
 
 impl Row {
     #[must_use]
-    pub fn render(&self, start: usize, end: usize) -> String {
+    /// Renders the graphemes in `start..end` in the styles of `theme`, with
+    /// the `selection` and the `matches` of a search drawn over them.
+    #[inline]
+    pub fn render(
+        &self,
+        theme: &Theme,
+        start: usize,
+        end: usize,
+        selection: Option<&Range<usize>>,
+        matches: &[Range<usize>],
+    ) -> String {
         let end = cmp::min(end, self.string.len());
         let start = cmp::min(start, end);
 
         let mut result = String::new();
-        let mut current_highlighting = &highlighting::Type::None;
+        let mut current_style = None;
 
         #[allow(clippy::integer_arithmetic)]
         for (index, grapheme) in self.string[..]
@@ -38,16 +62,23 @@ impl Row {
             .take(end - start)
         {
             if let Some(c) = grapheme.chars().next() {
-                let highlighting_type = self
-                    .highlighting
-                    .get(index)
-                    .unwrap_or(&highlighting::Type::None);
-
-                if current_highlighting != highlighting_type {
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()));
-                    result.push_str(&start_highlight);
-                    current_highlighting = highlighting_type
+                let highlighting_type = if matches.iter().any(|range| range.contains(&index)) {
+                    highlighting::Type::Match
+                } else {
+                    self.highlighting
+                        .get(index)
+                        .copied()
+                        .unwrap_or(highlighting::Type::None)
+                };
+
+                let style = if selection.is_some_and(|range| range.contains(&index)) {
+                    theme.selection(highlighting_type)
+                } else {
+                    theme.highlight(highlighting_type)
+                };
+                if current_style != Some(style) {
+                    result.push_str(&style.to_string());
+                    current_style = Some(style);
                 }
 
                 if c == '\t' {
@@ -58,12 +89,12 @@ impl Row {
             }
         }
 
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
-        result.push_str(&end_highlight);
+        result.push_str(&theme.text().to_string());
         result
     }
 
     pub fn insert(&mut self, at: usize, c: char) {
+        self.stale = true;
         if at >= self.len() {
             self.string.push(c);
             self.len += 1;
@@ -85,6 +116,7 @@ impl Row {
     }
 
     pub fn delete(&mut self, at: usize) {
+        self.stale = true;
         if at >= self.len() {
             return;
         }
@@ -101,12 +133,35 @@ impl Row {
         self.string = result;
     }
 
+    #[inline]
+    pub fn insert_str(&mut self, at: usize, text: &str) {
+        self.stale = true;
+        let index = self.byte_index(at);
+        self.string.insert_str(index, text);
+        self.len = self.string.graphemes(true).count();
+    }
+
+    #[inline]
+    pub fn delete_range(&mut self, start: usize, end: usize) {
+        self.stale = true;
+        let start = self.byte_index(start);
+        let end = self.byte_index(end);
+        if start >= end {
+            return;
+        }
+
+        self.string.replace_range(start..end, "");
+        self.len = self.string.graphemes(true).count();
+    }
+
     pub fn append(&mut self, new: &Self) {
+        self.stale = true;
         self.string = format!("{}{}", self.string, new.string);
         self.len += new.len;
     }
 
     pub fn split(&mut self, at: usize) -> Self {
+        self.stale = true;
         let mut row: String = String::new();
         let mut length = 0;
         let mut splitted_row = String::new();
@@ -127,110 +182,513 @@ impl Row {
         Self {
             string: splitted_row,
             highlighting: Vec::new(),
+            stale: true,
+            start_state: State::Normal,
+            end_state: State::Normal,
             len: splitted_length,
         }
     }
 
-    #[must_use]
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
-        if at > self.len || query.is_empty() {
-            return None;
-        }
+    /// Highlights the row, marking the grapheme ranges in `matches` as search
+    /// matches, from the `start` state the row above ended in.
+    #[inline]
+    pub fn highlight(
+        &mut self,
+        opts: &HighlightingOptions,
+        matches: &[Range<usize>],
+        start: State,
+    ) {
+        self.highlighting = Vec::new();
+        let chars: Vec<char> = self.string.chars().collect();
 
-        let (start, end) = if direction == SearchDirection::Forward {
-            (at, self.len)
+        let mut index = 0;
+        let mut state = start;
+        // Results lines start with `path:line:col: `.
+        let location_len = if opts.locations() {
+            self.string
+                .match_indices(": ")
+                .find(|&(end, _)| {
+                    self.string
+                        .get(..end)
+                        .is_some_and(|before| before.ends_with(|ch: char| ch.is_ascii_digit()))
+                })
+                .map_or(0, |(end, _)| {
+                    self.string
+                        .get(..end)
+                        .map_or(0, |before| before.chars().count().saturating_add(1))
+                })
         } else {
-            (0, at)
+            0
         };
+        // Keys start lines, after any indentation and list marker as in YAML.
+        let mut key_start = chars.iter().take_while(|ch| ch.is_whitespace()).count();
+        if chars.get(key_start..key_start.saturating_add(2)) == Some(&['-', ' ']) {
+            key_start = chars
+                .iter()
+                .skip(key_start.saturating_add(2))
+                .position(|ch| !ch.is_whitespace())
+                .map_or(chars.len(), |offset| {
+                    offset.saturating_add(key_start).saturating_add(2)
+                });
+        }
 
-        #[allow(clippy::integer_arithmetic)]
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
+        while let Some(c) = chars.get(index) {
+            if matches.iter().any(|range| range.contains(&index)) {
+                index = index.saturating_add(1);
+                self.highlighting.push(highlighting::Type::Match);
+                continue;
+            }
+            if index < location_len {
+                index = index.saturating_add(1);
+                self.highlighting.push(highlighting::Type::Location);
+                continue;
+            }
 
-        let matching_byte_index = if direction == SearchDirection::Forward {
-            substring.find(query)
-        } else {
-            substring.rfind(query)
-        };
+            match state {
+                State::Comment { depth, doc } => {
+                    state = self.highlight_block_comment(&mut index, opts, &chars, depth, doc);
+                    continue;
+                }
+                State::String(delimiter) => {
+                    state =
+                        self.highlight_multiline_string(&mut index, opts, &chars, delimiter, false);
+                    continue;
+                }
+                State::Fence(fence) => {
+                    state = self.highlight_fence(&mut index, opts, &chars, Some(fence));
+                    continue;
+                }
+                State::Normal => (),
+            }
+            if index == 0 {
+                let fence = self.highlight_fence(&mut index, opts, &chars, None);
+                if fence != State::Normal {
+                    state = fence;
+                    continue;
+                }
+                if self.highlight_heading(&mut index, opts, &chars) {
+                    continue;
+                }
+            }
+            if let Some((len, doc)) = block_comment_start(opts, &chars, index) {
+                self.highlight_run(&mut index, len, comment_type(doc));
+                state = State::Comment { depth: 1, doc };
+                continue;
+            }
 
-        if let Some(index) = matching_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring[..].grapheme_indices(true).enumerate()
+            if let Some(delimiter) = opts.multiline_strings().iter().position(|delimiter| {
+                starts_with(chars.get(index..).unwrap_or_default(), delimiter)
+            }) {
+                state = self.highlight_multiline_string(&mut index, opts, &chars, delimiter, true);
+                continue;
+            }
+            if (index == key_start && self.highlight_key(&mut index, opts, &chars))
+                || self.highlight_char(&mut index, opts, *c, &chars)
+                || self.highlight_string(&mut index, opts, &chars)
+                || self.highlight_variable(&mut index, opts, &chars)
+                || self.highlight_line_comment(&mut index, opts, &chars)
+                || self.highlight_keywords(&mut index, opts, &chars)
+                || self.highlight_number(&mut index, opts, *c, &chars)
             {
-                if index == byte_index {
-                    #[allow(clippy::integer_arithmetic)]
-                    return Some(start + grapheme_index);
-                }
+                continue;
             }
+            self.highlighting.push(highlighting::Type::None);
+            index = index.saturating_add(1);
         }
 
-        None
+        self.start_state = start;
+        self.end_state = state;
+        self.stale = false;
     }
 
-    pub fn highlight(&mut self, opts: HighlightingOptions, word: Option<&str>) {
-        let mut highlighting = Vec::new();
-        let chars: Vec<char> = self.string.chars().collect();
+    /// The state the row was last highlighted from.
+    #[must_use]
+    #[inline]
+    pub fn start_state(&self) -> State {
+        self.start_state
+    }
+
+    /// The state the row ends in, which the next row starts from.
+    #[must_use]
+    #[inline]
+    pub fn end_state(&self) -> State {
+        self.end_state
+    }
 
-        let mut matches = Vec::new();
+    /// Highlights `len` chars from `index` as `kind` and moves past them.
+    fn highlight_run(&mut self, index: &mut usize, len: usize, kind: highlighting::Type) {
+        for _ in 0..len {
+            self.highlighting.push(kind);
+        }
+        *index = index.saturating_add(len);
+    }
 
-        let mut search_index = 0;
+    /// Highlights a character literal such as `'a'` or `'\n'`. In Rust, a
+    /// quote followed by a word that isn't closed is a lifetime instead.
+    fn highlight_char(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        current: char,
+        chars: &[char],
+    ) -> bool {
+        if !opts.characters() || current != '\'' {
+            return false;
+        }
+        let rest = chars.get(index.saturating_add(1)..).unwrap_or_default();
+        let char_len = match *rest {
+            // The escaped character may be a quote, and the escape may be as
+            // long as `\u{10FFFF}`.
+            ['\\', _, ..] => rest
+                .iter()
+                .skip(2)
+                .take(MAX_ESCAPE_LEN)
+                .position(|ch| *ch == '\'')
+                .map(|end| end.saturating_add(4)),
+            [inner, '\'', ..] if inner != '\'' => Some(3),
+            _ => None,
+        };
+        if let Some(len) = char_len {
+            self.highlight_run(index, len, highlighting::Type::Character);
+            return true;
+        }
+        if opts.lifetimes() && rest.first().is_some_and(|ch| is_word_char(*ch)) {
+            let len = rest
+                .iter()
+                .position(|ch| !is_word_char(*ch))
+                .unwrap_or(rest.len())
+                .saturating_add(1);
+            self.highlight_run(index, len, highlighting::Type::Lifetime);
+            return true;
+        }
+        false
+    }
 
-        if let Some(word) = word {
-            while let Some(search_match) = self.find(word, search_index, SearchDirection::Forward) {
-                matches.push(search_match);
-                if let Some(next_index) = search_match.checked_add(word[..].graphemes(true).count())
+    /// Highlights a string literal up to its closing delimiter, or to the end
+    /// of the row when it isn't closed, skipping escaped characters. A string
+    /// followed by the key token is a key.
+    fn highlight_string(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+    ) -> bool {
+        let rest = chars.get(*index..).unwrap_or_default();
+        let Some(delimiter) = opts
+            .strings()
+            .iter()
+            .find(|delimiter| starts_with(rest, delimiter))
+        else {
+            return false;
+        };
+        let delimiter_len = delimiter.chars().count();
+        let len = string_len(rest, delimiter_len, delimiter).unwrap_or(rest.len());
+
+        let after = rest.get(len..).unwrap_or_default();
+        if key_follows(opts, after) {
+            self.highlight_run(index, len, highlighting::Type::Key);
+            return true;
+        }
+        let start = *index;
+        self.highlight_run(index, len, highlighting::Type::String);
+        if opts.variables() && delimiter == "\"" {
+            let mut at = delimiter_len;
+            while at < len {
+                let inside = rest.get(at..len).unwrap_or_default();
+                let variable = variable_len(inside).unwrap_or(0);
+                for kind in self
+                    .highlighting
+                    .iter_mut()
+                    .skip(start.saturating_add(at))
+                    .take(variable)
                 {
-                    search_index = next_index;
-                } else {
-                    break;
+                    *kind = highlighting::Type::Variable;
                 }
+                let skip = if inside.first() == Some(&'\\') { 2 } else { 1 };
+                at = at.saturating_add(variable.max(skip));
             }
         }
+        true
+    }
 
-        let mut prev_is_separator = true;
-        let mut index = 0;
+    /// Highlights a string that may span lines, opened by the multi-line
+    /// string delimiter at `delimiter`: from its opening delimiter at `index`
+    /// if `opens`, or from the start of the row the row above left it open
+    /// at. Returns the state after it.
+    fn highlight_multiline_string(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+        delimiter: usize,
+        opens: bool,
+    ) -> State {
+        let Some(token) = opts.multiline_strings().get(delimiter) else {
+            return State::Normal;
+        };
+        let rest = chars.get(*index..).unwrap_or_default();
+        let from = if opens { token.chars().count() } else { 0 };
+        let (len, state) = match string_len(rest, from, token) {
+            Some(len) => (len, State::Normal),
+            None => (rest.len(), State::String(delimiter)),
+        };
+        self.highlight_run(index, len, highlighting::Type::String);
+        state
+    }
 
-        while let Some(c) = chars.get(index) {
-            if let Some(word) = word {
-                if matches.contains(&index) {
-                    for _ in word[..].graphemes(true) {
-                        index += 1;
-                        highlighting.push(highlighting::Type::Match);
-                    }
-                    continue;
-                }
+    /// Highlights `$NAME`, `${NAME}` or `$1` in file types with variables.
+    fn highlight_variable(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+    ) -> bool {
+        if !opts.variables() {
+            return false;
+        }
+        match variable_len(chars.get(*index..).unwrap_or_default()) {
+            Some(len) => {
+                self.highlight_run(index, len, highlighting::Type::Variable);
+                true
             }
+            None => false,
+        }
+    }
 
-            let previous_highlight = if index > 0 {
-                highlighting
-                    .get(index - 1)
-                    .unwrap_or(&highlighting::Type::None)
-            } else {
-                &highlighting::Type::None
-            };
+    /// Highlights a word starting the line, after any indentation and list
+    /// marker, as a key when the key token follows it, as in `name: value`.
+    fn highlight_key(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+    ) -> bool {
+        let before = chars.get(..*index).unwrap_or_default();
+        let starts_line =
+            before.iter().all(|ch| ch.is_whitespace() || *ch == '-') && !before.ends_with(&['-']);
+        if opts.keys().is_none() || !starts_line {
+            return false;
+        }
+        let rest = chars.get(*index..).unwrap_or_default();
+        let len = rest
+            .iter()
+            .position(|ch| !is_word_char(*ch) && *ch != '-' && *ch != '.')
+            .unwrap_or(rest.len());
+        if len == 0 || !key_follows(opts, rest.get(len..).unwrap_or_default()) {
+            return false;
+        }
+        self.highlight_run(index, len, highlighting::Type::Key);
+        true
+    }
 
-            if opts.numbers() {
-                let highlight_number = (c.is_ascii_digit()
-                    && (prev_is_separator || previous_highlight == &highlighting::Type::Number))
-                    || (c == &'.' && previous_highlight == &highlighting::Type::Number);
+    /// Highlights the whole row as a heading if it starts with a heading
+    /// token after any indentation.
+    fn highlight_heading(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+    ) -> bool {
+        let indent = chars.iter().take_while(|ch| ch.is_whitespace()).count();
+        let rest = chars.get(indent..).unwrap_or_default();
+        if !opts.headings().iter().any(|token| starts_with(rest, token)) {
+            return false;
+        }
+        self.highlight_run(index, chars.len(), highlighting::Type::Heading);
+        true
+    }
 
-                if highlight_number {
-                    highlighting.push(highlighting::Type::Number);
-                } else {
-                    highlighting.push(highlighting::Type::None);
+    /// Highlights the whole row as code if it is inside the fenced code block
+    /// opened by the fence at `open`, or if it opens one. Returns the state
+    /// after the row: in the block unless the row closes it.
+    fn highlight_fence(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+        open: Option<usize>,
+    ) -> State {
+        let indent = chars.iter().take_while(|ch| ch.is_whitespace()).count();
+        let rest = chars.get(indent..).unwrap_or_default();
+        let fence = opts
+            .code_fences()
+            .iter()
+            .position(|token| starts_with(rest, token));
+        let state = match (open, fence) {
+            (Some(open), Some(fence)) if open == fence => State::Normal,
+            (Some(open), _) => State::Fence(open),
+            (None, Some(fence)) => State::Fence(fence),
+            (None, None) => return State::Normal,
+        };
+        self.highlight_run(index, chars.len(), highlighting::Type::String);
+        state
+    }
+
+    /// Highlights a line comment through the end of the row.
+    fn highlight_line_comment(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+    ) -> bool {
+        let rest = chars.get(*index..).unwrap_or_default();
+        if !opts
+            .line_comments()
+            .iter()
+            .any(|token| starts_with(rest, token))
+        {
+            return false;
+        }
+        self.highlight_run(index, rest.len(), comment_type(is_doc_comment(opts, rest)));
+        true
+    }
+
+    /// Highlights the inside of a block comment nested `depth` deep, up to
+    /// the token closing it or the end of the row, and returns the state after.
+    fn highlight_block_comment(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+        mut depth: usize,
+        doc: bool,
+    ) -> State {
+        let Some((start, end)) = opts.block_comment() else {
+            return State::Normal;
+        };
+        let kind = comment_type(doc);
+        while let Some(rest) = chars.get(*index..).filter(|rest| !rest.is_empty()) {
+            if starts_with(rest, end) {
+                self.highlight_run(index, end.chars().count(), kind);
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return State::Normal;
                 }
+            } else if opts.nested_comments() && starts_with(rest, start) {
+                self.highlight_run(index, start.chars().count(), kind);
+                depth = depth.saturating_add(1);
             } else {
-                highlighting.push(highlighting::Type::None);
+                self.highlight_run(index, 1, kind);
             }
+        }
+        State::Comment { depth, doc }
+    }
 
-            prev_is_separator = c.is_ascii_punctuation() || c.is_ascii_whitespace();
-            index += 1;
+    /// Highlights a keyword of either set starting at `index`, as a whole
+    /// word between separators.
+    fn highlight_keywords(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        chars: &[char],
+    ) -> bool {
+        if !follows_separator(chars, *index) {
+            return false;
         }
+        let rest = chars.get(*index..).unwrap_or_default();
+        for (keywords, kind) in [
+            (opts.primary_keywords(), highlighting::Type::PrimaryKeywords),
+            (
+                opts.secondary_keywords(),
+                highlighting::Type::SecondaryKeywords,
+            ),
+        ] {
+            let found = keywords.iter().find_map(|keyword| {
+                let mut len = 0;
+                for ch in keyword.chars() {
+                    if rest.get(len) != Some(&ch) {
+                        return None;
+                    }
+                    len = len.saturating_add(1);
+                }
+                rest.get(len)
+                    .is_none_or(|ch| is_separator(*ch))
+                    .then_some(len)
+            });
+            if let Some(len) = found {
+                self.highlight_run(index, len, kind);
+                return true;
+            }
+        }
+        false
+    }
 
-        self.highlighting = highlighting;
+    /// Highlights a number starting after a separator: digits with dots
+    /// between them, and the prefix, separators and suffix its file type's
+    /// rules allow.
+    fn highlight_number(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        current: char,
+        chars: &[char],
+    ) -> bool {
+        let rules = match opts.numbers() {
+            Some(rules) if current.is_ascii_digit() => rules,
+            _ => return false,
+        };
+        if !follows_separator(chars, *index) {
+            return false;
+        }
+        let rest = chars.get(*index..).unwrap_or_default();
+        let prefixed =
+            rules.prefixes() && matches!(rest, ['0', 'x' | 'X' | 'o' | 'O' | 'b' | 'B', ..]);
+        let mut len = if prefixed { 2 } else { 0 };
+        while let Some(ch) = rest.get(len) {
+            let digit = if prefixed {
+                ch.is_ascii_hexdigit()
+            } else {
+                ch.is_ascii_digit()
+            };
+            // A dot only belongs to the number when a digit follows, unlike
+            // in `1..10` or `1.max(2)`.
+            let point = !prefixed
+                && *ch == '.'
+                && rest
+                    .get(len.saturating_add(1))
+                    .is_some_and(char::is_ascii_digit);
+            if !digit && !point && Some(*ch) != rules.separator() {
+                break;
+            }
+            len = len.saturating_add(1);
+        }
+        if rules.suffixes() {
+            while rest.get(len).is_some_and(|ch| is_word_char(*ch)) {
+                len = len.saturating_add(1);
+            }
+        }
+        self.highlight_run(index, len, highlighting::Type::Number);
+        true
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Highlights the row with `spans`, byte ranges of its text found by a
+    /// parser, the later of two overlapping spans winning.
+    #[inline]
+    pub fn highlight_spans(&mut self, spans: &[(Range<usize>, highlighting::Type)]) {
+        self.highlighting = self
+            .string
+            .grapheme_indices(true)
+            .map(|(byte, _)| {
+                spans
+                    .iter()
+                    .rev()
+                    .find(|span| span.0.contains(&byte))
+                    .map_or(highlighting::Type::None, |span| span.1)
+            })
+            .collect();
+        self.stale = false;
+    }
+
+    /// Marks the highlighting as out of date, as when the file type changed.
+    #[inline]
+    pub fn invalidate_highlighting(&mut self) {
+        self.stale = true;
     }
 
     #[must_use]
@@ -247,4 +705,236 @@ impl Row {
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
     }
+
+    #[must_use]
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    /// How the grapheme at `at` was last highlighted.
+    #[must_use]
+    #[inline]
+    pub fn highlighting_at(&self, at: usize) -> Option<highlighting::Type> {
+        self.highlighting.get(at).copied()
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn grapheme(&self, at: usize) -> Option<&str> {
+        self.string.graphemes(true).nth(at)
+    }
+
+    /// Returns the graphemes in `start..end`, clamped to the row length.
+    #[must_use]
+    #[inline]
+    pub fn substring(&self, start: usize, end: usize) -> &str {
+        let start = self.byte_index(start);
+        let end = cmp::max(start, self.byte_index(end));
+        self.string.get(start..end).unwrap_or_default()
+    }
+
+    /// The number of graphemes starting before the byte at `index`.
+    #[must_use]
+    #[inline]
+    pub fn grapheme_index(&self, index: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .take_while(|&(start, _)| start < index)
+            .count()
+    }
+
+    /// The byte offset of the grapheme at `at`, or the row length past its end.
+    #[must_use]
+    #[inline]
+    pub fn byte_index(&self, at: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
+}
+
+/// Whether `ch` ends a word, so a number or keyword may start after it. An
+/// underscore is part of a word, so `fn` in `my_fn` isn't a keyword.
+fn is_separator(ch: char) -> bool {
+    (ch.is_ascii_punctuation() && ch != '_') || ch.is_ascii_whitespace()
+}
+
+/// Whether the char at `index` starts the row or follows a separator.
+fn follows_separator(chars: &[char], index: usize) -> bool {
+    index
+        .checked_sub(1)
+        .and_then(|prev| chars.get(prev))
+        .is_none_or(|ch| is_separator(*ch))
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// The length of the string starting `rest`, from `from` up to and including
+/// the closing `delimiter`, skipping escaped characters; `None` if it isn't
+/// closed on the row.
+fn string_len(rest: &[char], from: usize, delimiter: &str) -> Option<usize> {
+    let mut len = from;
+    while let Some(ch) = rest.get(len) {
+        if starts_with(rest.get(len..).unwrap_or_default(), delimiter) {
+            return Some(len.saturating_add(delimiter.chars().count()));
+        }
+        len = len.saturating_add(if *ch == '\\' { 2 } else { 1 });
+    }
+    None
+}
+
+/// Whether the key token follows `rest`, after any blanks.
+fn key_follows(opts: &HighlightingOptions, rest: &[char]) -> bool {
+    let blanks = rest
+        .iter()
+        .take_while(|ch| **ch == ' ' || **ch == '\t')
+        .count();
+    opts.keys()
+        .is_some_and(|token| starts_with(rest.get(blanks..).unwrap_or_default(), token))
+}
+
+/// The length of the variable starting `rest`: `$NAME`, `${NAME}`, or `$`
+/// followed by a digit or one of `@*#?$!-`.
+fn variable_len(rest: &[char]) -> Option<usize> {
+    match *rest {
+        ['$', '{', ..] => Some(
+            rest.iter()
+                .skip(2)
+                .position(|ch| *ch == '}')
+                .map_or(rest.len(), |end| end.saturating_add(3)),
+        ),
+        ['$', ch, ..] if ch.is_ascii_digit() || "@*#?$!-".contains(ch) => Some(2),
+        ['$', ch, ..] if ch.is_alphabetic() || ch == '_' => Some(
+            rest.iter()
+                .skip(1)
+                .position(|next| !is_word_char(*next))
+                .map_or(rest.len(), |end| end.saturating_add(1)),
+        ),
+        _ => None,
+    }
+}
+
+/// Whether a block comment opens at `index`, and if so the length of the
+/// token opening it and whether it is a doc comment.
+fn block_comment_start(
+    opts: &HighlightingOptions,
+    chars: &[char],
+    index: usize,
+) -> Option<(usize, bool)> {
+    let (start, end) = opts.block_comment()?;
+    let rest = chars.get(index..).unwrap_or_default();
+    if !starts_with(rest, start) {
+        return None;
+    }
+    let len = start.chars().count();
+    // A comment closed right away, as `/**/`, is a plain one.
+    let empty = starts_with(rest.get(len..).unwrap_or_default(), end);
+    Some((len, !empty && is_doc_comment(opts, rest)))
+}
+
+/// Whether the comment starting `rest` is a doc comment: it starts with a
+/// doc comment token that isn't followed by its last character again.
+fn is_doc_comment(opts: &HighlightingOptions, rest: &[char]) -> bool {
+    opts.doc_comments().iter().any(|token| {
+        let len = token.chars().count();
+        starts_with(rest, token) && rest.get(len).copied() != token.chars().last()
+    })
+}
+
+fn starts_with(chars: &[char], token: &str) -> bool {
+    !token.is_empty()
+        && chars
+            .iter()
+            .copied()
+            .take(token.chars().count())
+            .eq(token.chars())
+}
+
+fn comment_type(doc: bool) -> highlighting::Type {
+    if doc {
+        highlighting::Type::DocComment
+    } else {
+        highlighting::Type::Comment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileTypes;
+
+    fn rust() -> HighlightingOptions {
+        FileTypes::default()
+            .detect("main.rs", &[])
+            .highlighting_options()
+            .clone()
+    }
+
+    /// One letter for each grapheme of `lines`, highlighted one after the
+    /// other as Rust.
+    fn marks(lines: &[&str]) -> Vec<String> {
+        let opts = rust();
+        let mut state = State::Normal;
+        lines
+            .iter()
+            .map(|line| {
+                let mut row = Row::from(*line);
+                row.highlight(&opts, &[], state);
+                state = row.end_state();
+                row.highlighting.iter().map(|kind| mark(*kind)).collect()
+            })
+            .collect()
+    }
+
+    fn mark(kind: highlighting::Type) -> char {
+        match kind {
+            highlighting::Type::Number => 'n',
+            highlighting::Type::String => 's',
+            highlighting::Type::Character => 'c',
+            highlighting::Type::Lifetime => 'l',
+            highlighting::Type::Comment => '/',
+            highlighting::Type::DocComment => 'd',
+            highlighting::Type::PrimaryKeywords => 'k',
+            highlighting::Type::SecondaryKeywords => 't',
+            _ => '.',
+        }
+    }
+
+    #[test]
+    fn graphemes_map_to_bytes_and_back() {
+        let row = Row::from("a\u{e9}e\u{301}b");
+        assert_eq!(row.len(), 4);
+        assert_eq!(row.byte_index(2), 3);
+        assert_eq!(row.byte_index(3), 6);
+        assert_eq!(row.byte_index(9), 7);
+        assert_eq!(row.grapheme_index(3), 2);
+        assert_eq!(row.grapheme_index(4), 3);
+        assert_eq!(row.grapheme_index(7), 4);
+    }
+
+    #[test]
+    fn strings_and_characters_are_highlighted() {
+        assert_eq!(marks(&[r#"x = "a\"b" + 1;"#]), ["....ssssss...n."]);
+        assert_eq!(marks(&[r"'a' '\n' '\u{e9}'"]), ["ccc.cccc.cccccccc"]);
+        assert_eq!(marks(&["&'a x"]), [".ll.."]);
+    }
+
+    #[test]
+    fn comments_are_highlighted_across_rows() {
+        assert_eq!(
+            marks(&["x // y", "/// doc", "/* a", "b /* c */", "d */ 1"]),
+            ["..////", "ddddddd", "////", "/////////", "////.n"]
+        );
+        assert_eq!(marks(&["\"/* not\"", "1"]), ["ssssssss", "n"]);
+    }
+
+    #[test]
+    fn keywords_are_highlighted() {
+        assert_eq!(marks(&["fn main() -> u32 {"]), ["kk...........ttt.."]);
+        assert_eq!(marks(&["my_fn fnord"]), ["..........."]);
+    }
 }
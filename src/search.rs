@@ -0,0 +1,282 @@
+use core::{iter, ops::Range};
+
+use regex::{Regex, RegexBuilder};
+
+#[derive(Default, PartialEq, Eq, Clone, Copy)]
+pub enum Case {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Ignores case unless the query has an uppercase letter.
+    Smart,
+}
+
+impl Case {
+    fn toggle(self, case: Self) -> Self {
+        if self == case {
+            Self::Sensitive
+        } else {
+            case
+        }
+    }
+}
+
+/// How the search prompt interprets its query.
+#[derive(Default, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub case: Case,
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    /// The names of the active options, such as `regex`.
+    #[must_use]
+    #[inline]
+    pub fn describe(self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.regex {
+            names.push("regex");
+        }
+        match self.case {
+            Case::Sensitive => (),
+            Case::Insensitive => names.push("nocase"),
+            Case::Smart => names.push("smartcase"),
+        }
+        if self.whole_word {
+            names.push("word");
+        }
+        names
+    }
+
+    /// Toggles the option bound to Alt and `key` in search prompts: `r`egex,
+    /// ignore `c`ase, `s`mart case or whole `w`ord.
+    #[inline]
+    pub fn toggle(&mut self, key: char) {
+        match key {
+            'r' => self.regex = !self.regex,
+            'c' => self.case = self.case.toggle(Case::Insensitive),
+            's' => self.case = self.case.toggle(Case::Smart),
+            'w' => self.whole_word = !self.whole_word,
+            _ => (),
+        }
+    }
+
+    fn ignores_case(self, query: &str) -> bool {
+        match self.case {
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+            Case::Smart => !query.chars().any(char::is_uppercase),
+        }
+    }
+}
+
+/// A compiled search query. Literal queries are escaped into a regex so both
+/// kinds are matched the same way; `^` and `$` match at row boundaries.
+#[derive(Clone)]
+pub struct Matcher {
+    regex: Regex,
+    whole_word: bool,
+    expand: bool,
+}
+
+impl Matcher {
+    /// # Errors
+    ///
+    /// Will return the last line of the regex error message if the query is an
+    /// invalid regular expression.
+    #[inline]
+    pub fn new(query: &str, options: SearchOptions) -> Result<Self, String> {
+        let pattern = if options.regex {
+            query.to_owned()
+        } else {
+            regex::escape(query)
+        };
+
+        RegexBuilder::new(&pattern)
+            .multi_line(true)
+            .case_insensitive(options.ignores_case(query))
+            .build()
+            .map(|regex| Self {
+                regex,
+                whole_word: options.whole_word,
+                expand: options.regex,
+            })
+            .map_err(|error| {
+                error
+                    .to_string()
+                    .lines()
+                    .last()
+                    .unwrap_or_default()
+                    .trim_start_matches("error: ")
+                    .to_owned()
+            })
+    }
+
+    /// The byte range of the first match starting at or after `start`.
+    /// Empty matches, and in whole-word mode matches inside words, are
+    /// skipped.
+    #[must_use]
+    #[inline]
+    pub fn find_at(&self, text: &str, mut start: usize) -> Option<Range<usize>> {
+        while start <= text.len() {
+            let found = self.regex.find_at(text, start)?.range();
+            if !found.is_empty() && (!self.whole_word || is_whole_word(text, &found)) {
+                return Some(found);
+            }
+            start = text
+                .get(found.start..)
+                .and_then(|rest| rest.chars().next())
+                .map_or(usize::MAX, |ch| found.start.saturating_add(ch.len_utf8()));
+        }
+        None
+    }
+
+    /// The byte range of the last match starting before `end`.
+    #[must_use]
+    #[inline]
+    pub fn find_before(&self, text: &str, end: usize) -> Option<Range<usize>> {
+        self.matches(text)
+            .take_while(|range| range.start < end)
+            .last()
+    }
+
+    /// The text replacing the match at `range`. In regex mode, `$1` and
+    /// `${name}` in `replacement` refer to the groups captured by the match.
+    #[must_use]
+    #[inline]
+    pub fn replacement(&self, text: &str, range: &Range<usize>, replacement: &str) -> String {
+        if !self.expand {
+            return replacement.to_owned();
+        }
+
+        let mut expanded = String::new();
+        match self.regex.captures_at(text, range.start) {
+            Some(captures) => captures.expand(replacement, &mut expanded),
+            None => expanded.push_str(replacement),
+        }
+        expanded
+    }
+
+    /// The byte ranges of every match in `text`, without overlaps.
+    #[inline]
+    pub fn matches<'text>(
+        &'text self,
+        text: &'text str,
+    ) -> impl Iterator<Item = Range<usize>> + 'text {
+        let mut start = 0;
+        iter::from_fn(move || {
+            let found = self.find_at(text, start)?;
+            start = found.end;
+            Some(found)
+        })
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Whether `range` is neither preceded nor followed by a word character.
+fn is_whole_word(text: &str, range: &Range<usize>) -> bool {
+    let before = text
+        .get(..range.start)
+        .and_then(|before| before.chars().next_back());
+    let after = text.get(range.end..).and_then(|after| after.chars().next());
+
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex() -> SearchOptions {
+        SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        }
+    }
+
+    fn all(query: &str, options: SearchOptions, text: &str) -> Vec<(usize, usize)> {
+        Matcher::new(query, options)
+            .map(|matcher| {
+                matcher
+                    .matches(text)
+                    .map(|range| (range.start, range.end))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn literal_queries_are_escaped() {
+        assert_eq!(all("a.c", SearchOptions::default(), "abc a.c"), [(4, 7)]);
+        assert!(Matcher::new("(", SearchOptions::default()).is_ok());
+        assert!(Matcher::new("(", regex()).is_err());
+    }
+
+    #[test]
+    fn regex_queries_match_within_rows() {
+        let text = "abb\nbb\nb\n";
+        assert_eq!(all("^b+", regex(), text), [(4, 6), (7, 8)]);
+
+        let matcher = Matcher::new("^b+", regex()).ok();
+        let find_at = |start| {
+            matcher
+                .as_ref()
+                .and_then(|matcher| matcher.find_at(text, start))
+        };
+        let find_before = |end| {
+            matcher
+                .as_ref()
+                .and_then(|matcher| matcher.find_before(text, end))
+        };
+        assert_eq!(find_at(5), Some(7..8));
+        assert_eq!(find_before(7), Some(4..6));
+        assert_eq!(find_before(4), None);
+    }
+
+    #[test]
+    fn empty_matches_are_skipped() {
+        assert_eq!(all("x*", regex(), "ax\u{e9}xx"), [(1, 2), (4, 6)]);
+    }
+
+    #[test]
+    fn case_follows_the_options() {
+        let case = |case| SearchOptions {
+            case,
+            ..SearchOptions::default()
+        };
+        let text = "Word word WORD";
+        assert_eq!(all("word", case(Case::Sensitive), text), [(5, 9)]);
+        assert_eq!(all("word", case(Case::Insensitive), text).len(), 3);
+        assert_eq!(all("word", case(Case::Smart), text).len(), 3);
+        assert_eq!(all("Word", case(Case::Smart), text), [(0, 4)]);
+    }
+
+    #[test]
+    fn whole_words_are_not_matched_inside_others() {
+        let options = SearchOptions {
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let text = "cat concat cat_s cat, cat\u{e9}";
+        assert_eq!(all("cat", options, text), [(0, 3), (17, 20)]);
+    }
+
+    #[test]
+    fn options_toggle_by_key() {
+        let mut options = SearchOptions::default();
+        options.toggle('r');
+        options.toggle('c');
+        options.toggle('w');
+        assert_eq!(options.describe(), ["regex", "nocase", "word"]);
+        options.toggle('s');
+        options.toggle('w');
+        assert_eq!(options.describe(), ["regex", "smartcase"]);
+        options.toggle('s');
+        assert!(options.case == Case::Sensitive);
+    }
+}
@@ -0,0 +1,295 @@
+use core::{convert::TryFrom as _, ops::Range};
+use std::collections::HashSet;
+
+use tree_sitter::{
+    InputEdit, Language, Node, Parser, Point, Query, QueryCursor, StreamingIterator as _, Tree,
+};
+
+use crate::{highlighting, Row};
+
+/// Patterns put before the bundled Rust query, so that doc comments win over
+/// the plain comments it matches first, and lifetimes get their quote colored
+/// along with their name.
+const RUST_HIGHLIGHTS: &str = "
+(line_comment (doc_comment)) @comment.documentation
+(block_comment (doc_comment)) @comment.documentation
+(lifetime \"'\" @label)
+";
+
+/// The grammar called `name` in a file type's `grammar`, with its query for
+/// highlighting, if it is compiled in.
+fn grammar(name: &str) -> Option<(Language, String)> {
+    let (language, query) = match name {
+        "rust" => (
+            tree_sitter_rust::LANGUAGE,
+            format!("{}{}", RUST_HIGHLIGHTS, tree_sitter_rust::HIGHLIGHTS_QUERY),
+        ),
+        "c" => (
+            tree_sitter_c::LANGUAGE,
+            tree_sitter_c::HIGHLIGHT_QUERY.to_owned(),
+        ),
+        "python" => (
+            tree_sitter_python::LANGUAGE,
+            tree_sitter_python::HIGHLIGHTS_QUERY.to_owned(),
+        ),
+        "go" => (
+            tree_sitter_go::LANGUAGE,
+            tree_sitter_go::HIGHLIGHTS_QUERY.to_owned(),
+        ),
+        "javascript" => (
+            tree_sitter_javascript::LANGUAGE,
+            tree_sitter_javascript::HIGHLIGHT_QUERY.to_owned(),
+        ),
+        "bash" => (
+            tree_sitter_bash::LANGUAGE,
+            tree_sitter_bash::HIGHLIGHT_QUERY.to_owned(),
+        ),
+        "json" => (
+            tree_sitter_json::LANGUAGE,
+            tree_sitter_json::HIGHLIGHTS_QUERY.to_owned(),
+        ),
+        _ => return None,
+    };
+    Some((language.into(), query))
+}
+
+/// The highlight group of a capture of a highlight query, such as `keyword`
+/// or `comment.documentation`.
+fn group(capture: &str) -> highlighting::Type {
+    let kind = capture.split('.').next().unwrap_or_default();
+    match (capture, kind) {
+        ("comment.documentation", _) => highlighting::Type::DocComment,
+        ("string.special.key", _) => highlighting::Type::Key,
+        ("property", _) => highlighting::Type::Variable,
+        ("variable.builtin", _) | (_, "keyword") => highlighting::Type::PrimaryKeywords,
+        (_, "type" | "constructor") => highlighting::Type::SecondaryKeywords,
+        (_, "function") => highlighting::Type::Function,
+        (_, "string") => highlighting::Type::String,
+        (_, "escape") => highlighting::Type::Character,
+        (_, "comment") => highlighting::Type::Comment,
+        (_, "number" | "constant") => highlighting::Type::Number,
+        (_, "label") => highlighting::Type::Lifetime,
+        _ => highlighting::Type::None,
+    }
+}
+
+/// A document parsed with tree-sitter, highlighted from its syntax tree
+/// instead of row by row.
+pub struct Syntax {
+    parser: Parser,
+    query: Query,
+    /// The highlight group of each capture of `query`.
+    groups: Vec<highlighting::Type>,
+    tree: Option<Tree>,
+    /// Whether the document was edited since `tree` was parsed.
+    edited: bool,
+    /// The byte offset of each row from the first, counting a newline after
+    /// every row, as far as they are known since the last edit.
+    offsets: Vec<usize>,
+}
+
+impl Syntax {
+    /// A parser for the grammar called `name`, if it is compiled in.
+    #[must_use]
+    pub fn new(name: &str) -> Option<Self> {
+        let (language, source) = grammar(name)?;
+        let mut parser = Parser::new();
+        parser.set_language(&language).ok()?;
+        let query = Query::new(&language, &source).ok()?;
+        let groups = query
+            .capture_names()
+            .iter()
+            .map(|capture| group(capture))
+            .collect();
+        Some(Self {
+            parser,
+            query,
+            groups,
+            tree: None,
+            edited: false,
+            offsets: Vec::new(),
+        })
+    }
+
+    /// Tells the syntax tree about an edit about to replace `removed` with
+    /// `inserted` at byte `column` of row `row` of `rows`, so that only the
+    /// part that changed is parsed again.
+    pub fn edit(&mut self, rows: &[Row], row: usize, column: usize, removed: &str, inserted: &str) {
+        let start_byte = self.offset(rows, row).saturating_add(column);
+        let start_position = Point { row, column };
+        self.offsets.truncate(row.saturating_add(1));
+        self.edited = true;
+
+        if let Some(tree) = self.tree.as_mut() {
+            tree.edit(&InputEdit {
+                start_byte,
+                old_end_byte: start_byte.saturating_add(removed.len()),
+                new_end_byte: start_byte.saturating_add(inserted.len()),
+                start_position,
+                old_end_position: end_point(start_position, removed),
+                new_end_position: end_point(start_position, inserted),
+            });
+        }
+    }
+
+    /// The byte offset of row `row`, from those of the rows above it.
+    fn offset(&mut self, rows: &[Row], row: usize) -> usize {
+        if self.offsets.is_empty() {
+            self.offsets.push(0);
+        }
+        while self.offsets.len() <= row {
+            let last = self.offsets.len().saturating_sub(1);
+            let start = self.offsets.last().copied().unwrap_or(0);
+            let len = rows.get(last).map_or(0, |above| above.as_str().len());
+            self.offsets
+                .push(start.saturating_add(len).saturating_add(1));
+        }
+        self.offsets.get(row).copied().unwrap_or(0)
+    }
+
+    /// Parses `rows` again if they were edited since they were last parsed,
+    /// reusing what the edits left of the old tree.
+    pub fn update(&mut self, rows: &[Row]) {
+        if self.tree.is_some() && !self.edited {
+            return;
+        }
+        let mut read = |_, point: Point| -> &[u8] {
+            match rows.get(point.row) {
+                Some(row) => row
+                    .as_bytes()
+                    .get(point.column..)
+                    .filter(|rest| !rest.is_empty())
+                    .unwrap_or(b"\n"),
+                None => &[],
+            }
+        };
+        self.tree = self
+            .parser
+            .parse_with_options(&mut read, self.tree.as_ref(), None);
+        self.edited = false;
+    }
+
+    /// Highlights the rows in `range` from the syntax tree. A node captured
+    /// by several patterns gets the group of the first one, as tree-sitter
+    /// queries expect, and a node inside another is drawn over it.
+    pub fn highlight(&self, rows: &mut [Row], range: Range<usize>) {
+        let Some(tree) = self.tree.as_ref() else {
+            return;
+        };
+        let mut spans: Vec<Vec<(Range<usize>, highlighting::Type)>> = vec![Vec::new(); range.len()];
+        {
+            let source: &[Row] = rows;
+            let text = |node: Node<'_>| node_text(source, node).into_iter();
+            let mut seen = HashSet::new();
+            let mut cursor = QueryCursor::new();
+            cursor.set_point_range(
+                Point {
+                    row: range.start,
+                    column: 0,
+                }..Point {
+                    row: range.end,
+                    column: 0,
+                },
+            );
+            let mut captures = cursor.captures(&self.query, tree.root_node(), text);
+            while let Some(next) = captures.next() {
+                let capture = match next.0.captures.get(next.1) {
+                    Some(capture) if seen.insert(capture.node.id()) => capture,
+                    _ => continue,
+                };
+                let kind = usize::try_from(capture.index)
+                    .ok()
+                    .and_then(|position| self.groups.get(position))
+                    .copied()
+                    .unwrap_or(highlighting::Type::None);
+                let (start, end) = (capture.node.start_position(), capture.node.end_position());
+                for (y, row_spans) in spans.iter_mut().enumerate() {
+                    let y = y.saturating_add(range.start);
+                    if y < start.row || y > end.row {
+                        continue;
+                    }
+                    let from = if y == start.row { start.column } else { 0 };
+                    let to = if y == end.row { end.column } else { usize::MAX };
+                    if from < to {
+                        row_spans.push((from..to, kind));
+                    }
+                }
+            }
+        }
+
+        for (row, row_spans) in rows.iter_mut().skip(range.start).zip(&spans) {
+            row.highlight_spans(row_spans);
+        }
+    }
+}
+
+/// The text of `node`, as the parts of the rows it spans.
+fn node_text<'rows>(rows: &'rows [Row], node: Node<'_>) -> Vec<&'rows [u8]> {
+    let (start, end) = (node.start_position(), node.end_position());
+    let mut parts = Vec::new();
+    for (y, row) in rows
+        .iter()
+        .enumerate()
+        .take(end.row.saturating_add(1))
+        .skip(start.row)
+    {
+        let bytes = row.as_bytes();
+        let from = if y == start.row { start.column } else { 0 };
+        let to = if y == end.row {
+            end.column
+        } else {
+            bytes.len()
+        };
+        if y > start.row {
+            parts.push(&b"\n"[..]);
+        }
+        parts.push(bytes.get(from..to.min(bytes.len())).unwrap_or_default());
+    }
+    parts
+}
+
+/// Where `text` ends when it is inserted at `start`.
+fn end_point(start: Point, text: &str) -> Point {
+    match text.rfind('\n') {
+        Some(newline) => Point {
+            row: start.row.saturating_add(text.matches('\n').count()),
+            column: text.len().saturating_sub(newline).saturating_sub(1),
+        },
+        None => Point {
+            row: start.row,
+            column: start.column.saturating_add(text.len()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiled_grammars_have_queries() {
+        for name in &["rust", "c", "python", "go", "javascript", "bash", "json"] {
+            assert!(Syntax::new(name).is_some(), "{}", name);
+        }
+        assert!(Syntax::new("cobol").is_none());
+    }
+
+    #[test]
+    fn captures_map_to_highlight_groups() {
+        assert!(group("comment.documentation") == highlighting::Type::DocComment);
+        assert!(group("comment") == highlighting::Type::Comment);
+        assert!(group("keyword.control") == highlighting::Type::PrimaryKeywords);
+        assert!(group("type.builtin") == highlighting::Type::SecondaryKeywords);
+        assert!(group("string.special.key") == highlighting::Type::Key);
+        assert!(group("punctuation") == highlighting::Type::None);
+    }
+
+    #[test]
+    fn edits_end_where_the_inserted_text_does() {
+        let start = Point { row: 2, column: 4 };
+        assert_eq!(end_point(start, "abc"), Point { row: 2, column: 7 });
+        assert_eq!(end_point(start, "a\nbc\nd"), Point { row: 4, column: 1 });
+        assert_eq!(end_point(start, "ab\n"), Point { row: 3, column: 0 });
+        assert_eq!(end_point(start, ""), start);
+    }
+}
@@ -1,37 +1,109 @@
-use std::io::{self, stdout, Write};
+use core::time::Duration;
+use std::{
+    env,
+    io::{self, stdout, Write as _},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+};
 
+use serde::Deserialize;
 use termion::{
-    color,
     event::Key,
     input::TermRead,
     raw::{IntoRawMode, RawTerminal},
+    style,
 };
 
-use crate::Position;
+use crate::{theme::Style, Position};
 
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
 
+/// How many colors the terminal can show.
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum ColorDepth {
+    /// No colors at all, only bold, italic, underlined and reversed text.
+    #[serde(rename = "none")]
+    None,
+    /// The 16 basic colors, as on the Linux console.
+    #[serde(rename = "16")]
+    Ansi16,
+    /// The 256-color xterm palette.
+    #[serde(rename = "256")]
+    Ansi256,
+    /// Any 24-bit color.
+    #[default]
+    #[serde(rename = "truecolor")]
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// The colors the terminal is said to support by `COLORTERM` and `TERM`,
+    /// or none when `NO_COLOR` is set.
+    #[must_use]
+    #[inline]
+    pub fn detect() -> Self {
+        if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColorDepth::None;
+        }
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+
+        let term = env::var("TERM").unwrap_or_default();
+        if term == "dumb" {
+            ColorDepth::None
+        } else if term.ends_with("-direct") || term.contains("truecolor") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
 pub struct Terminal {
     size: Size,
+    color_depth: ColorDepth,
     _stdout: RawTerminal<std::io::Stdout>,
+    keys: Receiver<Result<Key, io::Error>>,
 }
 
 impl Terminal {
+    /// Sets up the terminal, with `colors` or, if not given, the colors it is
+    /// detected to support.
+    ///
     /// # Errors
     ///
     /// Will return `std::io::Error` if it fails to get terminal size
-    pub fn new() -> Result<Self, std::io::Error> {
+    #[inline]
+    pub fn new(colors: Option<ColorDepth>) -> Result<Self, io::Error> {
         let size = termion::terminal_size()?;
 
+        // Keys are read on their own thread so the editor can wait for them
+        // and for background work, such as a project search, at once.
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            for key in io::stdin().keys() {
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+
         Ok(Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(2),
             },
+            color_depth: colors.unwrap_or_else(ColorDepth::detect),
             _stdout: stdout().into_raw_mode()?,
+            keys,
         })
     }
 
@@ -40,6 +112,12 @@ impl Terminal {
         &self.size
     }
 
+    #[must_use]
+    #[inline]
+    pub fn color_depth(&self) -> ColorDepth {
+        self.color_depth
+    }
+
     pub fn clear_screen() {
         print!("{}", termion::clear::All);
     }
@@ -61,12 +139,23 @@ impl Terminal {
 
     /// # Errors
     ///
-    /// Will never return Error for now
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
-            }
+    /// Will return `std::io::Error` if reading from stdin fails or it is closed.
+    #[inline]
+    pub fn read_key(&self) -> Result<Key, io::Error> {
+        self.keys.recv().unwrap_or_else(|_| Err(closed_stdin()))
+    }
+
+    /// Waits up to `timeout` for a key.
+    ///
+    /// # Errors
+    ///
+    /// Will return `std::io::Error` if reading from stdin fails or it is closed.
+    #[inline]
+    pub fn poll_key(&self, timeout: Duration) -> Result<Option<Key>, io::Error> {
+        match self.keys.recv_timeout(timeout) {
+            Ok(key) => key.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(closed_stdin()),
         }
     }
 
@@ -82,19 +171,18 @@ impl Terminal {
         print!("{}", termion::clear::CurrentLine);
     }
 
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
-    }
-
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    /// Sets the colors and attributes of what is printed next.
+    #[inline]
+    pub fn set_style(style: Style) {
+        print!("{style}");
     }
 
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
+    #[inline]
+    pub fn reset_style() {
+        print!("{}", style::Reset);
     }
+}
 
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
-    }
+fn closed_stdin() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "stdin was closed")
 }
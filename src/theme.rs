@@ -0,0 +1,605 @@
+use core::{convert::TryFrom, fmt, iter};
+use std::fs;
+
+use serde::Deserialize;
+use termion::{color, style};
+
+use crate::{config, highlighting, ColorDepth};
+
+/// The themes shipped with hecto, in the format of the files in the `themes`
+/// directory of the config directory.
+const BUNDLED: &[(&str, &str)] = &[
+    ("dark.toml", include_str!("../themes/dark.toml")),
+    ("light.toml", include_str!("../themes/light.toml")),
+];
+
+const THEMES_DIR: &str = "themes";
+
+/// The theme used when the config does not name one.
+pub const DEFAULT_THEME: &str = "Dark";
+
+/// The levels of red, green and blue in the color cube of the 256-color
+/// palette, which starts at index `CUBE_START`.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+const CUBE_START: u8 = 16;
+/// The 24 grays following the color cube go from 8 to 238 in steps of 10.
+const GRAYS_START: u8 = 232;
+
+/// The 16 basic colors as xterm shows them.
+const BASIC_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// A color given as `#rrggbb` in a theme file, or the palette entry nearest
+/// to it on terminals with fewer colors.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Color {
+    Rgb(u8, u8, u8),
+    /// An index into the 256-color palette.
+    Ansi256(u8),
+    /// One of the 16 basic colors, bright ones from 8 on.
+    Ansi16(u8),
+}
+
+impl Color {
+    /// This color as the terminal can show it with `depth`, if at all.
+    fn for_depth(self, depth: ColorDepth) -> Option<Self> {
+        let rgb = match self {
+            Color::Rgb(red, green, blue) => (red, green, blue),
+            _ => return Some(self),
+        };
+        match depth {
+            ColorDepth::None => None,
+            ColorDepth::Ansi16 => Some(Color::Ansi16(nearest_basic(rgb))),
+            ColorDepth::Ansi256 => Some(Color::Ansi256(nearest_256(rgb))),
+            ColorDepth::TrueColor => Some(self),
+        }
+    }
+
+    fn write(self, formatter: &mut fmt::Formatter<'_>, background: bool) -> fmt::Result {
+        match (self, background) {
+            (Color::Rgb(red, green, blue), false) => {
+                write!(formatter, "{}", color::Fg(color::Rgb(red, green, blue)))
+            }
+            (Color::Rgb(red, green, blue), true) => {
+                write!(formatter, "{}", color::Bg(color::Rgb(red, green, blue)))
+            }
+            (Color::Ansi256(index), false) => {
+                write!(formatter, "{}", color::Fg(color::AnsiValue(index)))
+            }
+            (Color::Ansi256(index), true) => {
+                write!(formatter, "{}", color::Bg(color::AnsiValue(index)))
+            }
+            // termion writes the basic colors as 256-color indices, which
+            // 16-color terminals do not understand.
+            (Color::Ansi16(index), background) => {
+                let (code, offset): (u8, u8) = if index < 8 {
+                    (30, index)
+                } else {
+                    (90, index.saturating_sub(8))
+                };
+                let code = if background {
+                    code.saturating_add(10)
+                } else {
+                    code
+                };
+                write!(formatter, "\x1b[{}m", code.saturating_add(offset))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid color \"{value}\", expected #rrggbb");
+        let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+        if hex.len() != 6 || !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |at: usize| u8::from_str_radix(hex.get(at..at.saturating_add(2))?, 16).ok();
+        match (channel(0), channel(2), channel(4)) {
+            (Some(red), Some(green), Some(blue)) => Ok(Color::Rgb(red, green, blue)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// How a highlight group is drawn. Colors left out are the terminal's own.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "each flag is one terminal attribute"
+)]
+pub struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    /// Swaps the foreground and background colors.
+    reverse: bool,
+}
+
+impl Style {
+    const REVERSE: Self = Self {
+        fg: None,
+        bg: None,
+        bold: false,
+        italic: false,
+        underline: false,
+        reverse: true,
+    };
+
+    /// This style drawn on top of `base`: its colors where it has them, and
+    /// the attributes of both.
+    #[must_use]
+    pub fn over(self, base: Self) -> Self {
+        Self {
+            fg: self.fg.or(base.fg),
+            bg: self.bg.or(base.bg),
+            bold: self.bold || base.bold,
+            italic: self.italic || base.italic,
+            underline: self.underline || base.underline,
+            reverse: self.reverse || base.reverse,
+        }
+    }
+
+    /// This style with its colors as the terminal can show them with `depth`.
+    fn for_depth(self, depth: ColorDepth) -> Self {
+        Self {
+            fg: self.fg.and_then(|color| color.for_depth(depth)),
+            bg: self.bg.and_then(|color| color.for_depth(depth)),
+            ..self
+        }
+    }
+
+    fn has_attributes(self) -> bool {
+        self.bold || self.italic || self.underline || self.reverse
+    }
+}
+
+/// Resets the terminal's colors and attributes, then sets the style's.
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", style::Reset)?;
+        if let Some(fg) = self.fg {
+            fg.write(f, false)?;
+        }
+        if let Some(bg) = self.bg {
+            bg.write(f, true)?;
+        }
+        if self.bold {
+            write!(f, "{}", style::Bold)?;
+        }
+        if self.italic {
+            write!(f, "{}", style::Italic)?;
+        }
+        if self.underline {
+            write!(f, "{}", style::Underline)?;
+        }
+        if self.reverse {
+            write!(f, "{}", style::Invert)?;
+        }
+        Ok(())
+    }
+}
+
+/// The styles of the highlight groups. Themes are defined in TOML files, see
+/// `BUNDLED`; a group left out of one is drawn as `text`, or as a related
+/// group where there is one.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    name: String,
+    /// The colors the styles are given in.
+    #[serde(skip)]
+    colors: ColorDepth,
+    /// The document and the message bar, and any group without a style.
+    #[serde(default)]
+    text: Style,
+    number: Option<Style>,
+    #[serde(rename = "match")]
+    search_match: Option<Style>,
+    /// The file and line of a search result.
+    location: Option<Style>,
+    string: Option<Style>,
+    /// Defaults to `string`.
+    character: Option<Style>,
+    /// Defaults to `secondary_keyword`.
+    lifetime: Option<Style>,
+    comment: Option<Style>,
+    /// Defaults to `comment`.
+    doc_comment: Option<Style>,
+    keyword: Option<Style>,
+    /// Defaults to `keyword`.
+    secondary_keyword: Option<Style>,
+    key: Option<Style>,
+    heading: Option<Style>,
+    variable: Option<Style>,
+    /// Function and macro names, found with the tree-sitter grammars only.
+    function: Option<Style>,
+    /// Defaults to the text reversed.
+    status_bar: Option<Style>,
+    /// Drawn over the groups of the selected text. Defaults to reversing it.
+    selection: Option<Style>,
+    line_number: Option<Style>,
+    /// The summary drawn after the first row of a fold. Defaults to
+    /// `line_number`.
+    fold: Option<Style>,
+    /// A prompt's answer that failed, as a search without matches.
+    error: Option<Style>,
+}
+
+impl Theme {
+    /// A theme drawing everything as plain text, in `colors`.
+    fn plain(colors: ColorDepth) -> Self {
+        Self {
+            name: DEFAULT_THEME.to_owned(),
+            colors,
+            text: Style::default(),
+            number: None,
+            search_match: None,
+            location: None,
+            string: None,
+            character: None,
+            lifetime: None,
+            comment: None,
+            doc_comment: None,
+            keyword: None,
+            secondary_keyword: None,
+            key: None,
+            heading: None,
+            variable: None,
+            function: None,
+            status_bar: None,
+            selection: None,
+            line_number: None,
+            fold: None,
+            error: None,
+        }
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn text(&self) -> Style {
+        self.text.for_depth(self.colors)
+    }
+
+    /// The style of text highlighted as `kind`.
+    #[must_use]
+    #[inline]
+    pub fn highlight(&self, kind: highlighting::Type) -> Style {
+        let secondary_keyword = self.secondary_keyword.or(self.keyword);
+        let style = match kind {
+            highlighting::Type::None => None,
+            highlighting::Type::Number => self.number,
+            highlighting::Type::Match => self.search_match,
+            highlighting::Type::Location => self.location,
+            highlighting::Type::String => self.string,
+            highlighting::Type::Character => self.character.or(self.string),
+            highlighting::Type::Lifetime => self.lifetime.or(secondary_keyword),
+            highlighting::Type::Comment => self.comment,
+            highlighting::Type::DocComment => self.doc_comment.or(self.comment),
+            highlighting::Type::PrimaryKeywords => self.keyword,
+            highlighting::Type::SecondaryKeywords => secondary_keyword,
+            highlighting::Type::Key => self.key,
+            highlighting::Type::Heading => self.heading,
+            highlighting::Type::Variable => self.variable,
+            highlighting::Type::Function => self.function,
+        };
+        if kind == highlighting::Type::Match {
+            self.standing_out(self.over_text(style))
+        } else {
+            self.over_text(style)
+        }
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn status_bar(&self) -> Style {
+        self.standing_out(self.over_text(self.status_bar.or(Some(Style::REVERSE))))
+    }
+
+    /// The style of selected text highlighted as `kind`.
+    #[must_use]
+    #[inline]
+    pub fn selection(&self, kind: highlighting::Type) -> Style {
+        let selection = self
+            .selection
+            .unwrap_or(Style::REVERSE)
+            .for_depth(self.colors);
+        self.standing_out(selection).over(self.highlight(kind))
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn line_number(&self) -> Style {
+        self.over_text(self.line_number)
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn fold(&self) -> Style {
+        self.over_text(self.fold.or(self.line_number))
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn error(&self) -> Style {
+        self.over_text(self.error)
+    }
+
+    fn over_text(&self, style: Option<Style>) -> Style {
+        let text = self.text();
+        style.map_or(text, |style| style.for_depth(self.colors).over(text))
+    }
+
+    /// `style`, reversed if it is told apart by its colors alone and there
+    /// are none to show.
+    fn standing_out(&self, style: Style) -> Style {
+        if self.colors == ColorDepth::None && !style.has_attributes() {
+            Style::REVERSE.over(style)
+        } else {
+            style
+        }
+    }
+}
+
+/// The themes known to the editor: the bundled ones, and those defined in
+/// the `themes` directory of the config directory, one per `.toml` file. A
+/// theme defined there replaces the bundled one of the same name.
+pub struct Themes {
+    definitions: Vec<Theme>,
+    colors: ColorDepth,
+    warnings: Vec<String>,
+}
+
+impl Default for Themes {
+    #[inline]
+    fn default() -> Self {
+        let mut themes = Self {
+            definitions: Vec::new(),
+            colors: ColorDepth::default(),
+            warnings: Vec::new(),
+        };
+        for &(name, contents) in BUNDLED {
+            themes.add(name, contents);
+        }
+        themes
+    }
+}
+
+impl Themes {
+    /// Loads the bundled themes and the user's, to be shown in `colors`.
+    /// Files that cannot be read or parsed are skipped and reported by
+    /// `warnings`.
+    #[must_use]
+    #[inline]
+    pub fn load(colors: ColorDepth) -> Self {
+        let mut themes = Self {
+            colors,
+            ..Self::default()
+        };
+        let dir = match config::config_dir() {
+            Some(dir) => dir.join(THEMES_DIR),
+            None => return themes,
+        };
+        let mut paths: Vec<_> = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "toml")
+                })
+                .collect(),
+            Err(_) => return themes,
+        };
+        paths.sort();
+        for path in paths {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            match fs::read_to_string(&path) {
+                Ok(contents) => themes.add(&format!("{THEMES_DIR}/{name}"), &contents),
+                Err(error) => {
+                    themes
+                        .warnings
+                        .push(format!("Could not read {}: {}", path.display(), error));
+                }
+            }
+        }
+        themes
+    }
+
+    /// Problems found in the user's themes.
+    #[must_use]
+    #[inline]
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// The theme called `name`, ignoring case.
+    #[must_use]
+    #[inline]
+    pub fn find(&self, name: &str) -> Option<Theme> {
+        let mut theme = self
+            .definitions
+            .iter()
+            .find(|theme| theme.name.eq_ignore_ascii_case(name))?
+            .clone();
+        theme.colors = self.colors;
+        Some(theme)
+    }
+
+    /// The theme called `DEFAULT_THEME`, or plain text should it be missing.
+    #[must_use]
+    #[inline]
+    pub fn default_theme(&self) -> Theme {
+        self.find(DEFAULT_THEME)
+            .unwrap_or_else(|| Theme::plain(self.colors))
+    }
+
+    /// The names of the themes, sorted.
+    #[must_use]
+    #[inline]
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .definitions
+            .iter()
+            .map(|theme| theme.name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Parses the theme in `contents`, read from `source`, replacing any of
+    /// the same name.
+    fn add(&mut self, source: &str, contents: &str) {
+        let theme: Theme = match toml::from_str(contents) {
+            Ok(theme) => theme,
+            Err(error) => {
+                self.warnings.push(format!("{source}: {error}"));
+                return;
+            }
+        };
+        self.definitions
+            .retain(|existing| !existing.name.eq_ignore_ascii_case(&theme.name));
+        self.definitions.push(theme);
+    }
+}
+
+/// The index of the entry of the 256-color palette nearest to `rgb`, in its
+/// color cube or among its grays.
+fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |value: u8| -> u8 {
+        (u8::MIN..)
+            .zip(CUBE_LEVELS.iter())
+            .min_by_key(|&(_, level)| level.abs_diff(value))
+            .map_or(0, |(index, _)| index)
+    };
+    let (red, green, blue) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube_level = |index: u8| CUBE_LEVELS.get(usize::from(index)).copied().unwrap_or(0);
+    let cube = (
+        CUBE_START
+            .saturating_add(red.saturating_mul(36))
+            .saturating_add(green.saturating_mul(6))
+            .saturating_add(blue),
+        (cube_level(red), cube_level(green), cube_level(blue)),
+    );
+    let grays = (u8::MIN..24).map(|step| {
+        let value = step.saturating_mul(10).saturating_add(8);
+        (GRAYS_START.saturating_add(step), (value, value, value))
+    });
+    grays
+        .chain(iter::once(cube))
+        .min_by_key(|&(_, candidate)| distance(rgb, candidate))
+        .map_or(0, |(index, _)| index)
+}
+
+/// The index of the basic color nearest to `rgb`.
+fn nearest_basic(rgb: (u8, u8, u8)) -> u8 {
+    (u8::MIN..)
+        .zip(BASIC_COLORS.iter())
+        .min_by_key(|&(_, candidate)| distance(rgb, *candidate))
+        .map_or(0, |(index, _)| index)
+}
+
+fn distance(from: (u8, u8, u8), to: (u8, u8, u8)) -> u32 {
+    let square = |x: u8, y: u8| u32::from(x.abs_diff(y)).pow(2);
+    square(from.0, to.0)
+        .saturating_add(square(from.1, to.1))
+        .saturating_add(square(from.2, to.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_themes_load() {
+        let themes = Themes::default();
+        assert!(themes.warnings().is_empty());
+        assert_eq!(themes.names(), ["Dark", "Light"]);
+        assert!(themes.find("light").is_some());
+        assert!(themes.find("solarized").is_none());
+    }
+
+    #[test]
+    fn user_themes_replace_bundled_ones() {
+        let mut themes = Themes::default();
+        themes.add(
+            "themes/dark.toml",
+            "name = \"dark\"\ncomment = { fg = \"#657b83\", italic = true }",
+        );
+        themes.add(
+            "themes/bad.toml",
+            "name = \"Bad\"\ncomment = { fg = \"red\" }",
+        );
+        assert_eq!(themes.names(), ["Light", "dark"]);
+        assert_eq!(themes.warnings().len(), 1);
+
+        let theme = themes.find("Dark");
+        let style = |kind| theme.as_ref().map(|theme| theme.highlight(kind));
+        assert!(style(highlighting::Type::DocComment) == style(highlighting::Type::Comment));
+        assert!(style(highlighting::Type::String) == theme.as_ref().map(Theme::text));
+    }
+
+    #[test]
+    fn colors_are_read_as_hex() {
+        assert!(Color::try_from("#ff8000".to_owned()) == Ok(Color::Rgb(255, 128, 0)));
+        assert!(Color::try_from("ff8000".to_owned()).is_err());
+        assert!(Color::try_from("#ff80".to_owned()).is_err());
+        assert!(Color::try_from("#gg0000".to_owned()).is_err());
+    }
+
+    #[test]
+    fn colors_map_to_the_cube_or_the_grays() {
+        assert_eq!(nearest_256((255, 0, 0)), 196);
+        assert_eq!(nearest_256((0, 0, 0)), 16);
+        assert_eq!(nearest_256((100, 150, 200)), 68);
+        // A gray between two cube levels is matched by the gray ramp.
+        assert_eq!(nearest_256((128, 128, 128)), 244);
+        assert_eq!(nearest_256((238, 238, 238)), 255);
+    }
+
+    #[test]
+    fn colors_map_to_the_basic_ones() {
+        assert_eq!(nearest_basic((205, 0, 0)), 1);
+        assert_eq!(nearest_basic((128, 128, 128)), 8);
+        assert_eq!(nearest_basic((250, 250, 250)), 15);
+        assert_eq!(nearest_basic((100, 100, 240)), 12);
+    }
+
+    #[test]
+    fn colors_follow_the_depth() {
+        let color = Color::Rgb(255, 0, 0);
+        assert!(color.for_depth(ColorDepth::TrueColor) == Some(color));
+        assert!(color.for_depth(ColorDepth::Ansi256) == Some(Color::Ansi256(196)));
+        assert!(color.for_depth(ColorDepth::Ansi16) == Some(Color::Ansi16(9)));
+        assert!(color.for_depth(ColorDepth::None).is_none());
+    }
+}
@@ -0,0 +1,1369 @@
+use core::{cmp, convert::TryFrom as _, fmt, mem, ops::Range};
+
+use termion::event::Key;
+
+use crate::{
+    motion::{class_at, classify, first_non_blank, step_backward, step_forward, Class},
+    registers::{self, Register, Registers},
+    Command, Document, Position,
+};
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+        })
+    }
+}
+
+/// What the editor should do after a key went through the vi layer.
+pub enum Outcome {
+    Handled,
+    Pending,
+    Unhandled,
+    Replay(Vec<Key>),
+    Command(Command),
+    RecordMacro(char),
+    StopMacro,
+    /// Plays a macro `count` times; without a register, plays the last one.
+    PlayMacro {
+        register: Option<char>,
+        count: usize,
+    },
+    /// The key was consumed but did nothing, as a motion that cannot move.
+    Failed,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    FindForward(char),
+    TillForward(char),
+    FindBackward(char),
+    TillBackward(char),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Clone, Copy)]
+enum TextObject {
+    Word {
+        around: bool,
+    },
+    Quote {
+        quote: char,
+        around: bool,
+    },
+    Pair {
+        open: char,
+        close: char,
+        around: bool,
+    },
+}
+
+enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    Line,
+    Selection,
+}
+
+enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    Insert(InsertAt),
+    Put { before: bool },
+    Select(TextObject),
+    SwapSelectionEnds,
+    ToggleVisual,
+    Repeat,
+    Escape,
+    Command(Command),
+    RecordMacro(char),
+    PlayMacro(Option<char>),
+}
+
+struct Parsed {
+    register: Option<char>,
+    count: Option<usize>,
+    /// The count typed before the command, and the number of keys taken by
+    /// it and the register prefix.
+    prefix_count: Option<usize>,
+    prefix_len: usize,
+    action: Action,
+}
+
+enum Parse<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+/// A change that can be repeated with `.`: the keys of the command without
+/// its count, and the keys typed in insert mode if the command started one.
+#[derive(Clone)]
+struct Change {
+    register: Option<char>,
+    count: Option<usize>,
+    keys: Vec<Key>,
+    inserted: Option<Vec<Key>>,
+}
+
+struct TextRange {
+    start: Position,
+    end: Position,
+    linewise: bool,
+}
+
+pub struct Vi {
+    mode: Mode,
+    pending: Vec<Key>,
+    registers: Registers,
+    anchor: Position,
+    last_change: Option<Change>,
+    recording: Option<Change>,
+    recording_macro: bool,
+}
+
+impl Default for Vi {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            registers: Registers::default(),
+            anchor: Position::default(),
+            last_change: None,
+            recording: None,
+            recording_macro: false,
+        }
+    }
+}
+
+impl Vi {
+    #[must_use]
+    #[inline]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn pending_keys(&self) -> &[Key] {
+        &self.pending
+    }
+
+    /// Lets a plain `q` stop the macro the editor is recording.
+    #[inline]
+    pub fn set_recording_macro(&mut self, recording: bool) {
+        self.recording_macro = recording;
+    }
+
+    #[inline]
+    pub fn leave_visual(&mut self) {
+        if self.mode == Mode::Visual {
+            self.mode = Mode::Normal;
+        }
+    }
+
+    /// The visual selection, from its first position to the position right
+    /// after its last grapheme.
+    #[must_use]
+    #[inline]
+    pub fn selection(&self, cursor: &Position) -> Option<(Position, Position)> {
+        if self.mode != Mode::Visual {
+            return None;
+        }
+
+        let (start, mut end) = ordered(&self.anchor, cursor);
+        end.x = end.x.saturating_add(1);
+        Some((start, end))
+    }
+
+    #[inline]
+    pub fn handle_key(
+        &mut self,
+        key: Key,
+        document: &mut Document,
+        cursor: &mut Position,
+    ) -> Outcome {
+        if self.mode == Mode::Insert {
+            if key == Key::Esc {
+                self.mode = Mode::Normal;
+                if let Some(change) = self.recording.take() {
+                    self.last_change = Some(change);
+                }
+                cursor.x = cursor.x.saturating_sub(1);
+                self.clamp(document, cursor);
+                return Outcome::Handled;
+            }
+            if let Some(inserted) = self
+                .recording
+                .as_mut()
+                .and_then(|change| change.inserted.as_mut())
+            {
+                inserted.push(key);
+            }
+            return Outcome::Unhandled;
+        }
+
+        if self.recording_macro && self.pending.is_empty() && key == Key::Char('q') {
+            return Outcome::StopMacro;
+        }
+
+        self.pending.push(key);
+        match parse(&self.pending, self.mode == Mode::Visual) {
+            Parse::Incomplete => Outcome::Pending,
+            Parse::Invalid => {
+                let keys = mem::take(&mut self.pending);
+                if keys.len() == 1 {
+                    Outcome::Unhandled
+                } else {
+                    Outcome::Failed
+                }
+            }
+            Parse::Done(parsed) => {
+                let keys = mem::take(&mut self.pending);
+                let outcome = self.execute(parsed, &keys, document, cursor);
+                self.clamp(document, cursor);
+                outcome
+            }
+        }
+    }
+
+    /// Keeps the cursor on an existing grapheme outside of insert mode.
+    #[inline]
+    pub fn clamp(&self, document: &Document, cursor: &mut Position) {
+        if self.mode == Mode::Insert {
+            return;
+        }
+        if document.is_empty() {
+            *cursor = Position::default();
+            return;
+        }
+
+        cursor.y = cmp::min(cursor.y, document.len().saturating_sub(1));
+        let len = document.row_len(cursor.y).unwrap_or(0);
+        cursor.x = cmp::min(cursor.x, len.saturating_sub(1));
+    }
+
+    fn execute(
+        &mut self,
+        parsed: Parsed,
+        keys: &[Key],
+        document: &mut Document,
+        cursor: &mut Position,
+    ) -> Outcome {
+        let count = parsed.count.unwrap_or(1);
+        let register = parsed.register.unwrap_or(registers::UNNAMED);
+        let change = Change {
+            register: parsed.register,
+            count: parsed.prefix_count,
+            keys: keys.get(parsed.prefix_len..).unwrap_or(&[]).to_vec(),
+            inserted: None,
+        };
+
+        match parsed.action {
+            Action::Move(motion) => {
+                let Some(mut target) = motion_target(document, cursor, motion, count, parsed.count)
+                else {
+                    return Outcome::Failed;
+                };
+                self.clamp(document, &mut target);
+                if target == *cursor && motion.can_fail() {
+                    return Outcome::Failed;
+                }
+                *cursor = target;
+            }
+            Action::Operate(operator, target) => {
+                let visual = self.mode == Mode::Visual;
+                let range = match target {
+                    Target::Motion(motion) => {
+                        motion_range(document, cursor, motion, count, parsed.count, operator)
+                    }
+                    Target::Object(object) => object_range(document, cursor, object),
+                    Target::Line => line_range(document, cursor, count),
+                    Target::Selection => self.selection(cursor).map(|(start, end)| TextRange {
+                        start,
+                        end,
+                        linewise: false,
+                    }),
+                };
+
+                self.mode = Mode::Normal;
+                if let Some(range) = range {
+                    self.operate(operator, &range, register, document, cursor);
+                }
+
+                if operator == Operator::Change {
+                    self.start_insert(if visual { None } else { Some(change) });
+                } else if operator == Operator::Delete && !visual {
+                    self.last_change = Some(change);
+                }
+            }
+            Action::Insert(at) => {
+                insert(&at, document, cursor);
+                self.start_insert(Some(change));
+            }
+            Action::Put { before } => {
+                self.put(register, before, count, document, cursor);
+                self.last_change = Some(change);
+            }
+            Action::Select(object) => {
+                if let Some(range) = object_range(document, cursor, object) {
+                    self.anchor = range.start;
+                    *cursor = range.end;
+                    cursor.x = cursor.x.saturating_sub(1);
+                }
+            }
+            Action::SwapSelectionEnds => mem::swap(&mut self.anchor, cursor),
+            Action::ToggleVisual => {
+                if self.mode == Mode::Visual {
+                    self.mode = Mode::Normal;
+                } else {
+                    self.mode = Mode::Visual;
+                    self.anchor = cursor.clone();
+                }
+            }
+            Action::Repeat => {
+                if let Some(last) = self.last_change.as_ref() {
+                    return Outcome::Replay(last.replay_keys(parsed.count));
+                }
+            }
+            Action::Escape => self.mode = Mode::Normal,
+            Action::Command(command) => return Outcome::Command(command),
+            Action::RecordMacro(name) => return Outcome::RecordMacro(name),
+            Action::PlayMacro(name) => {
+                return Outcome::PlayMacro {
+                    register: name,
+                    count,
+                }
+            }
+        }
+
+        Outcome::Handled
+    }
+
+    fn start_insert(&mut self, change: Option<Change>) {
+        self.mode = Mode::Insert;
+        self.recording = change.map(|change| Change {
+            inserted: Some(Vec::new()),
+            ..change
+        });
+    }
+
+    fn operate(
+        &mut self,
+        operator: Operator,
+        range: &TextRange,
+        register: char,
+        document: &mut Document,
+        cursor: &mut Position,
+    ) {
+        if range.linewise {
+            let first = range.start.y;
+            let last = range.end.y;
+            let last_len = document.row_len(last).unwrap_or(0);
+            let text = document.text_range(
+                &Position { x: 0, y: first },
+                &Position {
+                    x: last_len,
+                    y: last,
+                },
+            );
+            let saved = Register {
+                text,
+                linewise: true,
+            };
+
+            match operator {
+                Operator::Yank => {
+                    self.registers.yank(register, saved);
+                    cursor.y = first;
+                }
+                Operator::Delete => {
+                    self.registers.delete(register, saved);
+                    delete_lines(document, first, last);
+                    cursor.y = first;
+                    cursor.x = first_non_blank(document, cursor.y);
+                }
+                Operator::Change => {
+                    self.registers.delete(register, saved);
+                    document.delete_range(
+                        &Position { x: 0, y: first },
+                        &Position {
+                            x: last_len,
+                            y: last,
+                        },
+                    );
+                    *cursor = Position { x: 0, y: first };
+                }
+            }
+            return;
+        }
+
+        let text = document.text_range(&range.start, &range.end);
+        let saved = Register {
+            text,
+            linewise: false,
+        };
+        if operator == Operator::Yank {
+            self.registers.yank(register, saved);
+        } else {
+            self.registers.delete(register, saved);
+            document.delete_range(&range.start, &range.end);
+        }
+        *cursor = range.start.clone();
+    }
+
+    fn put(
+        &mut self,
+        register: char,
+        before: bool,
+        count: usize,
+        document: &mut Document,
+        cursor: &mut Position,
+    ) {
+        let saved = match self.registers.get(register) {
+            Some(saved) => saved.clone(),
+            None => return,
+        };
+
+        if saved.linewise {
+            let text = vec![saved.text; count].join("\n");
+            if before || document.is_empty() {
+                let at = Position { x: 0, y: cursor.y };
+                document.insert_str(&at, &format!("{text}\n"));
+                *cursor = at;
+            } else {
+                let len = document.row_len(cursor.y).unwrap_or(0);
+                document.insert_str(
+                    &Position {
+                        x: len,
+                        y: cursor.y,
+                    },
+                    &format!("\n{text}"),
+                );
+                cursor.y = cursor.y.saturating_add(1);
+            }
+            cursor.x = first_non_blank(document, cursor.y);
+        } else {
+            let len = document.row_len(cursor.y).unwrap_or(0);
+            let at = if before {
+                cursor.clone()
+            } else {
+                Position {
+                    x: cmp::min(cursor.x.saturating_add(1), len),
+                    y: cursor.y,
+                }
+            };
+            let end = document.insert_str(&at, &saved.text.repeat(count));
+            *cursor = Position {
+                x: end.x.saturating_sub(1),
+                y: end.y,
+            };
+        }
+    }
+}
+
+impl Change {
+    fn replay_keys(&self, count: Option<usize>) -> Vec<Key> {
+        let mut keys = Vec::new();
+        if let Some(register) = self.register {
+            keys.extend(&[Key::Char('"'), Key::Char(register)]);
+        }
+        if let Some(count) = count.or(self.count) {
+            keys.extend(count.to_string().chars().map(Key::Char));
+        }
+
+        keys.extend(&self.keys);
+        if let Some(inserted) = self.inserted.as_ref() {
+            keys.extend(inserted);
+            keys.push(Key::Esc);
+        }
+        keys
+    }
+}
+
+fn parse(keys: &[Key], visual: bool) -> Parse<Parsed> {
+    let mut index = 0;
+    let mut register = None;
+
+    if keys.first() == Some(&Key::Char('"')) {
+        match keys.get(1).copied() {
+            None => return Parse::Incomplete,
+            Some(Key::Char(ch)) if ch.is_ascii_alphanumeric() || ch == '"' || ch == '_' => {
+                register = Some(ch);
+            }
+            Some(_) => return Parse::Invalid,
+        }
+        index = 2;
+    }
+
+    let (prefix_count, next) = parse_count(keys, index);
+    let prefix_len = next;
+    let key = match keys.get(next) {
+        Some(key) => *key,
+        None => return Parse::Incomplete,
+    };
+    let rest = keys.get(next.saturating_add(1)..).unwrap_or(&[]);
+
+    let action = match parse_action(key, rest, visual) {
+        Parse::Done(action) => action,
+        Parse::Incomplete => return Parse::Incomplete,
+        Parse::Invalid => return Parse::Invalid,
+    };
+
+    // An operator may take its own count, as in `2d3w`.
+    let operates = matches!(action, Action::Operate(_, _));
+    let count = match (operates, prefix_count) {
+        (true, Some(count)) => Some(count.saturating_mul(operator_count(rest))),
+        (true, None) if operator_count(rest) > 1 => Some(operator_count(rest)),
+        _ => prefix_count,
+    };
+
+    Parse::Done(Parsed {
+        register,
+        count,
+        prefix_count,
+        prefix_len,
+        action,
+    })
+}
+
+fn parse_count(keys: &[Key], start: usize) -> (Option<usize>, usize) {
+    let mut count: Option<usize> = None;
+    let mut index = start;
+
+    while let Some(&Key::Char(ch)) = keys.get(index) {
+        let digit = match ch.to_digit(10) {
+            Some(0) if count.is_none() => break,
+            Some(digit) => usize::try_from(digit).unwrap_or_default(),
+            None => break,
+        };
+        count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+        index = index.saturating_add(1);
+    }
+
+    (count, index)
+}
+
+fn operator_count(rest: &[Key]) -> usize {
+    parse_count(rest, 0).0.unwrap_or(1)
+}
+
+fn parse_action(key: Key, rest: &[Key], visual: bool) -> Parse<Action> {
+    let operator = match key {
+        Key::Char('d') => Some(Operator::Delete),
+        Key::Char('c') => Some(Operator::Change),
+        Key::Char('y') => Some(Operator::Yank),
+        _ => None,
+    };
+
+    if let Some(operator) = operator {
+        if visual {
+            return Parse::Done(Action::Operate(operator, Target::Selection));
+        }
+
+        let (_, next) = parse_count(rest, 0);
+        let rest = rest.get(next..).unwrap_or(&[]);
+        return match rest.first().copied() {
+            None => Parse::Incomplete,
+            Some(repeated) if repeated == key => {
+                Parse::Done(Action::Operate(operator, Target::Line))
+            }
+            Some(Key::Char('i' | 'a')) => match parse_text_object(rest) {
+                Parse::Done(object) => {
+                    Parse::Done(Action::Operate(operator, Target::Object(object)))
+                }
+                Parse::Incomplete => Parse::Incomplete,
+                Parse::Invalid => Parse::Invalid,
+            },
+            Some(motion_key) => match parse_motion(motion_key, rest.get(1..).unwrap_or(&[])) {
+                Parse::Done(motion) => {
+                    Parse::Done(Action::Operate(operator, Target::Motion(motion)))
+                }
+                Parse::Incomplete => Parse::Incomplete,
+                Parse::Invalid => Parse::Invalid,
+            },
+        };
+    }
+
+    if visual {
+        let action = match key {
+            Key::Char('x') | Key::Delete => Action::Operate(Operator::Delete, Target::Selection),
+            Key::Char('s') => Action::Operate(Operator::Change, Target::Selection),
+            Key::Char('o') => Action::SwapSelectionEnds,
+            Key::Char('v') => Action::ToggleVisual,
+            Key::Esc => Action::Escape,
+            Key::Char('i' | 'a') => {
+                let keys = [&[key], rest].concat();
+                return match parse_text_object(&keys) {
+                    Parse::Done(object) => Parse::Done(Action::Select(object)),
+                    Parse::Incomplete => Parse::Incomplete,
+                    Parse::Invalid => Parse::Invalid,
+                };
+            }
+            _ => return parse_motion(key, rest).map(Action::Move),
+        };
+        return Parse::Done(action);
+    }
+
+    let action = match key {
+        Key::Char('i') | Key::Insert => Action::Insert(InsertAt::Cursor),
+        Key::Char('a') => Action::Insert(InsertAt::After),
+        Key::Char('I') => Action::Insert(InsertAt::LineStart),
+        Key::Char('A') => Action::Insert(InsertAt::LineEnd),
+        Key::Char('o') => Action::Insert(InsertAt::LineBelow),
+        Key::Char('O') => Action::Insert(InsertAt::LineAbove),
+        Key::Char('x') | Key::Delete => {
+            Action::Operate(Operator::Delete, Target::Motion(Motion::Right))
+        }
+        Key::Char('X') => Action::Operate(Operator::Delete, Target::Motion(Motion::Left)),
+        Key::Char('D') => Action::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        Key::Char('C') => Action::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        Key::Char('s') => Action::Operate(Operator::Change, Target::Motion(Motion::Right)),
+        Key::Char('S') => Action::Operate(Operator::Change, Target::Line),
+        Key::Char('Y') => Action::Operate(Operator::Yank, Target::Line),
+        Key::Char('p') => Action::Put { before: false },
+        Key::Char('P') => Action::Put { before: true },
+        Key::Char('v') => Action::ToggleVisual,
+        Key::Char('.') => Action::Repeat,
+        Key::Char('/') => Action::Command(Command::Find),
+        Key::Char('u') => Action::Command(Command::Undo),
+        Key::Ctrl('r') => Action::Command(Command::Redo),
+        Key::Char('z') => return parse_fold(rest).map(Action::Command),
+        Key::Char(prefix @ ('q' | '@')) => {
+            let register = match rest.first().copied() {
+                None => return Parse::Incomplete,
+                Some(Key::Char(ch)) if ch.is_ascii_alphanumeric() || ch == '"' => ch,
+                Some(Key::Char('@')) if prefix == '@' => {
+                    return Parse::Done(Action::PlayMacro(None))
+                }
+                Some(_) => return Parse::Invalid,
+            };
+            if prefix == 'q' {
+                Action::RecordMacro(register)
+            } else {
+                Action::PlayMacro(Some(register))
+            }
+        }
+        Key::Esc => Action::Escape,
+        _ => return parse_motion(key, rest).map(Action::Move),
+    };
+    Parse::Done(action)
+}
+
+/// `zc`, `zo` and `zi`: fold, unfold and toggle all folds.
+fn parse_fold(rest: &[Key]) -> Parse<Command> {
+    match rest.first().copied() {
+        None => Parse::Incomplete,
+        Some(Key::Char('c')) => Parse::Done(Command::Fold),
+        Some(Key::Char('o')) => Parse::Done(Command::Unfold),
+        Some(Key::Char('i')) => Parse::Done(Command::ToggleFolds),
+        Some(_) => Parse::Invalid,
+    }
+}
+
+fn parse_motion(key: Key, rest: &[Key]) -> Parse<Motion> {
+    let motion = match key {
+        Key::Char('h') | Key::Left | Key::Backspace => Motion::Left,
+        Key::Char('l' | ' ') | Key::Right => Motion::Right,
+        Key::Char('k') | Key::Up => Motion::Up,
+        Key::Char('j' | '\n') | Key::Down => Motion::Down,
+        Key::Char('w') => Motion::WordForward,
+        Key::Char('b') => Motion::WordBackward,
+        Key::Char('e') => Motion::WordEnd,
+        Key::Char('0') | Key::Home => Motion::LineStart,
+        Key::Char('$') | Key::End => Motion::LineEnd,
+        Key::Char('G') => Motion::LastLine,
+        Key::Char('g') => {
+            return match rest.first().copied() {
+                None => Parse::Incomplete,
+                Some(Key::Char('g')) => Parse::Done(Motion::FirstLine),
+                Some(_) => Parse::Invalid,
+            }
+        }
+        Key::Char(find @ ('f' | 't' | 'F' | 'T')) => {
+            let target = match rest.first().copied() {
+                None => return Parse::Incomplete,
+                Some(Key::Char(ch)) => ch,
+                Some(_) => return Parse::Invalid,
+            };
+            match find {
+                'f' => Motion::FindForward(target),
+                't' => Motion::TillForward(target),
+                'F' => Motion::FindBackward(target),
+                _ => Motion::TillBackward(target),
+            }
+        }
+        _ => return Parse::Invalid,
+    };
+    Parse::Done(motion)
+}
+
+fn parse_text_object(keys: &[Key]) -> Parse<TextObject> {
+    let around = match keys.first().copied() {
+        Some(Key::Char('a')) => true,
+        Some(Key::Char('i')) => false,
+        _ => return Parse::Invalid,
+    };
+
+    let object = match keys.get(1).copied() {
+        None => return Parse::Incomplete,
+        Some(Key::Char('w')) => TextObject::Word { around },
+        Some(Key::Char(quote @ ('"' | '\'' | '`'))) => TextObject::Quote { quote, around },
+        Some(Key::Char('(' | ')' | 'b')) => TextObject::Pair {
+            open: '(',
+            close: ')',
+            around,
+        },
+        Some(Key::Char('{' | '}' | 'B')) => TextObject::Pair {
+            open: '{',
+            close: '}',
+            around,
+        },
+        Some(Key::Char('[' | ']')) => TextObject::Pair {
+            open: '[',
+            close: ']',
+            around,
+        },
+        Some(Key::Char('<' | '>')) => TextObject::Pair {
+            open: '<',
+            close: '>',
+            around,
+        },
+        Some(_) => return Parse::Invalid,
+    };
+    Parse::Done(object)
+}
+
+impl<T> Parse<T> {
+    fn map<U, F: FnOnce(T) -> U>(self, transform: F) -> Parse<U> {
+        match self {
+            Parse::Done(value) => Parse::Done(transform(value)),
+            Parse::Incomplete => Parse::Incomplete,
+            Parse::Invalid => Parse::Invalid,
+        }
+    }
+}
+
+impl Motion {
+    fn kind(self) -> MotionKind {
+        match self {
+            Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine => {
+                MotionKind::Linewise
+            }
+            Motion::WordEnd | Motion::LineEnd | Motion::FindForward(_) | Motion::TillForward(_) => {
+                MotionKind::Inclusive
+            }
+            _ => MotionKind::Exclusive,
+        }
+    }
+
+    /// Jumps to a fixed place never fail, even when the cursor is already
+    /// there; other motions fail when they cannot move.
+    fn can_fail(self) -> bool {
+        !matches!(
+            self,
+            Motion::LineStart | Motion::LineEnd | Motion::FirstLine | Motion::LastLine
+        )
+    }
+}
+
+fn ordered(first: &Position, second: &Position) -> (Position, Position) {
+    if first <= second {
+        (first.clone(), second.clone())
+    } else {
+        (second.clone(), first.clone())
+    }
+}
+
+fn word_forward(document: &Document, start: &Position) -> Position {
+    let mut position = start.clone();
+    let class = class_at(document, &position);
+
+    if class == Class::Word || class == Class::Punctuation {
+        while class_at(document, &position) == class {
+            match step_forward(document, &position) {
+                Some(next) => position = next,
+                None => return position,
+            }
+        }
+    } else {
+        match step_forward(document, &position) {
+            Some(next) => position = next,
+            None => return position,
+        }
+    }
+
+    while class_at(document, &position) == Class::Space {
+        match step_forward(document, &position) {
+            Some(next) => position = next,
+            None => return position,
+        }
+    }
+    position
+}
+
+fn word_end(document: &Document, start: &Position) -> Position {
+    let Some(mut position) = step_forward(document, start) else {
+        return start.clone();
+    };
+
+    while let Class::Space | Class::EmptyLine = class_at(document, &position) {
+        match step_forward(document, &position) {
+            Some(next) => position = next,
+            None => return position,
+        }
+    }
+
+    let class = class_at(document, &position);
+    while let Some(next) = step_forward(document, &position) {
+        if class_at(document, &next) != class {
+            break;
+        }
+        position = next;
+    }
+    position
+}
+
+fn word_backward(document: &Document, start: &Position) -> Position {
+    let Some(mut position) = step_backward(document, start) else {
+        return start.clone();
+    };
+
+    while class_at(document, &position) == Class::Space {
+        match step_backward(document, &position) {
+            Some(previous) => position = previous,
+            None => return position,
+        }
+    }
+
+    let class = class_at(document, &position);
+    if class == Class::EmptyLine {
+        return position;
+    }
+    while let Some(previous) = step_backward(document, &position) {
+        if class_at(document, &previous) != class {
+            break;
+        }
+        position = previous;
+    }
+    position
+}
+
+fn find_in_row(
+    document: &Document,
+    position: &Position,
+    target: char,
+    forward: bool,
+    count: usize,
+) -> Option<usize> {
+    let row = document.row(position.y)?;
+    let matches = |x: &usize| {
+        row.grapheme(*x)
+            .is_some_and(|grapheme| grapheme.chars().eq(Some(target)))
+    };
+
+    if forward {
+        (position.x.saturating_add(1)..row.len())
+            .filter(matches)
+            .nth(count.saturating_sub(1))
+    } else {
+        (0..position.x)
+            .rev()
+            .filter(matches)
+            .nth(count.saturating_sub(1))
+    }
+}
+
+fn motion_target(
+    document: &Document,
+    cursor: &Position,
+    motion: Motion,
+    count: usize,
+    explicit_count: Option<usize>,
+) -> Option<Position> {
+    let last_line = document.len().saturating_sub(1);
+    let len = document.row_len(cursor.y).unwrap_or(0);
+    let mut target = cursor.clone();
+
+    match motion {
+        Motion::Left => target.x = target.x.saturating_sub(count),
+        Motion::Right => target.x = cmp::min(target.x.saturating_add(count), len),
+        Motion::Up => target.y = target.y.saturating_sub(count),
+        Motion::Down => target.y = cmp::min(target.y.saturating_add(count), last_line),
+        Motion::WordForward => {
+            for _ in 0..count {
+                target = word_forward(document, &target);
+            }
+        }
+        Motion::WordBackward => {
+            for _ in 0..count {
+                target = word_backward(document, &target);
+            }
+        }
+        Motion::WordEnd => {
+            for _ in 0..count {
+                target = word_end(document, &target);
+            }
+        }
+        Motion::LineStart => target.x = 0,
+        Motion::LineEnd => {
+            target.y = cmp::min(target.y.saturating_add(count.saturating_sub(1)), last_line);
+            target.x = document.row_len(target.y).unwrap_or(0).saturating_sub(1);
+        }
+        Motion::FirstLine | Motion::LastLine => {
+            let default = if motion == Motion::FirstLine {
+                0
+            } else {
+                last_line
+            };
+            target.y =
+                explicit_count.map_or(default, |line| cmp::min(line.saturating_sub(1), last_line));
+            target.x = first_non_blank(document, target.y);
+        }
+        Motion::FindForward(ch) => target.x = find_in_row(document, cursor, ch, true, count)?,
+        Motion::TillForward(ch) => {
+            target.x = find_in_row(document, cursor, ch, true, count)?.saturating_sub(1);
+        }
+        Motion::FindBackward(ch) => target.x = find_in_row(document, cursor, ch, false, count)?,
+        Motion::TillBackward(ch) => {
+            target.x = find_in_row(document, cursor, ch, false, count)?.saturating_add(1);
+        }
+    }
+
+    Some(target)
+}
+
+fn motion_range(
+    document: &Document,
+    cursor: &Position,
+    motion: Motion,
+    count: usize,
+    explicit_count: Option<usize>,
+    operator: Operator,
+) -> Option<TextRange> {
+    // `cw` on a word behaves like `ce`, as in vi.
+    let motion = if operator == Operator::Change
+        && motion == Motion::WordForward
+        && class_at(document, cursor) != Class::Space
+    {
+        Motion::WordEnd
+    } else {
+        motion
+    };
+
+    let target = motion_target(document, cursor, motion, count, explicit_count)?;
+    let (start, mut end) = ordered(cursor, &target);
+
+    match motion.kind() {
+        MotionKind::Linewise => {
+            return Some(TextRange {
+                start: Position { x: 0, y: start.y },
+                end: Position { x: 0, y: end.y },
+                linewise: true,
+            })
+        }
+        MotionKind::Inclusive => end.x = end.x.saturating_add(1),
+        MotionKind::Exclusive => {
+            // A word motion that crosses lines stops at the end of the last
+            // word instead of the start of the next line.
+            if motion == Motion::WordForward
+                && end.y > start.y
+                && end.x <= first_non_blank(document, end.y)
+            {
+                let y = end.y.saturating_sub(1);
+                end = Position {
+                    x: document.row_len(y).unwrap_or(0),
+                    y,
+                };
+            }
+        }
+    }
+
+    Some(TextRange {
+        start,
+        end,
+        linewise: false,
+    })
+}
+
+fn line_range(document: &Document, cursor: &Position, count: usize) -> Option<TextRange> {
+    if document.is_empty() {
+        return None;
+    }
+
+    let last = cmp::min(
+        cursor.y.saturating_add(count.saturating_sub(1)),
+        document.len().saturating_sub(1),
+    );
+    Some(TextRange {
+        start: Position { x: 0, y: cursor.y },
+        end: Position { x: 0, y: last },
+        linewise: true,
+    })
+}
+
+fn object_range(document: &Document, cursor: &Position, object: TextObject) -> Option<TextRange> {
+    let (start, end) = match object {
+        TextObject::Word { around } => word_object(document, cursor, around)?,
+        TextObject::Quote { quote, around } => quote_object(document, cursor, quote, around)?,
+        TextObject::Pair {
+            open,
+            close,
+            around,
+        } => pair_object(document, cursor, open, close, around)?,
+    };
+
+    Some(TextRange {
+        start,
+        end,
+        linewise: false,
+    })
+}
+
+fn word_object(
+    document: &Document,
+    cursor: &Position,
+    around: bool,
+) -> Option<(Position, Position)> {
+    let row = document.row(cursor.y)?;
+    let class_of = |x: usize| row.grapheme(x).map(classify);
+    let class = class_of(cursor.x)?;
+
+    let run = |from: usize, wanted: Class| -> Range<usize> {
+        let mut start = from;
+        while start > 0 && class_of(start.saturating_sub(1)) == Some(wanted) {
+            start = start.saturating_sub(1);
+        }
+        let mut end = from;
+        while class_of(end) == Some(wanted) {
+            end = end.saturating_add(1);
+        }
+        start..end
+    };
+
+    let mut range = run(cursor.x, class);
+    if around {
+        if class_of(range.end) == Some(Class::Space) {
+            range.end = run(range.end, Class::Space).end;
+        } else if range.start > 0 && class_of(range.start.saturating_sub(1)) == Some(Class::Space) {
+            range.start = run(range.start.saturating_sub(1), Class::Space).start;
+        }
+    }
+
+    Some((
+        Position {
+            x: range.start,
+            y: cursor.y,
+        },
+        Position {
+            x: range.end,
+            y: cursor.y,
+        },
+    ))
+}
+
+fn quote_object(
+    document: &Document,
+    cursor: &Position,
+    quote: char,
+    around: bool,
+) -> Option<(Position, Position)> {
+    let row = document.row(cursor.y)?;
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+
+    for x in 0..row.len() {
+        let grapheme = row.grapheme(x)?;
+        if escaped {
+            escaped = false;
+        } else if grapheme == "\\" {
+            escaped = true;
+        } else if grapheme.chars().eq(Some(quote)) {
+            quotes.push(x);
+        }
+    }
+
+    let (open, close) = quotes
+        .chunks(2)
+        .filter_map(|pair| match *pair {
+            [open, close] => Some((open, close)),
+            _ => None,
+        })
+        .find(|&(_, close)| cursor.x <= close)?;
+
+    let (start, end) = if around {
+        (open, close.saturating_add(1))
+    } else {
+        (open.saturating_add(1), close)
+    };
+    Some((
+        Position {
+            x: start,
+            y: cursor.y,
+        },
+        Position {
+            x: end,
+            y: cursor.y,
+        },
+    ))
+}
+
+fn pair_object(
+    document: &Document,
+    cursor: &Position,
+    open: char,
+    close: char,
+    around: bool,
+) -> Option<(Position, Position)> {
+    let is = |position: &Position, ch: char| {
+        document
+            .row(position.y)
+            .and_then(|row| row.grapheme(position.x))
+            .is_some_and(|grapheme| grapheme.chars().eq(Some(ch)))
+    };
+
+    let mut opening = cursor.clone();
+    if !is(&opening, open) {
+        let mut depth: usize = 0;
+        if is(&opening, close) {
+            depth = 1;
+        }
+        loop {
+            opening = step_backward(document, &opening)?;
+            if is(&opening, close) {
+                depth = depth.saturating_add(1);
+            } else if is(&opening, open) {
+                if depth == 0 {
+                    break;
+                }
+                depth = depth.saturating_sub(1);
+            }
+        }
+    }
+
+    let mut closing = opening.clone();
+    let mut depth: usize = 0;
+    loop {
+        closing = step_forward(document, &closing)?;
+        if is(&closing, open) {
+            depth = depth.saturating_add(1);
+        } else if is(&closing, close) {
+            if depth == 0 {
+                break;
+            }
+            depth = depth.saturating_sub(1);
+        }
+    }
+
+    if around {
+        closing.x = closing.x.saturating_add(1);
+    } else {
+        opening = step_forward(document, &opening)?;
+    }
+    Some((opening, closing))
+}
+
+fn insert(at: &InsertAt, document: &mut Document, cursor: &mut Position) {
+    let len = document.row_len(cursor.y).unwrap_or(0);
+    match *at {
+        InsertAt::Cursor => (),
+        InsertAt::After => cursor.x = cmp::min(cursor.x.saturating_add(1), len),
+        InsertAt::LineStart => cursor.x = first_non_blank(document, cursor.y),
+        InsertAt::LineEnd => cursor.x = len,
+        InsertAt::LineBelow => {
+            document.insert_str(
+                &Position {
+                    x: len,
+                    y: cursor.y,
+                },
+                "\n",
+            );
+            *cursor = Position {
+                x: 0,
+                y: cursor.y.saturating_add(1),
+            };
+        }
+        InsertAt::LineAbove => {
+            document.insert_str(&Position { x: 0, y: cursor.y }, "\n");
+            cursor.x = 0;
+        }
+    }
+}
+
+fn delete_lines(document: &mut Document, first: usize, last: usize) {
+    let last_len = document.row_len(last).unwrap_or(0);
+
+    if last.saturating_add(1) < document.len() {
+        document.delete_range(
+            &Position { x: 0, y: first },
+            &Position {
+                x: 0,
+                y: last.saturating_add(1),
+            },
+        );
+    } else if first > 0 {
+        let y = first.saturating_sub(1);
+        document.delete_range(
+            &Position {
+                x: document.row_len(y).unwrap_or(0),
+                y,
+            },
+            &Position {
+                x: last_len,
+                y: last,
+            },
+        );
+    } else {
+        document.delete_range(
+            &Position { x: 0, y: first },
+            &Position {
+                x: last_len,
+                y: last,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Row;
+
+    fn document(text: &str) -> Document {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), text);
+        document
+    }
+
+    fn keys(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    fn press(vi: &mut Vi, document: &mut Document, cursor: &mut Position, text: &str) {
+        for key in keys(text) {
+            vi.handle_key(key, document, cursor);
+        }
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        (0..document.len())
+            .filter_map(|y| document.row(y).map(Row::as_str))
+            .collect()
+    }
+
+    #[test]
+    fn counts_multiply_across_the_operator() {
+        assert!(matches!(
+            parse(&keys("2d3w"), false),
+            Parse::Done(Parsed {
+                count: Some(6),
+                prefix_count: Some(2),
+                action: Action::Operate(Operator::Delete, Target::Motion(Motion::WordForward)),
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse(&keys("\"a3yy"), false),
+            Parse::Done(Parsed {
+                register: Some('a'),
+                count: Some(3),
+                action: Action::Operate(Operator::Yank, Target::Line),
+                ..
+            })
+        ));
+        assert!(matches!(parse(&keys("2d"), false), Parse::Incomplete));
+        assert!(matches!(
+            parse(&keys("0"), false),
+            Parse::Done(Parsed {
+                count: None,
+                action: Action::Move(Motion::LineStart),
+                ..
+            })
+        ));
+        assert!(matches!(parse(&keys("dq"), false), Parse::Invalid));
+    }
+
+    #[test]
+    fn motions_move_the_cursor() {
+        let mut vi = Vi::default();
+        let mut document = document("one two three\nfour");
+        let mut cursor = Position::default();
+
+        press(&mut vi, &mut document, &mut cursor, "w");
+        assert_eq!(cursor, Position { x: 4, y: 0 });
+        press(&mut vi, &mut document, &mut cursor, "e");
+        assert_eq!(cursor, Position { x: 6, y: 0 });
+        press(&mut vi, &mut document, &mut cursor, "$");
+        assert_eq!(cursor, Position { x: 12, y: 0 });
+        press(&mut vi, &mut document, &mut cursor, "2b");
+        assert_eq!(cursor, Position { x: 4, y: 0 });
+        press(&mut vi, &mut document, &mut cursor, "fe");
+        assert_eq!(cursor, Position { x: 11, y: 0 });
+        press(&mut vi, &mut document, &mut cursor, "0j");
+        assert_eq!(cursor, Position { x: 0, y: 1 });
+        press(&mut vi, &mut document, &mut cursor, "gg");
+        assert_eq!(cursor, Position { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn operators_apply_to_counted_motions() {
+        let mut vi = Vi::default();
+        let mut document = document("aa bb cc dd\nsecond\nthird");
+        let mut cursor = Position::default();
+
+        press(&mut vi, &mut document, &mut cursor, "2dw");
+        assert_eq!(lines(&document), ["cc dd", "second", "third"]);
+        press(&mut vi, &mut document, &mut cursor, "j2dd");
+        assert_eq!(lines(&document), ["cc dd"]);
+        press(&mut vi, &mut document, &mut cursor, "cw");
+        assert!(vi.mode() == Mode::Insert);
+        assert_eq!(lines(&document), [" dd"]);
+        vi.handle_key(Key::Esc, &mut document, &mut cursor);
+        press(&mut vi, &mut document, &mut cursor, "yyp");
+        assert_eq!(lines(&document), [" dd", " dd"]);
+    }
+}